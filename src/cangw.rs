@@ -0,0 +1,580 @@
+/*
+ * Filename: cangw.rs
+ * Created Date: Friday, October 16th 2026, 11:20:05 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::metrics::METRICS;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Output;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+// Extra attempts at a failed add or remove; set from RUSTYCAN_CANGW_RETRIES at startup
+static RETRIES: AtomicU32 = AtomicU32::new(2);
+
+// Wait before the first retry, doubled before each one after it
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+
+// Most retries allowed, so the backoff stays under a minute
+const MAX_RETRIES: u32 = 10;
+
+/// A single CAN gateway job as the kernel reports it
+/// Every tracked (src, dst) pair is installed twice: once for classic frames
+/// and once with -X for CAN FD frames
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KernelRule {
+    pub src: String,
+    pub dst: String,
+    pub fd: bool,
+}
+
+impl KernelRule {
+    pub fn new(src: &str, dst: &str, fd: bool) -> Self {
+        KernelRule {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            fd,
+        }
+    }
+}
+
+/// Settings of a gateway job
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobOptions {
+    // Most gateway hops a frame may take (cangw -l)
+    pub hoplimit: Option<u8>,
+    // Only frames matching this filter are forwarded (cangw -f), see `parse_filter`
+    pub filter: Option<String>,
+    // Frame modifications (cangw -m), see `parse_modifications`
+    pub modify: Option<String>,
+}
+
+/// A single gateway job and the exact cangw arguments it was installed with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CangwJob {
+    pub fd: bool,
+    // Everything after -A, e.g. `-s vcan0 -d vxcan1234abcd -e -f 100:7F0`
+    pub args: Vec<String>,
+}
+
+impl CangwJob {
+    pub fn new(src: &str, dst: &str, fd: bool, opts: &JobOptions) -> Self {
+        let mut args: Vec<String> = ["-s", src, "-d", dst, if fd { "-eX" } else { "-e" }]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        if let Some(hops) = opts.hoplimit {
+            args.extend([String::from("-l"), hops.to_string()]);
+        }
+        if let Some(filter) = &opts.filter {
+            args.extend([String::from("-f"), filter.clone()]);
+        }
+        for m in opts.modify.iter().flat_map(|m| m.split(',')) {
+            if fd {
+                args.extend([String::from("-M"), fd_modification(m)]);
+            } else {
+                args.extend([String::from("-m"), m.to_string()]);
+            }
+        }
+        CangwJob { fd, args }
+    }
+}
+
+/// A tracked (src, dst) gateway rule with every job installed for it
+/// Removal replays each job's arguments, whatever options it was created with
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct CangwRule {
+    pub src: String,
+    pub dst: String,
+    pub jobs: Vec<CangwJob>,
+}
+
+impl CangwRule {
+    pub fn is(&self, src: &str, dst: &str) -> bool {
+        self.src == src && self.dst == dst
+    }
+}
+
+/// Install a gateway job
+pub fn add(job: &CangwJob) -> std::io::Result<Output> {
+    run("-A", job)
+}
+
+/// Remove a gateway job installed with the same arguments
+pub fn remove(job: &CangwJob) -> std::io::Result<Output> {
+    run("-D", job)
+}
+
+/// Retry failed adds and removes this many extra times before giving up
+pub fn set_retries(retries: u32) {
+    RETRIES.store(retries.min(MAX_RETRIES), Ordering::Relaxed);
+}
+
+// A missing rule on removal, or no cangw binary at all, won't go away by retrying
+fn transient(result: &io::Result<Output>) -> bool {
+    match result {
+        Ok(out) if out.status.success() => false,
+        Ok(out) => !String::from_utf8_lossy(&out.stderr).contains("No such file or directory"),
+        Err(e) => e.kind() != io::ErrorKind::NotFound,
+    }
+}
+
+fn run(op: &str, job: &CangwJob) -> io::Result<Output> {
+    let retries = RETRIES.load(Ordering::Relaxed);
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let result = run_once(op, job);
+        if attempt == retries || !transient(&result) {
+            return result;
+        }
+        attempt += 1;
+        warn!(
+            "cangw {} {} failed, retrying in {}ms ({}/{})",
+            op,
+            job.args.join(" "),
+            delay.as_millis(),
+            attempt,
+            retries
+        );
+        std::thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+fn run_once(op: &str, job: &CangwJob) -> io::Result<Output> {
+    let mut args = vec![op];
+    args.extend(job.args.iter().map(String::as_str));
+
+    let started = Instant::now();
+    let result = command::output("cangw", &args);
+    let op = if op == "-A" { "add" } else { "remove" };
+    let rule = if job.fd { "extended" } else { "base" };
+    METRICS
+        .cangw_op_seconds
+        .observe([op, rule], started.elapsed());
+    result
+}
+
+/// Validate a cangw filter, `<id>:<mask>` (match) or `<id>~<mask>` (inverted match),
+/// both in hex, and return it in the form passed to `cangw -f`
+pub fn parse_filter(spec: &str) -> Result<String, String> {
+    let spec = spec.trim();
+    let sep = spec
+        .find([':', '~'])
+        .ok_or_else(|| format!("filter '{spec}' must be <id>:<mask> or <id>~<mask>"))?;
+    let (id, mask) = (&spec[..sep], &spec[sep + 1..]);
+    // Ids and masks are 32 bits: the 29-bit id plus the EFF/RTR/ERR flags
+    let hex = |v: &str| {
+        u32::from_str_radix(v, 16)
+            .map_err(|_| format!("'{v}' in filter '{spec}' is not a hex CAN id or mask"))
+    };
+    let (id, mask) = (hex(id)?, hex(mask)?);
+    Ok(format!("{:X}{}{:X}", id, &spec[sep..sep + 1], mask))
+}
+
+/// Validate a comma-separated list of filters, each as accepted by `parse_filter`,
+/// and return it normalized; every filter becomes a separate gateway job
+pub fn parse_filters(spec: &str) -> Result<String, String> {
+    let filters = spec
+        .split(',')
+        .map(parse_filter)
+        .collect::<Result<Vec<String>, String>>()?;
+    Ok(filters.join(","))
+}
+
+// Modification operations, which the kernel applies in this order, each at most once per job
+const MODIFY_OPS: [&str; 4] = ["AND", "OR", "XOR", "SET"];
+
+/// Validate a cangw frame modification, `<op>:<elements>:<id>.<len>.<data>`, and return
+/// it in the form passed to `cangw -m`. `op` is AND, OR, XOR or SET; `elements` picks
+/// which of the id (I), length (L) and data (D) it changes; the values are hex, with
+/// the data as 8 bytes (16 digits), e.g. `SET:I:123.0.0000000000000000`
+pub fn parse_modification(spec: &str) -> Result<String, String> {
+    let spec = spec.trim();
+    let malformed = || format!("modification '{spec}' must be <op>:<elements>:<id>.<len>.<data>");
+    let mut parts = spec.split(':');
+    let (Some(op), Some(elements), Some(values), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed());
+    };
+
+    let op = op.to_ascii_uppercase();
+    if !MODIFY_OPS.contains(&op.as_str()) {
+        return Err(format!(
+            "'{op}' in modification '{spec}' is not AND, OR, XOR or SET"
+        ));
+    }
+
+    let elements = elements.to_ascii_uppercase();
+    if elements.is_empty() || !elements.chars().all(|c| "ILD".contains(c)) {
+        return Err(format!(
+            "'{elements}' in modification '{spec}' must be one or more of I, L and D"
+        ));
+    }
+    let elements: String = "ILD".chars().filter(|c| elements.contains(*c)).collect();
+
+    let mut values = values.split('.');
+    let (Some(id), Some(len), Some(data), None) =
+        (values.next(), values.next(), values.next(), values.next())
+    else {
+        return Err(malformed());
+    };
+    let id = u32::from_str_radix(id, 16)
+        .map_err(|_| format!("'{id}' in modification '{spec}' is not a hex CAN id"))?;
+    let len = u8::from_str_radix(len, 16)
+        .ok()
+        .filter(|l| *l <= 8)
+        .ok_or_else(|| format!("'{len}' in modification '{spec}' is not a length of 0 to 8"))?;
+    if data.len() != 16 || !data.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!(
+            "'{data}' in modification '{spec}' must be 8 bytes as 16 hex digits"
+        ));
+    }
+    Ok(format!(
+        "{op}:{elements}:{id:X}.{len:X}.{}",
+        data.to_ascii_uppercase()
+    ))
+}
+
+/// Validate a comma-separated list of modifications, each as accepted by
+/// `parse_modification` and each with a different operation, and return it normalized
+pub fn parse_modifications(spec: &str) -> Result<String, String> {
+    let mods = spec
+        .split(',')
+        .map(parse_modification)
+        .collect::<Result<Vec<String>, String>>()?;
+    let mut ops: Vec<&str> = mods.iter().filter_map(|m| m.split(':').next()).collect();
+    ops.sort_unstable();
+    if let Some(op) = ops.windows(2).find(|w| w[0] == w[1]).map(|w| w[0]) {
+        return Err(format!("{op} is used by more than one modification"));
+    }
+    Ok(mods.join(","))
+}
+
+// A normalized classic modification in the `cangw -M` form for CAN FD jobs: no flags
+// changed and the data widened to 64 bytes, padded so AND, OR and XOR leave the extra
+// bytes as they are (SET can't, and clears them)
+fn fd_modification(spec: &str) -> String {
+    let mut parts = spec.splitn(3, ':');
+    let (op, elements, values) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+    let mut values = values.splitn(3, '.');
+    let (id, len, data) = (
+        values.next().unwrap_or_default(),
+        values.next().unwrap_or_default(),
+        values.next().unwrap_or_default(),
+    );
+    let pad = if op == "AND" { "FF" } else { "00" };
+    format!("{op}:{elements}:{id}.0.{len}.{data}{}", pad.repeat(56))
+}
+
+// Parameters of the can-gw kernel module, when it is loaded
+const MODULE_PARAMS_DIR: &str = "/sys/module/can_gw/parameters";
+
+/// Parameters the can-gw kernel module was loaded with
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ModuleParams {
+    // False when the module isn't loaded (or is built in without sysfs parameters)
+    pub loaded: bool,
+    // Largest hop limit the kernel accepts for a gateway job
+    pub max_hops: Option<u8>,
+    // Every parameter file as read from sysfs
+    pub params: BTreeMap<String, String>,
+}
+
+impl ModuleParams {
+    /// Read the can-gw module parameters from sysfs
+    pub fn probe() -> Self {
+        ModuleParams::read_from(Path::new(MODULE_PARAMS_DIR))
+    }
+
+    /// Read module parameters from a sysfs-style directory of one file per parameter
+    pub fn read_from(dir: &Path) -> Self {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return ModuleParams::default(),
+        };
+
+        let params: BTreeMap<String, String> = entries
+            .filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let value = fs::read_to_string(e.path()).ok()?;
+                Some((
+                    e.file_name().to_string_lossy().to_string(),
+                    value.trim().to_string(),
+                ))
+            })
+            .collect();
+
+        ModuleParams {
+            loaded: true,
+            max_hops: params.get("max_hops").and_then(|v| v.parse::<u8>().ok()),
+            params,
+        }
+    }
+
+    /// Check a requested hop limit against the kernel's maximum, when known
+    pub fn validate_hoplimit(&self, hops: u8) -> Result<(), String> {
+        if hops == 0 {
+            return Err(String::from("vxcan.hoplimit must be at least 1"));
+        }
+        match self.max_hops {
+            Some(max) if hops > max => Err(format!(
+                "vxcan.hoplimit {hops} exceeds the can-gw module's max_hops of {max}"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read the gateway jobs currently installed in the kernel
+pub fn list() -> Result<Vec<KernelRule>, String> {
+    Ok(list_stats()?.into_iter().map(|s| s.rule).collect())
+}
+
+/// Read the gateway jobs currently installed in the kernel with their frame counters
+pub fn list_stats() -> Result<Vec<RuleStats>, String> {
+    let output =
+        command::output("cangw", &["-L"]).map_err(|e| format!("failed to execute cangw: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("cangw -L failed: {}", stderr.trim()));
+    }
+
+    Ok(parse_list(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// A kernel gateway job and the frame counters the kernel keeps for it
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct RuleStats {
+    #[serde(flatten)]
+    pub rule: KernelRule,
+    // Frames forwarded by the job
+    pub handled: u64,
+    // Frames the job failed to forward
+    pub dropped: u64,
+    // Frames deleted by the job's hop limit or a checksum/modification failure
+    pub deleted: u64,
+}
+
+/// Parse `cangw -L` output, e.g.
+/// `cangw -A -s vcan0 -d vxcan1234abcd -e # 12 handled 0 dropped 0 deleted`
+pub fn parse_list(output: &str) -> Vec<RuleStats> {
+    output.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<RuleStats> {
+    let mut parts = line.splitn(2, '#');
+    let rule = parts.next()?.trim();
+    let mut tokens = rule.split_whitespace();
+    if tokens.next()? != "cangw" {
+        return None;
+    }
+
+    let mut src = None;
+    let mut dst = None;
+    let mut fd = false;
+    while let Some(tok) = tokens.next() {
+        match tok {
+            "-s" => src = tokens.next(),
+            "-d" => dst = tokens.next(),
+            t if t.starts_with('-') && !t.starts_with("--") && t.contains('X') => fd = true,
+            _ => {}
+        }
+    }
+
+    // Counters follow the comment marker as "<n> handled <n> dropped <n> deleted"
+    let mut stats = RuleStats {
+        rule: KernelRule::new(src?, dst?, fd),
+        handled: 0,
+        dropped: 0,
+        deleted: 0,
+    };
+    let counters: Vec<&str> = parts.next().unwrap_or("").split_whitespace().collect();
+    for pair in counters.windows(2) {
+        if let Ok(n) = pair[0].parse::<u64>() {
+            match pair[1] {
+                "handled" => stats.handled = n,
+                "dropped" => stats.dropped = n,
+                "deleted" => stats.deleted = n,
+                _ => {}
+            }
+        }
+    }
+
+    Some(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::MockRunner;
+    use std::sync::atomic::AtomicUsize;
+
+    fn job() -> CangwJob {
+        CangwJob::new("vcan0", "vxcan1", false, &JobOptions::default())
+    }
+
+    #[test]
+    fn add_retries_until_cangw_succeeds() {
+        let attempts = AtomicUsize::new(0);
+        let mock = MockRunner::responding(move |_| match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Err(String::from("netlink error: Device or resource busy")),
+            _ => Ok(String::new()),
+        });
+        let _mock = mock.install();
+        set_retries(2);
+
+        let result = add(&job()).unwrap();
+        assert!(result.status.success());
+        assert_eq!(mock.calls_to("cangw"), ["-A -s vcan0 -d vxcan1 -e"; 3]);
+    }
+
+    #[test]
+    fn remove_gives_up_on_a_missing_rule() {
+        let mock = MockRunner::responding(|_| Err(String::from("No such file or directory")));
+        let _mock = mock.install();
+        set_retries(2);
+
+        assert!(!remove(&job()).unwrap().status.success());
+        assert_eq!(mock.calls_to("cangw").len(), 1);
+    }
+
+    #[test]
+    fn parse_filter_normalizes_hex() {
+        assert_eq!(parse_filter(" 100:7f0 ").unwrap(), "100:7F0");
+        assert_eq!(
+            parse_filter("1fffff7e~1fffffff").unwrap(),
+            "1FFFFF7E~1FFFFFFF"
+        );
+    }
+
+    #[test]
+    fn parse_filter_rejects_malformed_specs() {
+        assert!(parse_filter("100").is_err());
+        assert!(parse_filter("100:").is_err());
+        assert!(parse_filter("xyz:7FF").is_err());
+        assert!(parse_filter("100:100000000").is_err());
+        assert!(parse_filter("0x1a~ff").unwrap_err().contains("0x1a"));
+    }
+
+    #[test]
+    fn parse_filters_keeps_each_filter() {
+        assert_eq!(
+            parse_filters("100:7f0, 200~7ff").unwrap(),
+            "100:7F0,200~7FF"
+        );
+        assert!(parse_filters("100:7f0,").is_err());
+    }
+
+    #[test]
+    fn parse_modification_normalizes_case_and_elements() {
+        assert_eq!(
+            parse_modification("set:di:0123.8.00112233445566aa").unwrap(),
+            "SET:ID:123.8.00112233445566AA"
+        );
+        assert_eq!(
+            parse_modification("XOR:I:100.0.0000000000000000").unwrap(),
+            "XOR:I:100.0.0000000000000000"
+        );
+    }
+
+    #[test]
+    fn parse_modification_rejects_malformed_specs() {
+        for spec in [
+            "SET:I",
+            "NAND:I:100.0.0000000000000000",
+            "SET:X:100.0.0000000000000000",
+            "SET::100.0.0000000000000000",
+            "SET:I:100.0",
+            "SET:I:zz.0.0000000000000000",
+            "SET:L:100.9.0000000000000000",
+            "SET:D:100.0.00",
+            "SET:D:100.0.000000000000000g",
+        ] {
+            assert!(parse_modification(spec).is_err(), "{spec} was accepted");
+        }
+    }
+
+    #[test]
+    fn parse_modifications_refuses_a_repeated_operation() {
+        assert_eq!(
+            parse_modifications("and:i:7ff.0.0000000000000000,or:i:100.0.0000000000000000")
+                .unwrap(),
+            "AND:I:7FF.0.0000000000000000,OR:I:100.0.0000000000000000"
+        );
+        let err = parse_modifications("SET:I:1.0.0000000000000000,set:d:0.0.0000000000000000")
+            .unwrap_err();
+        assert!(err.contains("SET"), "{err}");
+    }
+
+    #[test]
+    fn parse_list_reads_rules_and_counters() {
+        let output = "\
+cangw -A -s vcan0 -d vxcanab12cd34 -e # 12 handled 1 dropped 2 deleted
+cangw -A -s vxcanab12cd34 -d vcan0 -eX -l 2 -f 100:7F0 # 0 handled 0 dropped 0 deleted
+not a rule
+cangw -A -s vcan0 -e # 1 handled 0 dropped 0 deleted
+";
+        assert_eq!(
+            parse_list(output),
+            vec![
+                RuleStats {
+                    rule: KernelRule::new("vcan0", "vxcanab12cd34", false),
+                    handled: 12,
+                    dropped: 1,
+                    deleted: 2,
+                },
+                RuleStats {
+                    rule: KernelRule::new("vxcanab12cd34", "vcan0", true),
+                    handled: 0,
+                    dropped: 0,
+                    deleted: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_list_defaults_missing_counters() {
+        let stats = parse_list("cangw -A -s vcan0 -d vxcan1p -e\n");
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            (stats[0].handled, stats[0].dropped, stats[0].deleted),
+            (0, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,370 @@
+/*
+ * Filename: command.rs
+ * Created Date: Friday, October 16th 2026, 6:12:03 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Output};
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(test)]
+use std::sync::Arc;
+use std::sync::{Mutex, OnceLock};
+
+// Set from RUSTYCAN_LOG_COMMANDS at startup
+static LOG_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+// Runs every external command; the system runner unless another was installed at startup
+static RUNNER: OnceLock<Box<dyn CommandRunner>> = OnceLock::new();
+
+/// Executes the external programs (`ip`, `cangw`, `tc`, ...) the plugin drives
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output>;
+}
+
+/// Runs commands on the host
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        Command::new(program).args(args).output()
+    }
+}
+
+/// Runs nothing: every command succeeds with empty output
+/// Commands are still logged and traced, so a trace shows what would have run
+pub struct DryRunner;
+
+impl CommandRunner for DryRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        info!("Dry run: {} {}", program, args.join(" "));
+        Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+}
+
+/// Install the runner used for every external command; only the first call takes effect
+pub fn set_runner(runner: Box<dyn CommandRunner>) {
+    if RUNNER.set(runner).is_err() {
+        warn!("A command runner is already installed, keeping it");
+    }
+}
+
+fn runner() -> &'static dyn CommandRunner {
+    RUNNER.get_or_init(|| Box::new(SystemRunner)).as_ref()
+}
+
+// Runner a test installed in place of the global one, see `MockRunner::install`
+#[cfg(test)]
+static MOCK: Mutex<Option<Arc<MockRunner>>> = Mutex::new(None);
+
+// Held by the test using the mock, so tests relying on one don't share it
+#[cfg(test)]
+static MOCK_USER: Mutex<()> = Mutex::new(());
+
+// Answers a command run through a MockRunner
+#[cfg(test)]
+type Respond = Box<dyn Fn(&TraceEntry) -> Result<String, String> + Send + Sync>;
+
+/// Records every command, whether run or dry-run through netlink, and answers the ones
+/// run through `respond`: stdout on success, or stderr with exit status 1 on failure
+#[cfg(test)]
+pub struct MockRunner {
+    calls: Mutex<Vec<TraceEntry>>,
+    respond: Respond,
+}
+
+#[cfg(test)]
+impl MockRunner {
+    /// Every command succeeds with no output
+    pub fn new() -> Arc<Self> {
+        Self::responding(|_| Ok(String::new()))
+    }
+
+    pub fn responding(
+        respond: impl Fn(&TraceEntry) -> Result<String, String> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(MockRunner {
+            calls: Mutex::new(Vec::new()),
+            respond: Box::new(respond),
+        })
+    }
+
+    /// Use this runner for every command until the guard is dropped, with interface
+    /// changes dry-run; waits for any other test still using a mock
+    pub fn install(self: &Arc<Self>) -> MockGuard {
+        let serial = MOCK_USER.lock().unwrap_or_else(|e| e.into_inner());
+        crate::netlink::set_dry_run(true);
+        *MOCK.lock().unwrap_or_else(|e| e.into_inner()) = Some(self.clone());
+        MockGuard { _serial: serial }
+    }
+
+    /// Every command so far, in order
+    pub fn calls(&self) -> Vec<TraceEntry> {
+        self.calls.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Arguments of every call to `program` so far, each joined with spaces
+    pub fn calls_to(&self, program: &str) -> Vec<String> {
+        self.calls()
+            .iter()
+            .filter(|c| c.program == program)
+            .map(|c| c.args.join(" "))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for MockRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<Output> {
+        let call = TraceEntry {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        };
+        let (code, stdout, stderr) = match (self.respond)(&call) {
+            Ok(stdout) => (0, stdout, String::new()),
+            Err(stderr) => (1, String::new(), stderr),
+        };
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+}
+
+/// Removes an installed `MockRunner` when dropped
+#[cfg(test)]
+pub struct MockGuard {
+    _serial: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        *MOCK.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        crate::netlink::set_dry_run(false);
+    }
+}
+
+#[cfg(test)]
+fn mock() -> Option<Arc<MockRunner>> {
+    MOCK.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+// Trace file every command is appended to while recording
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// One external command, as written to a trace file (one JSON object per line)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+struct Trace {
+    name: String,
+    path: String,
+    file: File,
+    commands: usize,
+}
+
+/// Name, path and command count of the trace being recorded
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TraceStatus {
+    pub name: String,
+    pub path: String,
+    pub commands: usize,
+}
+
+impl Trace {
+    fn status(&self) -> TraceStatus {
+        TraceStatus {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            commands: self.commands,
+        }
+    }
+}
+
+/// Start recording every command into `<dir>/<name>.jsonl`, replacing any earlier
+/// trace of that name; a trace already being recorded is stopped first
+pub fn start_trace(dir: &str, name: &str) -> Result<TraceStatus, String> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "trace name '{name}' may only contain letters, digits, '-' and '_'"
+        ));
+    }
+
+    fs::create_dir_all(dir).map_err(|e| format!("create {dir}: {e}"))?;
+    let path = Path::new(dir)
+        .join(format!("{name}.jsonl"))
+        .to_string_lossy()
+        .to_string();
+    let file = File::create(&path).map_err(|e| format!("create {path}: {e}"))?;
+
+    let trace = Trace {
+        name: name.to_string(),
+        path,
+        file,
+        commands: 0,
+    };
+    let status = trace.status();
+    *TRACE.lock().unwrap_or_else(|e| e.into_inner()) = Some(trace);
+    info!("Recording commands to {}", status.path);
+    Ok(status)
+}
+
+/// Stop recording, returning what was recorded
+pub fn stop_trace() -> Option<TraceStatus> {
+    let trace = TRACE.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+    info!("Recorded {} commands to {}", trace.commands, trace.path);
+    Some(trace.status())
+}
+
+pub fn trace_status() -> Option<TraceStatus> {
+    TRACE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(Trace::status)
+}
+
+/// Read back a recorded trace, in command order
+pub fn load_trace(path: &str) -> Result<Vec<TraceEntry>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("read {path}: {e}"))?;
+    contents
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| serde_json::from_str(l).map_err(|e| format!("parse {path}: {e}")))
+        .collect()
+}
+
+/// `trace-diff` command: compare the command sequences of two recorded traces, e.g.
+/// the same operation recorded before and after an upgrade
+/// Exits with 0 when they match, otherwise reports the first difference
+pub fn trace_diff_command(expected: &str, actual: &str) -> i32 {
+    let (expected, actual) = match (load_trace(expected), load_trace(actual)) {
+        (Ok(e), Ok(a)) => (e, a),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("{}", e);
+            return 2;
+        }
+    };
+
+    let show = |entry: Option<&TraceEntry>| match entry {
+        Some(e) => format!("{} {}", e.program, e.args.join(" ")),
+        None => String::from("<end of trace>"),
+    };
+    for i in 0..expected.len().max(actual.len()) {
+        if expected.get(i) != actual.get(i) {
+            println!("Traces differ at command {}:", i + 1);
+            println!("  expected: {}", show(expected.get(i)));
+            println!("  actual:   {}", show(actual.get(i)));
+            return 1;
+        }
+    }
+    println!("Traces match ({} commands)", expected.len());
+    0
+}
+
+fn record(program: &str, args: &[&str]) {
+    let mut guard = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(trace) = guard.as_mut() else {
+        return;
+    };
+    let entry = TraceEntry {
+        program: program.to_string(),
+        args: args.iter().map(|a| a.to_string()).collect(),
+    };
+    if let Ok(line) = serde_json::to_string(&entry) {
+        if let Err(e) = writeln!(trace.file, "{}", line) {
+            error!("Unable to write trace {}: {}", trace.path, e);
+            return;
+        }
+        trace.commands += 1;
+    }
+}
+
+/// Enable or disable debug logging of every external command
+pub fn set_logging(enabled: bool) {
+    LOG_COMMANDS.store(enabled, Ordering::Relaxed);
+}
+
+/// Log the exact argument vector of a command about to run, when enabled, and add it
+/// to the trace being recorded
+pub fn log_invocation(program: &str, args: &[&str]) {
+    record(program, args);
+    #[cfg(test)]
+    if let Some(mock) = mock() {
+        mock.calls
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(TraceEntry {
+                program: program.to_string(),
+                args: args.iter().map(|a| a.to_string()).collect(),
+            });
+    }
+    if LOG_COMMANDS.load(Ordering::Relaxed) {
+        debug!("exec program={:?} args={:?}", program, args);
+    }
+}
+
+/// Run an external command to completion, logging the invocation and any failure
+pub fn output(program: &str, args: &[&str]) -> io::Result<Output> {
+    log_invocation(program, args);
+    #[cfg(test)]
+    let result = match mock() {
+        Some(mock) => mock.run(program, args),
+        None => runner().run(program, args),
+    };
+    #[cfg(not(test))]
+    let result = runner().run(program, args);
+    match &result {
+        Ok(out) if !out.status.success() => warn!(
+            "Command program={:?} args={:?} exited with {}: {}",
+            program,
+            args,
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        ),
+        Ok(_) => {}
+        Err(e) => error!(
+            "Command program={:?} args={:?} failed to start: {}",
+            program, args, e
+        ),
+    }
+    result
+}
@@ -0,0 +1,414 @@
+/*
+ * Filename: config.rs
+ * Created Date: Friday, October 16th 2026, 11:20:05 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::endpoint::{DEFAULT_SHORT_NAME_LEN, MAX_SHORT_NAME_LEN};
+use crate::introspect::INTROSPECT_SOCKET;
+use crate::state::{FALLBACK_STATE_FILE, NETWORK_STATE_FILE};
+use log::warn;
+use std::env;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// How to handle a CreateEndpoint for an existing endpoint with different options
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DuplicateEndpointPolicy {
+    // Refuse the second request with a conflict error
+    Reject,
+    // Tear down the existing endpoint and create it again with the new options
+    #[default]
+    Recreate,
+}
+
+/// What to do when Docker detaches an endpoint from a network the plugin no longer knows
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissingNetworkPolicy {
+    // Delete the endpoint's vxcan pair by name, so it isn't leaked
+    #[default]
+    Cleanup,
+    // Fail the request with a network-not-found error
+    Error,
+}
+
+/// Scope reported to Docker in the driver's capabilities
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Scope {
+    // Networks exist on this host only
+    #[default]
+    Local,
+    // Networks are known cluster-wide, as swarm mode expects
+    Global,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Local => "local",
+            Scope::Global => "global",
+        }
+    }
+}
+
+/// How log records are written
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    // ` -> ` and ` !! ` prefixed lines, as the plugin always printed
+    #[default]
+    Text,
+    // One JSON object per line, for centralized logging
+    Json,
+}
+
+impl LogFormat {
+    /// Read RUSTYCAN_LOG_FORMAT ahead of the rest of the configuration, which logs as it's read
+    /// An unrecognized value is returned as the error
+    pub fn from_env() -> Result<Self, String> {
+        match env_str("RUSTYCAN_LOG_FORMAT").as_deref() {
+            None | Some("text") => Ok(LogFormat::Text),
+            Some("json") => Ok(LogFormat::Json),
+            Some(v) => Err(v.to_string()),
+        }
+    }
+}
+
+/// What to do at startup when the recovered state is unhealthy
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum WarmupPolicy {
+    // Serve Docker requests anyway, logging a warning
+    #[default]
+    FailOpen,
+    // Hold off serving Docker requests until healthy or released by an operator
+    FailClosed,
+}
+
+/// Plugin-wide settings, read once from RUSTYCAN_* environment variables at startup
+#[derive(Debug, Clone)]
+pub struct PluginConfig {
+    // Interval for diffing `cangw -L` against tracked rules; None disables the watcher
+    pub rule_watch_interval: Option<Duration>,
+    // Re-install tracked rules that were removed outside the plugin
+    pub rule_watch_strict: bool,
+    pub duplicate_endpoint_policy: DuplicateEndpointPolicy,
+    pub missing_network_detach: MissingNetworkPolicy,
+    pub scope: Scope,
+    // Number of lifecycle events kept for the introspection API; 0 disables the log
+    pub event_log_size: usize,
+    // Number of idle vcan interfaces kept ready for new networks; 0 disables the pool
+    pub vcan_pool_size: usize,
+    // Largest options JSON accepted from Docker before it is parsed
+    pub max_options_bytes: usize,
+    // Most label sets exported per labeled metric; the rest are aggregated
+    pub metrics_max_series: usize,
+    // Interval for probing forwarding and repairing rules; None disables self-heal
+    pub self_heal_interval: Option<Duration>,
+    // Endpoint id bytes used in vxcan interface names, already clamped to fit IFNAMSIZ
+    pub endpoint_short_name_len: usize,
+    // Delay after Join before checking the peer left the host namespace; None disables it
+    pub peer_check_delay: Option<Duration>,
+    // Longest a Join may take before Docker is told it failed; None waits indefinitely
+    pub attach_timeout: Option<Duration>,
+    // Log the exact argument vector of every ip/cangw command the plugin runs
+    pub log_commands: bool,
+    // Extra attempts at a cangw add or remove that fails
+    pub cangw_retries: u32,
+    // Log and trace external commands without running them
+    pub dry_run_commands: bool,
+    // Change nothing on the host: commands and netlink changes are logged, not made
+    pub dry_run: bool,
+    // UNIX socket path or TCP address (e.g. 127.0.0.1:9180) of the introspection/admin API
+    pub admin_addr: String,
+    // Bearer token required by mutating admin endpoints; None leaves them open
+    pub admin_token: Option<String>,
+    // TCP address of a listener serving only /metrics; None leaves metrics on the admin API
+    pub metrics_addr: Option<SocketAddr>,
+    // TCP address of a listener serving only /health, for liveness probes
+    pub health_addr: Option<SocketAddr>,
+    // JSON file of networks that should exist at startup
+    pub declared_networks: Option<String>,
+    // Delete networks the declaration doesn't list
+    pub declared_prune: bool,
+    pub warmup_policy: WarmupPolicy,
+    // How often a fail-closed warm-up re-checks the recovered state
+    pub warmup_retry: Duration,
+    // Most interface creations (`ip link add`) run at once; 0 removes the limit
+    pub max_concurrent_creates: usize,
+    // On Join, install only the cangw jobs missing from the kernel instead of all of them
+    pub minimal_repair: bool,
+    // Remove networks' rules and interfaces on SIGTERM/SIGINT instead of leaving them in place
+    pub shutdown_teardown: bool,
+    // Longest wait for in-flight operations on SIGTERM/SIGINT
+    pub shutdown_timeout: Duration,
+    // State file locations tried in order; the first writable one is used
+    pub state_files: Vec<String>,
+    // Directory command traces are recorded into
+    pub trace_dir: String,
+    // How often networks with a vxcan.ttl are checked for expiry
+    pub ttl_sweep_interval: Duration,
+    // In-container interface names a DstPrefix is warned about (e.g. `eth` would clash with eth0)
+    pub reserved_prefixes: Vec<String>,
+    // Refuse CreateNetwork requests carrying an IP address pool (use --ipam-driver null)
+    pub require_null_ipam: bool,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        PluginConfig {
+            rule_watch_interval: None,
+            rule_watch_strict: false,
+            duplicate_endpoint_policy: DuplicateEndpointPolicy::default(),
+            missing_network_detach: MissingNetworkPolicy::default(),
+            scope: Scope::default(),
+            event_log_size: 256,
+            vcan_pool_size: 0,
+            max_options_bytes: 64 * 1024,
+            metrics_max_series: 256,
+            self_heal_interval: None,
+            endpoint_short_name_len: DEFAULT_SHORT_NAME_LEN,
+            peer_check_delay: None,
+            attach_timeout: Some(Duration::from_secs(5)),
+            log_commands: false,
+            cangw_retries: 2,
+            dry_run_commands: false,
+            dry_run: false,
+            admin_addr: String::from(INTROSPECT_SOCKET),
+            admin_token: None,
+            metrics_addr: None,
+            health_addr: None,
+            declared_networks: None,
+            declared_prune: false,
+            warmup_policy: WarmupPolicy::default(),
+            warmup_retry: Duration::from_secs(5),
+            max_concurrent_creates: 4,
+            minimal_repair: false,
+            shutdown_teardown: false,
+            shutdown_timeout: Duration::from_secs(10),
+            state_files: vec![
+                String::from(NETWORK_STATE_FILE),
+                String::from(FALLBACK_STATE_FILE),
+            ],
+            trace_dir: String::from("/var/lib/rustycan4docker/traces"),
+            ttl_sweep_interval: Duration::from_secs(5),
+            reserved_prefixes: ["eth", "lo", "veth", "docker", "br", "wlan", "can"]
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            require_null_ipam: false,
+        }
+    }
+}
+
+impl PluginConfig {
+    pub fn from_env() -> Self {
+        let defaults = PluginConfig::default();
+        PluginConfig {
+            rule_watch_interval: env_u64("RUSTYCAN_RULE_WATCH_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .or(defaults.rule_watch_interval),
+            rule_watch_strict: env_bool("RUSTYCAN_RULE_WATCH_STRICT")
+                .unwrap_or(defaults.rule_watch_strict),
+            duplicate_endpoint_policy: match env_str("RUSTYCAN_DUPLICATE_ENDPOINT_POLICY") {
+                Some(v) if v == "reject" => DuplicateEndpointPolicy::Reject,
+                Some(v) if v == "recreate" => DuplicateEndpointPolicy::Recreate,
+                Some(v) => {
+                    warn!(
+                        "Ignoring invalid value '{}' for RUSTYCAN_DUPLICATE_ENDPOINT_POLICY",
+                        v
+                    );
+                    defaults.duplicate_endpoint_policy
+                }
+                None => defaults.duplicate_endpoint_policy,
+            },
+            missing_network_detach: match env_str("RUSTYCAN_DETACH_MISSING_NETWORK") {
+                Some(v) if v == "cleanup" => MissingNetworkPolicy::Cleanup,
+                Some(v) if v == "error" => MissingNetworkPolicy::Error,
+                Some(v) => {
+                    warn!(
+                        "Ignoring invalid value '{}' for RUSTYCAN_DETACH_MISSING_NETWORK",
+                        v
+                    );
+                    defaults.missing_network_detach
+                }
+                None => defaults.missing_network_detach,
+            },
+            // Unlike other settings, a bad scope is fatal: Docker would treat the
+            // networks differently from what the operator asked for
+            scope: match env_raw("RUSTYCAN_SCOPE").as_deref() {
+                Some("local") => Scope::Local,
+                Some("global") => Scope::Global,
+                Some(v) => {
+                    warn!(
+                        "Invalid RUSTYCAN_SCOPE '{}': must be exactly 'local' or 'global'",
+                        v
+                    );
+                    std::process::exit(2);
+                }
+                None => defaults.scope,
+            },
+            event_log_size: env_u64("RUSTYCAN_EVENT_LOG_SIZE")
+                .map(|n| n as usize)
+                .unwrap_or(defaults.event_log_size),
+            vcan_pool_size: env_u64("RUSTYCAN_VCAN_POOL_SIZE")
+                .map(|n| n as usize)
+                .unwrap_or(defaults.vcan_pool_size),
+            max_options_bytes: env_u64("RUSTYCAN_MAX_OPTIONS_BYTES")
+                .filter(|n| *n > 0)
+                .map(|n| n as usize)
+                .unwrap_or(defaults.max_options_bytes),
+            metrics_max_series: env_u64("RUSTYCAN_METRICS_MAX_SERIES")
+                .map(|n| n as usize)
+                .unwrap_or(defaults.metrics_max_series),
+            self_heal_interval: env_u64("RUSTYCAN_SELF_HEAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .or(defaults.self_heal_interval),
+            endpoint_short_name_len: match env_u64("RUSTYCAN_ENDPOINT_NAME_LEN") {
+                Some(n) if n == 0 || n as usize > MAX_SHORT_NAME_LEN => {
+                    let clamped = (n as usize).clamp(1, MAX_SHORT_NAME_LEN);
+                    warn!(
+                        "RUSTYCAN_ENDPOINT_NAME_LEN={} does not fit an interface name, using {}",
+                        n, clamped
+                    );
+                    clamped
+                }
+                Some(n) => n as usize,
+                None => defaults.endpoint_short_name_len,
+            },
+            peer_check_delay: env_u64("RUSTYCAN_PEER_CHECK_MS")
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis)
+                .or(defaults.peer_check_delay),
+            attach_timeout: match env_u64("RUSTYCAN_ATTACH_TIMEOUT_MS") {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => defaults.attach_timeout,
+            },
+            log_commands: env_bool("RUSTYCAN_LOG_COMMANDS").unwrap_or(defaults.log_commands),
+            cangw_retries: env_u64("RUSTYCAN_CANGW_RETRIES")
+                .map(|n| n.min(10) as u32)
+                .unwrap_or(defaults.cangw_retries),
+            dry_run_commands: env_bool("RUSTYCAN_DRY_RUN_COMMANDS")
+                .unwrap_or(defaults.dry_run_commands),
+            dry_run: env_bool("RUSTYCAN_DRY_RUN").unwrap_or(defaults.dry_run),
+            admin_addr: env_raw("RUSTYCAN_ADMIN_ADDR").unwrap_or(defaults.admin_addr),
+            admin_token: env_raw("RUSTYCAN_ADMIN_TOKEN").or(defaults.admin_token),
+            metrics_addr: env_addr("RUSTYCAN_METRICS_ADDR").or(defaults.metrics_addr),
+            health_addr: env_addr("RUSTYCAN_HEALTH_ADDR").or(defaults.health_addr),
+            declared_networks: env_raw("RUSTYCAN_NETWORKS_FILE").or(defaults.declared_networks),
+            declared_prune: env_bool("RUSTYCAN_NETWORKS_PRUNE").unwrap_or(defaults.declared_prune),
+            warmup_policy: match env_str("RUSTYCAN_WARMUP_POLICY") {
+                Some(v) if v == "open" => WarmupPolicy::FailOpen,
+                Some(v) if v == "closed" => WarmupPolicy::FailClosed,
+                Some(v) => {
+                    warn!("Ignoring invalid value '{}' for RUSTYCAN_WARMUP_POLICY", v);
+                    defaults.warmup_policy
+                }
+                None => defaults.warmup_policy,
+            },
+            warmup_retry: env_u64("RUSTYCAN_WARMUP_RETRY_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.warmup_retry),
+            max_concurrent_creates: env_u64("RUSTYCAN_MAX_CONCURRENT_CREATES")
+                .map(|n| n as usize)
+                .unwrap_or(defaults.max_concurrent_creates),
+            minimal_repair: env_bool("RUSTYCAN_MINIMAL_REPAIR").unwrap_or(defaults.minimal_repair),
+            shutdown_teardown: env_bool("RUSTYCAN_SHUTDOWN_TEARDOWN")
+                .unwrap_or(defaults.shutdown_teardown),
+            shutdown_timeout: env_u64("RUSTYCAN_SHUTDOWN_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.shutdown_timeout),
+            // A single RUSTYCAN_STATE_FILE pins the location, with no fallback
+            state_files: env_list("RUSTYCAN_STATE_FILES")
+                .or_else(|| env_raw("RUSTYCAN_STATE_FILE").map(|path| vec![path]))
+                .unwrap_or(defaults.state_files),
+            trace_dir: env_raw("RUSTYCAN_TRACE_DIR").unwrap_or(defaults.trace_dir),
+            ttl_sweep_interval: env_u64("RUSTYCAN_TTL_SWEEP_SECS")
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.ttl_sweep_interval),
+            reserved_prefixes: env_list("RUSTYCAN_RESERVED_PREFIXES")
+                .unwrap_or(defaults.reserved_prefixes),
+            require_null_ipam: env_bool("RUSTYCAN_REQUIRE_NULL_IPAM")
+                .unwrap_or(defaults.require_null_ipam),
+        }
+    }
+}
+
+// Comma-separated list, with blank entries dropped
+fn env_list(key: &str) -> Option<Vec<String>> {
+    env_raw(key).map(|v| {
+        v.split(',')
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect()
+    })
+}
+
+fn env_str(key: &str) -> Option<String> {
+    env::var(key).ok().map(|v| v.trim().to_ascii_lowercase())
+}
+
+// Case-preserving value; empty counts as unset
+fn env_raw(key: &str) -> Option<String> {
+    env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn env_addr(key: &str) -> Option<SocketAddr> {
+    let value = env_raw(key)?;
+    match value.parse::<SocketAddr>() {
+        Ok(addr) => Some(addr),
+        Err(_) => {
+            warn!("Ignoring invalid value '{}' for {}", value, key);
+            None
+        }
+    }
+}
+
+fn env_u64(key: &str) -> Option<u64> {
+    let value = env::var(key).ok()?;
+    match value.trim().parse::<u64>() {
+        Ok(v) => Some(v),
+        Err(_) => {
+            warn!("Ignoring invalid value '{}' for {}", value, key);
+            None
+        }
+    }
+}
+
+fn env_bool(key: &str) -> Option<bool> {
+    let value = env::var(key).ok()?;
+    match value.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "0" | "false" | "no" | "off" => Some(false),
+        _ => {
+            warn!("Ignoring invalid value '{}' for {}", value, key);
+            None
+        }
+    }
+}
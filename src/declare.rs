@@ -0,0 +1,122 @@
+/*
+ * Filename: declare.rs
+ * Created Date: Saturday, October 17th 2026, 9:40:22 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::error::RustyCanError;
+use crate::manager::{CreateNetworkRequest, NetworkManager};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+
+/// Operator-authored desired set of networks, e.g.
+/// `{"networks": [{"nuid": "bench", "options": {"vxcan.id": "1"}}]}`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DeclaredNetworks {
+    #[serde(default)]
+    pub networks: Vec<CreateNetworkRequest>,
+}
+
+impl DeclaredNetworks {
+    pub fn load(path: &str) -> Result<Self, RustyCanError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| RustyCanError::InvalidOptions(format!("reading {path}: {e}")))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| RustyCanError::InvalidOptions(format!("parsing {path}: {e}")))
+    }
+}
+
+/// Differences found (and fixed) while applying a declaration
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DriftReport {
+    // Declared networks that were missing and have been created
+    pub created: Vec<String>,
+    // Declared networks that exist with different options; left as they are
+    pub drifted: Vec<String>,
+    // Networks not in the declaration that were deleted (prune only)
+    pub pruned: Vec<String>,
+    // Declared networks that could not be created, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+/// Bring the plugin's networks in line with a declaration
+/// Missing networks are created; existing ones with different options are only reported.
+/// With `prune`, every network the declaration doesn't list is deleted
+pub fn apply(mgr: &NetworkManager, declared: &DeclaredNetworks, prune: bool) -> DriftReport {
+    let mut report = DriftReport::default();
+
+    for req in declared.networks.iter() {
+        let options = match req.options.as_str() {
+            Some(o) => o.to_string(),
+            None => req.options.to_string(),
+        };
+
+        match mgr.network_matches(&req.nuid, &options) {
+            Ok(Some(true)) => {}
+            Ok(Some(false)) => {
+                warn!(
+                    "Declared network {} exists with different options",
+                    req.nuid
+                );
+                report.drifted.push(req.nuid.clone());
+            }
+            Ok(None) => match mgr.network_create(req.nuid.clone(), options) {
+                Ok(()) => {
+                    info!("Created declared network {}", req.nuid);
+                    report.created.push(req.nuid.clone());
+                }
+                Err(e) => {
+                    error!("Unable to create declared network {}: {}", req.nuid, e);
+                    report.failed.push((req.nuid.clone(), e.to_string()));
+                }
+            },
+            Err(e) => {
+                warn!(
+                    "Invalid options for declared network {}: {}",
+                    req.nuid, e
+                );
+                report.failed.push((req.nuid.clone(), e.to_string()));
+            }
+        }
+    }
+
+    if prune {
+        let wanted: HashSet<&str> = declared.networks.iter().map(|r| r.nuid.as_str()).collect();
+        for nuid in mgr.network_ids() {
+            if wanted.contains(nuid.as_str()) {
+                continue;
+            }
+            match mgr.network_delete(nuid.clone()) {
+                Ok(()) => {
+                    info!("Pruned undeclared network {}", nuid);
+                    report.pruned.push(nuid);
+                }
+                Err(e) => error!("Unable to prune network {}: {}", nuid, e),
+            }
+        }
+    }
+
+    report
+}
@@ -24,92 +24,122 @@
  * SOFTWARE.
  */
 
-use truncrate::*;
+use crate::error::RustyCanError;
+use crate::link::{self, LinkStatus, Origin};
+use crate::netlink;
+use crate::netem::Netem;
+use crate::network::InterfaceRole;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+/// Endpoint interface name prefix used unless a network sets `vxcan.prefix`
+pub const DEFAULT_PREFIX: &str = "vxcan";
+const PEER_SUFFIX: &str = "p";
+
+/// Default number of endpoint id bytes used in interface names
+pub const DEFAULT_SHORT_NAME_LEN: usize = 8;
+/// Most endpoint id bytes that still leave room for the default prefix and peer suffix
+pub const MAX_SHORT_NAME_LEN: usize = link::IFNAMSIZ - 1 - DEFAULT_PREFIX.len() - PEER_SUFFIX.len();
+
+/// Which way frames flow between an endpoint and the rest of its network
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    #[default]
+    Both,
+    // The container only receives; nothing it sends leaves its vxcan pair
+    Rx,
+    // The container only sends; no frames are routed to it
+    Tx,
+}
+
+impl Direction {
+    /// Direction requested through the `vxcan.direction` endpoint option
+    pub fn from_options(options: &serde_json::Value) -> Result<Self, RustyCanError> {
+        match options.get("vxcan.direction") {
+            None | Some(serde_json::Value::Null) => Ok(Direction::Both),
+            Some(v) => match v.as_str().map(|s| s.trim().to_ascii_lowercase()).as_deref() {
+                Some("both") => Ok(Direction::Both),
+                Some("rx") => Ok(Direction::Rx),
+                Some("tx") => Ok(Direction::Tx),
+                _ => Err(RustyCanError::InvalidOptions(format!(
+                    "vxcan.direction must be both, rx or tx, got {v}"
+                ))),
+            },
+        }
+    }
+
+    pub fn sends(self) -> bool {
+        self != Direction::Rx
+    }
+
+    pub fn receives(self) -> bool {
+        self != Direction::Tx
+    }
+}
 
 #[derive(Clone)]
 pub struct Endpoint {
     pub uid: String,
     pub device: String,
     pub peer: String,
-    created: bool,
+    pub created: bool,
+    // Options from the CreateEndpoint request (Null when recreated during recovery)
+    pub options: serde_json::Value,
+    // Rebuilt from kernel state; `uid` is only the short id until Docker names it in full
+    pub reconstructed: bool,
+    // Impairment applied to the host-side device while attached
+    pub netem: Option<Netem>,
+    // MTU of both ends of the pair, kept so a rebuilt pair matches the network
+    pub mtu: u32,
+    // Which gateway rules join the endpoint to the bus and the other endpoints
+    pub direction: Direction,
 }
 
 impl Endpoint {
     /// Check if the endpoint's vxcan interface exists in the kernel
-    pub fn interface_exists(&self) -> bool {
-        match interfaces::Interface::get_all() {
-            Ok(ifcs) => {
-                for i in ifcs.into_iter() {
-                    if i.name == self.device {
-                        return true;
-                    }
-                }
-                false
-            }
-            Err(_) => {
-                eprintln!(" !! Failed to query network interfaces");
-                false
-            }
-        }
+    pub fn interface_exists(&self) -> Result<bool, RustyCanError> {
+        link::interface_exists(&self.device)
     }
 
     /// Recreate the vxcan interface pair if it's missing
     /// Returns true if interfaces were recreated, false if they already existed
-    pub fn ensure_interface_exists(&mut self) -> Result<bool, String> {
-        if self.interface_exists() {
-            println!(" -> Interface {} already exists, no recreation needed", self.device);
+    pub fn ensure_interface_exists(&mut self) -> Result<bool, RustyCanError> {
+        // Never recreate on an enumeration failure; the interface may well exist
+        if self.interface_exists()? {
+            info!("Interface {} already exists, no recreation needed", self.device);
             return Ok(false);
         }
 
-        println!(" -> Interface {} missing after reboot, recreating...", self.device);
+        info!("Interface {} missing after reboot, recreating...", self.device);
         
         // Try to create the vxcan pair
-        let output = std::process::Command::new("ip")
-            .arg("link")
-            .arg("add")
-            .arg("dev")
-            .arg(&self.device)
-            .arg("type")
-            .arg("vxcan")
-            .arg("peer")
-            .arg("name")
-            .arg(&self.peer)
-            .output();
-
-        match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let stderr = String::from_utf8_lossy(&result.stderr);
-                    // Check if error is "File exists" - that means interface was created by another thread
-                    if stderr.contains("File exists") {
-                        println!(" -> Interface {} was created concurrently, continuing", self.device);
-                        return Ok(false);
-                    }
-                    return Err(format!(" !! Failed to recreate vxcan device {}: {}", self.device, stderr));
-                }
+        match link::create_vxcan_pair(&self.device, &self.peer, self.mtu, Origin::Rebuild) {
+            Ok(()) => {}
+            // The interface was created by another thread
+            Err(RustyCanError::LinkExists(_)) => {
+                info!("Interface {} was created concurrently, continuing", self.device);
+                return Ok(false);
+            }
+            Err(e) => {
+                return Err(RustyCanError::InterfaceCreate {
+                    name: self.device.clone(),
+                    reason: e.to_string(),
+                })
             }
-            Err(e) => return Err(format!(" !! Failed to execute ip command: {}", e)),
         }
 
         // Bring up the interface
-        let output = std::process::Command::new("ip")
-            .arg("link")
-            .arg("set")
-            .arg("up")
-            .arg(&self.device)
-            .output();
-
-        match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let stderr = String::from_utf8_lossy(&result.stderr);
-                    return Err(format!(" !! Failed to bring up vxcan device {}: {}", self.device, stderr));
-                }
-            }
-            Err(e) => return Err(format!(" !! Failed to execute ip command: {}", e)),
+        if let Err(e) = netlink::set_link_up(&self.device) {
+            return Err(RustyCanError::InterfaceUp {
+                name: self.device.clone(),
+                reason: e.to_string(),
+            });
         }
 
-        println!(" -> Successfully recreated interface pair: {} <-> {}", self.device, self.peer);
+        info!("Successfully recreated interface pair: {} <-> {}", self.device, self.peer);
         
         // Mark as created so we clean it up properly on drop
         self.created = true;
@@ -117,79 +147,342 @@ impl Endpoint {
         Ok(true)
     }
 
-    pub fn new(uid: String) -> Self {
-        println!("Creating a new endpoint");
-        let ifcs = interfaces::Interface::get_all().unwrap();
+    /// Give an endpoint rebuilt without options (from the kernel) the options it was
+    /// saved with, so its direction and netem match the original
+    /// The MTU is left as the kernel reports it for the existing pair
+    pub fn restore_options(&mut self, options: serde_json::Value) -> Result<(), RustyCanError> {
+        if !self.options.is_null() || options.is_null() {
+            return Ok(());
+        }
+        self.netem = Netem::from_options(&options)?;
+        self.direction = Direction::from_options(&options)?;
+        self.options = options;
+        Ok(())
+    }
 
-        let mut exists: bool = false;
-        let newifc = format!("vxcan{}", uid.truncate_to_byte_offset(8));
-        let peerifc = format!("{newifc}p");
+    /// Most endpoint id bytes that fit after `prefix` with room for the peer suffix
+    pub fn max_short_name_len(prefix: &str) -> usize {
+        (link::IFNAMSIZ - 1).saturating_sub(prefix.len() + PEER_SUFFIX.len())
+    }
+
+    /// Check a `vxcan.prefix` leaves room for `short_len` id bytes and the peer suffix
+    pub fn validate_prefix(prefix: &str, short_len: usize) -> Result<(), RustyCanError> {
+        let sample = format!("{prefix}{}{PEER_SUFFIX}", "0".repeat(short_len));
+        link::validate_interface_name(&sample, "vxcan.prefix")
+    }
+
+    /// Device and peer interface names for an endpoint: `short_len` hex digits of a
+    /// SHA-256 of its whole id (clamped so the peer name still fits in IFNAMSIZ)
+    /// Hashing the whole id keeps ids that share a long prefix apart
+    pub fn interface_names(prefix: &str, uid: &str, short_len: usize) -> (String, String) {
+        let short_len = short_len.clamp(1, Endpoint::max_short_name_len(prefix));
+        let digest: String = Sha256::digest(uid.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let device = format!("{prefix}{}", &digest[..short_len]);
+        let peer = format!("{device}{PEER_SUFFIX}");
+        (device, peer)
+    }
 
-        for i in ifcs.into_iter() {
-            if i.name.eq(&newifc) {
-                exists = true;
+    /// Every device and peer name an endpoint may be given, most preferred first: the
+    /// hashed names, then those with the last digit replaced by each other hex digit
+    pub fn candidate_interface_names(
+        prefix: &str,
+        uid: &str,
+        short_len: usize,
+    ) -> Vec<(String, String)> {
+        let hashed = Endpoint::interface_names(prefix, uid, short_len);
+        let stem = &hashed.0[..hashed.0.len() - 1];
+        let others: Vec<(String, String)> = "0123456789abcdef"
+            .chars()
+            .map(|suffix| format!("{stem}{suffix}"))
+            .filter(|device| *device != hashed.0)
+            .map(|device| {
+                let peer = format!("{device}{PEER_SUFFIX}");
+                (device, peer)
+            })
+            .collect();
+        let mut names = vec![hashed];
+        names.extend(others);
+        names
+    }
+
+    /// Device and peer names that collide with no name in `claimed`, across network
+    /// devices, endpoint devices and endpoint peers alike
+    /// `saved` are the names the endpoint was given before, kept from the state file so
+    /// a re-join after a restart lands on the same pair; otherwise the hashed names are
+    /// used, with a suffix digit on a collision
+    pub fn unclaimed_interface_names(
+        prefix: &str,
+        uid: &str,
+        short_len: usize,
+        claimed: &HashSet<String>,
+        saved: Option<(String, String)>,
+    ) -> Result<(String, String), RustyCanError> {
+        let free = |(device, peer): &(String, String)| {
+            !claimed.contains(device) && !claimed.contains(peer)
+        };
+
+        if let Some(names) = saved.filter(|names| free(names)) {
+            return Ok(names);
+        }
+
+        let candidates = Endpoint::candidate_interface_names(prefix, uid, short_len);
+        match candidates.iter().position(free) {
+            Some(0) => Ok(candidates[0].clone()),
+            Some(i) => {
+                info!(
+                    "Using {} for endpoint {} to avoid an interface name collision",
+                    candidates[i].0, uid
+                );
+                Ok(candidates[i].clone())
             }
+            None => Err(RustyCanError::InterfaceNameTaken(candidates[0].0.clone())),
+        }
+    }
+
+    /// Whether a kernel interface name follows the endpoint naming scheme for any of
+    /// `prefixes`, and which end it is
+    pub fn classify_interface_name<'a>(
+        name: &str,
+        prefixes: impl IntoIterator<Item = &'a str>,
+    ) -> Option<InterfaceRole> {
+        prefixes
+            .into_iter()
+            .find_map(|prefix| Endpoint::classify_with_prefix(name, prefix))
+    }
+
+    fn classify_with_prefix(name: &str, prefix: &str) -> Option<InterfaceRole> {
+        let short = name.strip_prefix(prefix)?;
+        let (short, role) = match short.strip_suffix(PEER_SUFFIX) {
+            Some(s) => (s, InterfaceRole::EndpointPeer),
+            None => (short, InterfaceRole::EndpointDevice),
+        };
+        // Endpoint ids are hex, so anything else is not one of ours
+        if short.is_empty() || !short.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(role)
+    }
+
+    /// MTU requested through the `vxcan.mtu` endpoint option, or `network_mtu`
+    /// when unset. Only the classic (16) and CAN FD (72) frame sizes exist, and a
+    /// CAN FD endpoint needs a network whose bus carries CAN FD frames
+    pub fn mtu_option(
+        options: &serde_json::Value,
+        network_mtu: u32,
+    ) -> Result<u32, RustyCanError> {
+        let value = match options.get("vxcan.mtu") {
+            None | Some(serde_json::Value::Null) => return Ok(network_mtu),
+            Some(v) => v,
+        };
+        let mtu = match value {
+            serde_json::Value::String(s) => s.trim().parse::<u32>().ok(),
+            v => v.as_u64().and_then(|n| u32::try_from(n).ok()),
+        };
+        match mtu {
+            Some(link::CAN_MTU) => Ok(link::CAN_MTU),
+            Some(link::CANFD_MTU) if network_mtu == link::CANFD_MTU => Ok(link::CANFD_MTU),
+            Some(link::CANFD_MTU) => Err(RustyCanError::InvalidOptions(String::from(
+                "vxcan.mtu=72 needs a network created with vxcan.fd=true",
+            ))),
+            _ => Err(RustyCanError::InvalidOptions(format!(
+                "vxcan.mtu must be {} (CAN) or {} (CAN FD), got {}",
+                link::CAN_MTU,
+                link::CANFD_MTU,
+                value
+            ))),
+        }
+    }
+
+    pub fn new(
+        uid: String,
+        options: serde_json::Value,
+        prefix: &str,
+        short_len: usize,
+        claimed: &HashSet<String>,
+        saved: Option<(String, String)>,
+        mtu: u32,
+    ) -> Result<Self, RustyCanError> {
+        info!("Creating a new endpoint");
+        let netem = Netem::from_options(&options)?;
+        let mtu = Endpoint::mtu_option(&options, mtu)?;
+        let direction = Direction::from_options(&options)?;
+        let kernel: HashSet<String> = link::interface_names()?.into_iter().collect();
+        let (newifc, peerifc) =
+            Endpoint::unclaimed_interface_names(prefix, &uid, short_len, claimed, saved)?;
+        link::validate_interface_name(&newifc, "EndpointID")?;
+        link::validate_interface_name(&peerifc, "EndpointID")?;
+
+        let exists = kernel.contains(&newifc);
+        if exists {
+            link::check_kind(&newifc, &["vxcan"])?;
         }
 
         if !exists {
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("add")
-                .arg("dev")
-                .arg(&newifc)
-                .arg("type")
-                .arg("vxcan")
-                .arg("peer")
-                .arg("name")
-                .arg(&peerifc)
-                .output()
-                .expect("failed to add VXCAN device");
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("set")
-                .arg("up")
-                .arg(&newifc)
-                .output()
-                .expect("failed to start VXCAN device");
+            link::create_vxcan_pair(&newifc, &peerifc, mtu, Origin::Request).map_err(|e| {
+                RustyCanError::InterfaceCreate {
+                    name: newifc.clone(),
+                    reason: e.to_string(),
+                }
+            })?;
+            link::bring_up_new(&newifc)?;
+        } else if LinkStatus::query(&newifc).mtu != Some(mtu) {
+            info!("Setting MTU {} on existing interface {}", mtu, newifc);
+            link::set_can_mtu(&newifc, mtu)
+                .and_then(|()| link::set_can_mtu(&peerifc, mtu))
+                .and_then(|()| netlink::set_link_up(&newifc))
+                .map_err(|e| RustyCanError::InterfaceCreate {
+                    name: newifc.clone(),
+                    reason: format!("unable to set MTU {}: {}", mtu, e),
+                })?;
         }
-        println!(
+        info!(
             "Creating VXCAN tunnel with settings: device='{}', peer='{}'",
             newifc, peerifc
         );
-        Endpoint {
-            uid: uid,
+        Ok(Endpoint {
+            uid,
             device: newifc,
             peer: peerifc,
             created: !exists,
+            options,
+            reconstructed: false,
+            netem,
+            mtu,
+            direction,
+        })
+    }
+
+    /// Second endpoint of a direct network, taking the other end of `first`'s vxcan pair
+    /// The pair stays owned by `first`, so nothing is created or deleted here
+    pub fn direct_partner(uid: String, options: serde_json::Value, first: &Endpoint) -> Self {
+        info!(
+            "Pairing endpoint directly with {}: device='{}', peer='{}'",
+            first.uid, first.peer, first.device
+        );
+        Endpoint {
+            uid,
+            device: first.peer.clone(),
+            peer: first.device.clone(),
+            created: false,
+            options,
+            reconstructed: false,
+            netem: None,
+            mtu: first.mtu,
+            direction: Direction::Both,
+        }
+    }
+
+    /// Rebuild an endpoint from an existing endpoint-named vxcan device in the kernel
+    /// Only the name is known, so it is keyed by the hex digits after the prefix until
+    /// Docker refers to it; the interface is owned as if created
+    pub fn from_kernel(device: &str, prefix: &str) -> Option<Self> {
+        if Endpoint::classify_with_prefix(device, prefix)? != InterfaceRole::EndpointDevice {
+            return None;
         }
+        let short = device.strip_prefix(prefix)?;
+        Some(Endpoint {
+            uid: short.to_string(),
+            device: device.to_string(),
+            peer: format!("{device}{PEER_SUFFIX}"),
+            created: true,
+            options: serde_json::Value::Null,
+            reconstructed: true,
+            netem: None,
+            mtu: LinkStatus::query(device).mtu.unwrap_or(link::CAN_MTU),
+            direction: Direction::Both,
+        })
     }
 }
 
 impl Drop for Endpoint {
     fn drop(&mut self) {
         if self.created {
-            // Actually delete the network interface
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("set")
-                .arg("down")
-                .arg(&self.device)
-                .output()
-                .expect("failed to start VCAN device");
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("del")
-                .arg("dev")
-                .arg(&self.device)
-                .arg("type")
-                .arg("vxcan")
-                .output()
-                .expect("failed to remove VCAN device");
-
-            println!(
+            // Actually delete the network interface (and with it the peer)
+            // It may already be gone, e.g. after a shutdown teardown or an external delete
+            match netlink::delete_link(&self.device) {
+                Ok(()) | Err(RustyCanError::InterfaceNotFound(_)) => {}
+                Err(e) => error!("Failed to remove vxcan device {}: {}", self.device, e),
+            }
+
+            info!(
                 "Dropping Endpoint object with {}, {}",
                 self.device, self.peer,
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_hash_the_whole_id() {
+        let (a, a_peer) = Endpoint::interface_names("vxcan", "abcdef0123456789", 8);
+        let (b, _) = Endpoint::interface_names("vxcan", "abcdef0123456780", 8);
+        assert_ne!(a, b);
+        assert_eq!(a.len(), "vxcan".len() + 8);
+        assert_eq!(a_peer, format!("{a}p"));
+        assert_eq!(
+            Endpoint::classify_interface_name(&a, ["vxcan"]),
+            Some(InterfaceRole::EndpointDevice)
+        );
+        assert_eq!(
+            Endpoint::classify_interface_name(&a_peer, ["vxcan"]),
+            Some(InterfaceRole::EndpointPeer)
+        );
+    }
+
+    #[test]
+    fn names_fit_in_ifnamsiz() {
+        let (device, peer) = Endpoint::interface_names("vxcan", "abcdef", 64);
+        assert_eq!(device.len(), "vxcan".len() + MAX_SHORT_NAME_LEN);
+        assert!(peer.len() < link::IFNAMSIZ);
+    }
+
+    #[test]
+    fn a_collision_gets_a_suffix_digit() {
+        let hashed = Endpoint::interface_names("vxcan", "ep1", 8);
+        let claimed: HashSet<String> = [hashed.0.clone()].into_iter().collect();
+        let (device, peer) =
+            Endpoint::unclaimed_interface_names("vxcan", "ep1", 8, &claimed, None).unwrap();
+        assert_ne!(device, hashed.0);
+        assert_eq!(device[..device.len() - 1], hashed.0[..hashed.0.len() - 1]);
+        assert_eq!(peer, format!("{device}p"));
+    }
+
+    #[test]
+    fn saved_names_are_reused_while_free() {
+        let saved = (String::from("vxcan00000000"), String::from("vxcan00000000p"));
+        let names = Endpoint::unclaimed_interface_names(
+            "vxcan",
+            "ep1",
+            8,
+            &HashSet::new(),
+            Some(saved.clone()),
+        )
+        .unwrap();
+        assert_eq!(names, saved);
+
+        let claimed: HashSet<String> = [saved.1.clone()].into_iter().collect();
+        let names =
+            Endpoint::unclaimed_interface_names("vxcan", "ep1", 8, &claimed, Some(saved)).unwrap();
+        assert_eq!(names, Endpoint::interface_names("vxcan", "ep1", 8));
+    }
+
+    #[test]
+    fn every_name_taken_is_an_error() {
+        let claimed: HashSet<String> = Endpoint::candidate_interface_names("vxcan", "ep1", 8)
+            .into_iter()
+            .map(|(device, _)| device)
+            .collect();
+        assert_eq!(claimed.len(), 16);
+        assert!(matches!(
+            Endpoint::unclaimed_interface_names("vxcan", "ep1", 8, &claimed, None),
+            Err(RustyCanError::InterfaceNameTaken(_))
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+/*
+ * Filename: error.rs
+ * Created Date: Friday, October 16th 2026, 10:02:11 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use std::fmt;
+use std::time::Duration;
+
+/// Errors surfaced by network and endpoint management operations
+#[derive(Debug, Clone, PartialEq)]
+pub enum RustyCanError {
+    InvalidOptions(String),
+    OptionsTooLarge { len: usize, limit: usize },
+    NetworkExists(String),
+    EndpointConflict(String),
+    Persistence(String),
+    InterfaceQuery(String),
+    NetworkNotFound(String),
+    EndpointNotFound(String),
+    DirectNetworkFull(String),
+    InterfaceNameTaken(String),
+    InvalidInterfaceName { name: String, option: String, reason: String },
+    InterfaceNotFound(String),
+    LinkMissing(String),
+    InterfaceInUse { name: String, nuid: String },
+    InterfaceKind { name: String, kind: String, expected: String },
+    // The cause, unless the attach itself panicked
+    AttachFailed { epuid: String, source: Option<Box<RustyCanError>> },
+    AttachTimeout { epuid: String, after: Duration },
+    Maintenance,
+    LinkCommand(String),
+    LinkExists(String),
+    InterfaceCreate { name: String, reason: String },
+    InterfaceUp { name: String, reason: String },
+    Netlink(String),
+    RuleQuery(String),
+    RuleCommand(String),
+    Probe(String),
+}
+
+impl fmt::Display for RustyCanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RustyCanError::InvalidOptions(msg) => write!(f, "invalid network options: {msg}"),
+            RustyCanError::OptionsTooLarge { len, limit } => {
+                write!(
+                    f,
+                    "options are {len} bytes, exceeding the {limit} byte limit"
+                )
+            }
+            RustyCanError::NetworkExists(nuid) => write!(f, "network {nuid} already exists"),
+            RustyCanError::EndpointConflict(epuid) => {
+                write!(f, "endpoint {epuid} already exists with different options")
+            }
+            RustyCanError::Persistence(msg) => write!(f, "unable to persist state: {msg}"),
+            RustyCanError::InterfaceQuery(msg) => {
+                write!(f, "unable to enumerate network interfaces: {msg}")
+            }
+            RustyCanError::NetworkNotFound(nuid) => write!(f, "network {nuid} not found"),
+            RustyCanError::EndpointNotFound(epuid) => write!(f, "endpoint {epuid} not found"),
+            RustyCanError::InterfaceNameTaken(name) => {
+                write!(
+                    f,
+                    "interface name {name} is already used by another network or endpoint"
+                )
+            }
+            RustyCanError::InvalidInterfaceName {
+                name,
+                option,
+                reason,
+            } => {
+                write!(
+                    f,
+                    "interface name '{name}' from {option} is invalid: {reason}"
+                )
+            }
+            RustyCanError::DirectNetworkFull(nuid) => {
+                write!(f, "direct network {nuid} already has two endpoints")
+            }
+            RustyCanError::InterfaceNotFound(name) => write!(f, "interface {name} does not exist"),
+            RustyCanError::LinkMissing(name) => {
+                write!(f, "linked interface {name} (vxcan.link) is missing; the link is broken")
+            }
+            RustyCanError::InterfaceInUse { name, nuid } => {
+                write!(f, "interface {name} is already used by network {nuid}")
+            }
+            RustyCanError::InterfaceKind { name, kind, expected } => {
+                write!(f, "interface {name} is of type {kind}, expected {expected}")
+            }
+            RustyCanError::AttachFailed { epuid, source } => match source {
+                Some(source) => write!(f, "unable to attach endpoint {epuid}: {source}"),
+                None => write!(f, "unable to attach endpoint {epuid}"),
+            },
+            RustyCanError::AttachTimeout { epuid, after } => {
+                write!(f, "attaching endpoint {epuid} took longer than {}ms", after.as_millis())
+            }
+            RustyCanError::Maintenance => {
+                write!(f, "plugin is in maintenance mode; changes are not accepted")
+            }
+            RustyCanError::LinkCommand(msg) => write!(f, "link command failed: {msg}"),
+            RustyCanError::LinkExists(name) => write!(f, "interface {name} already exists"),
+            RustyCanError::InterfaceCreate { name, reason } => {
+                write!(f, "unable to create interface {name}: {reason}")
+            }
+            RustyCanError::InterfaceUp { name, reason } => {
+                write!(f, "unable to bring up interface {name}: {reason}")
+            }
+            RustyCanError::Netlink(msg) => write!(f, "netlink request failed: {msg}"),
+            RustyCanError::RuleCommand(msg) => write!(f, "cangw command failed: {msg}"),
+            RustyCanError::RuleQuery(msg) => write!(f, "unable to list kernel rules: {msg}"),
+            RustyCanError::Probe(msg) => write!(f, "forwarding probe failed: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for RustyCanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RustyCanError::AttachFailed {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
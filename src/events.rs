@@ -0,0 +1,96 @@
+/*
+ * Filename: events.rs
+ * Created Date: Friday, October 16th 2026, 1:45:31 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Network and endpoint lifecycle changes, including reboot recovery
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum LifecycleEvent {
+    NetworkCreated { nuid: String },
+    NetworkDeleted { nuid: String },
+    NetworkRecovered { nuid: String },
+    NetworkExpired { nuid: String },
+    NetworkBusOff { nuid: String },
+    NetworkBusRecovered { nuid: String },
+    EndpointCreated { nuid: String, epuid: String },
+    EndpointDeleted { nuid: String, epuid: String },
+    EndpointRecovered { nuid: String, epuid: String },
+    EndpointAttached { nuid: String, epuid: String },
+    EndpointDetached { nuid: String, epuid: String },
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TimedEvent {
+    // Milliseconds since the UNIX epoch
+    pub timestamp_ms: u64,
+    pub event: LifecycleEvent,
+}
+
+/// Bounded history of recent lifecycle events; the oldest entries are evicted first
+pub struct EventLog {
+    capacity: usize,
+    events: Mutex<VecDeque<TimedEvent>>,
+}
+
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        EventLog {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn record(&self, event: LifecycleEvent) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+
+        let mut events = self.events.lock();
+        while events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(TimedEvent {
+            timestamp_ms,
+            event,
+        });
+    }
+
+    /// The most recent `n` events, oldest first
+    pub fn recent(&self, n: usize) -> Vec<TimedEvent> {
+        let events = self.events.lock();
+        let skip = events.len().saturating_sub(n);
+        events.iter().skip(skip).cloned().collect()
+    }
+}
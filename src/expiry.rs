@@ -0,0 +1,50 @@
+/*
+ * Filename: expiry.rs
+ * Created Date: Saturday, October 17th 2026, 9:14:37 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::manager::NetworkManager;
+use log::error;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Background task deleting networks whose vxcan.ttl has run out, until shutdown
+pub async fn run(mgr: NetworkManager, interval: Duration, mut rx: oneshot::Receiver<()>) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if mgr.in_maintenance() {
+                    continue;
+                }
+                let m = mgr.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || m.expire_networks()).await {
+                    error!("Network expiry pass failed: {}", e);
+                    return;
+                }
+            }
+            _ = &mut rx => return,
+        }
+    }
+}
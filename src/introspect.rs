@@ -0,0 +1,653 @@
+/*
+ * Filename: introspect.rs
+ * Created Date: Friday, October 16th 2026, 9:12:40 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::error::RustyCanError;
+use crate::manager::{CloneNetworkRequest, CreateNetworkRequest, NetworkManager};
+use crate::metrics::Format;
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UnixListener;
+use tokio::sync::oneshot;
+use tokio_stream::wrappers::UnixListenerStream;
+use warp::{http, Filter};
+
+// Default debug/administration socket, kept separate from the Docker plugin socket
+pub const INTROSPECT_SOCKET: &str = "/run/rustycan4docker/introspect.sock";
+
+// How long the `status` command waits for the admin API
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the `reconcile` command waits; every network's rules are checked with cangw
+const RECONCILE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Rejection for a mutating request without the configured admin token
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Require `Authorization: Bearer <token>` when an admin token is configured
+fn authorized(token: Option<String>) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    let expected = token.map(|t| Arc::new(format!("Bearer {t}")));
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let expected = expected.clone();
+            async move {
+                match expected {
+                    None => Ok(()),
+                    Some(e) if header.as_deref() == Some(e.as_str()) => Ok(()),
+                    Some(_) => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, warp::Rejection> {
+    if err.find::<Unauthorized>().is_some() {
+        let body = serde_json::json!({ "Err": "Missing or invalid admin token" }).to_string();
+        return Ok(warp::reply::with_status(
+            body,
+            http::StatusCode::UNAUTHORIZED,
+        ));
+    }
+    Err(err)
+}
+
+fn reply_json<T: serde::Serialize>(value: &T) -> warp::reply::WithStatus<String> {
+    reply_json_status(value, http::StatusCode::OK)
+}
+
+fn reply_json_status<T: serde::Serialize>(
+    value: &T,
+    status: http::StatusCode,
+) -> warp::reply::WithStatus<String> {
+    match serde_json::to_string(value) {
+        Ok(json) => warp::reply::with_status(json, status),
+        Err(_) => warp::reply::with_status(
+            String::from(r#"{"Err":"Serializing introspection response"}"#),
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    }
+}
+
+fn reply_not_found(msg: &str) -> warp::reply::WithStatus<String> {
+    let body = serde_json::json!({ "Err": msg }).to_string();
+    warp::reply::with_status(body, http::StatusCode::NOT_FOUND)
+}
+
+async fn api_inspect_interface(
+    ifname: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.inspect_interface(&ifname) {
+        Some(info) => reply_json(&info),
+        None => reply_not_found("Interface is not managed by the plugin"),
+    };
+    Ok(reply)
+}
+
+async fn api_list_networks(mgr: NetworkManager) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply_json(&mgr.list_networks()))
+}
+
+async fn api_describe_network(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.describe_network(&nuid) {
+        Some(desc) => reply_json(&desc),
+        None => reply_not_found("Network is not managed by the plugin"),
+    };
+    Ok(reply)
+}
+
+async fn api_list_orphans(mgr: NetworkManager) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.list_orphans() {
+        Ok(orphans) => reply_json(&orphans),
+        Err(e) => warp::reply::with_status(
+            serde_json::json!({ "Err": e.to_string() }).to_string(),
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    };
+    Ok(reply)
+}
+
+async fn api_recent_events(
+    query: HashMap<String, String>,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let n = query
+        .get("n")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(usize::MAX);
+    Ok(reply_json(&mgr.recent_events(n)))
+}
+
+async fn api_create_networks(
+    requests: Vec<CreateNetworkRequest>,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.create_networks(requests) {
+        Ok(()) => warp::reply::with_status(String::from("{}"), http::StatusCode::OK),
+        Err(e) => warp::reply::with_status(
+            serde_json::json!({ "Err": e.to_string() }).to_string(),
+            http::StatusCode::CONFLICT,
+        ),
+    };
+    Ok(reply)
+}
+
+fn reply_result(result: Result<(), RustyCanError>) -> warp::reply::WithStatus<String> {
+    match result {
+        Ok(()) => warp::reply::with_status(String::from("{}"), http::StatusCode::OK),
+        Err(RustyCanError::NetworkNotFound(nuid)) => {
+            reply_not_found(&format!("Network {nuid} is not managed by the plugin"))
+        }
+        Err(e) => warp::reply::with_status(
+            serde_json::json!({ "Err": e.to_string() }).to_string(),
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+    }
+}
+
+async fn api_trigger_busoff(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply_result(mgr.trigger_busoff(&nuid)))
+}
+
+async fn api_recover_busoff(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply_result(mgr.recover_busoff(&nuid)))
+}
+
+async fn api_detach_all(
+    nuid: String,
+    query: HashMap<String, String>,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let remove = query.get("remove").is_some_and(|v| v == "true");
+    let reply = match mgr.detach_all(&nuid, remove) {
+        Ok(detached) => reply_json(&serde_json::json!({ "Detached": detached })),
+        Err(e) => reply_result(Err(e)),
+    };
+    Ok(reply)
+}
+
+async fn api_reconcile_rules(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.reconcile_rules(&nuid) {
+        Ok(reinstalled) => reply_json(&serde_json::json!({ "Reinstalled": reinstalled })),
+        Err(e) => reply_result(Err(e)),
+    };
+    Ok(reply)
+}
+
+async fn api_reconcile(mgr: NetworkManager) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.reconcile() {
+        Ok(report) => reply_json(&serde_json::json!({ "Networks": report })),
+        Err(e) => reply_result(Err(e)),
+    };
+    Ok(reply)
+}
+
+async fn api_rule_stats(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.rule_stats(&nuid) {
+        Ok(stats) => reply_json(&stats),
+        Err(e) => reply_result(Err(e)),
+    };
+    Ok(reply)
+}
+
+async fn api_diagnose_rules(
+    nuid: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.diagnose_rules(&nuid) {
+        Ok(diagnosis) => reply_json(&diagnosis),
+        Err(e) => reply_result(Err(e)),
+    };
+    Ok(reply)
+}
+
+async fn api_maintenance_status(mgr: NetworkManager) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(reply_json(
+        &serde_json::json!({ "Maintenance": mgr.in_maintenance() }),
+    ))
+}
+
+async fn api_set_maintenance(
+    enabled: bool,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    mgr.set_maintenance(enabled);
+    Ok(reply_json(&serde_json::json!({ "Maintenance": enabled })))
+}
+
+async fn api_start_trace(
+    name: String,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match command::start_trace(&mgr.config().trace_dir, &name) {
+        Ok(status) => reply_json(&status),
+        Err(e) => warp::reply::with_status(
+            serde_json::json!({ "Err": e }).to_string(),
+            http::StatusCode::BAD_REQUEST,
+        ),
+    };
+    Ok(reply)
+}
+
+async fn api_stop_trace() -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match command::stop_trace() {
+        Some(status) => reply_json(&status),
+        None => reply_not_found("No trace is being recorded"),
+    };
+    Ok(reply)
+}
+
+async fn api_release_warmup(mgr: NetworkManager) -> Result<impl warp::Reply, warp::Rejection> {
+    mgr.release_warmup();
+    Ok(reply_json(&serde_json::json!({ "Released": true })))
+}
+
+async fn api_clone_network(
+    src: String,
+    req: CloneNetworkRequest,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let reply = match mgr.clone_network(&src, req.nuid, req.overrides) {
+        Ok(()) => warp::reply::with_status(String::from("{}"), http::StatusCode::OK),
+        Err(RustyCanError::NetworkNotFound(nuid)) => {
+            reply_not_found(&format!("Network {nuid} is not managed by the plugin"))
+        }
+        Err(e) => warp::reply::with_status(
+            serde_json::json!({ "Err": e.to_string() }).to_string(),
+            http::StatusCode::CONFLICT,
+        ),
+    };
+    Ok(reply)
+}
+
+/// Serve the introspection API until the shutdown signal fires
+/// `status` command: print the running plugin's startup phase
+/// Exits with 0 only once the plugin is ready, so it can be used as a readiness probe
+pub fn status_command(addr: &str) -> i32 {
+    let body = match request(addr, "GET /status", None, STATUS_TIMEOUT) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Unable to query the plugin at {}: {}", addr, e);
+            return 2;
+        }
+    };
+    println!("{}", body);
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(v) if v["Phase"] == "ready" => 0,
+        _ => 1,
+    }
+}
+
+/// `reconcile` command: have the running plugin repair kernel drift and print what it fixed
+/// Exits with 0 when every network was reconciled, 1 if any failed, 2 if the plugin
+/// couldn't be reached
+pub fn reconcile_command(addr: &str, token: Option<&str>) -> i32 {
+    let body = match request(addr, "POST /reconcile", token, RECONCILE_TIMEOUT) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Unable to reach the plugin at {}: {}", addr, e);
+            return 2;
+        }
+    };
+    println!("{}", body);
+    match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(v) => match v["Networks"].as_object() {
+            Some(networks) if networks.values().all(|n| n["error"].is_null()) => 0,
+            Some(_) => 1,
+            None => 2,
+        },
+        Err(_) => 2,
+    }
+}
+
+// `target` is the method and path, e.g. "GET /status"
+fn request(addr: &str, target: &str, token: Option<&str>, timeout: Duration) -> io::Result<String> {
+    let mut request = format!("{target} HTTP/1.0\r\nHost: localhost\r\nContent-Length: 0\r\n");
+    if let Some(token) = token {
+        request.push_str(&format!("Authorization: Bearer {token}\r\n"));
+    }
+    request.push_str("\r\n");
+
+    let mut response = String::new();
+    if let Ok(tcp) = addr.parse::<SocketAddr>() {
+        let mut stream = TcpStream::connect_timeout(&tcp, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.write_all(request.as_bytes())?;
+        stream.read_to_string(&mut response)?;
+    } else {
+        let mut stream = UnixStream::connect(addr)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.write_all(request.as_bytes())?;
+        stream.read_to_string(&mut response)?;
+    }
+
+    // The body follows the blank line ending the headers
+    response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body.to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed HTTP response"))
+}
+
+fn metrics_reply(accept: Option<String>, mgr: NetworkManager) -> impl warp::Reply {
+    let format = Format::from_accept(accept.as_deref());
+    warp::reply::with_header(
+        mgr.render_metrics(format),
+        http::header::CONTENT_TYPE,
+        format.content_type(),
+    )
+}
+
+// 503 when any network or endpoint is missing its interface, so probes can use the status
+fn health_reply(mgr: NetworkManager) -> impl warp::Reply {
+    let report = mgr.health_report();
+    let status = if report.healthy {
+        http::StatusCode::OK
+    } else {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    reply_json_status(&report, status)
+}
+
+/// Serve only `/health` on a TCP address, for liveness probes that can't reach the admin API
+pub async fn serve_health(mgr: NetworkManager, addr: SocketAddr, rx: oneshot::Receiver<()>) {
+    let filter = warp::any().map(move || mgr.clone());
+    let health = warp::get()
+        .and(warp::path!("health"))
+        .and(filter)
+        .map(health_reply);
+
+    match warp::serve(health).try_bind_with_graceful_shutdown(addr, async {
+        rx.await.ok();
+    }) {
+        Ok((bound, server)) => {
+            info!("Health check listening on {}", bound);
+            server.await;
+        }
+        Err(e) => error!("Unable to bind health check address {}: {}", addr, e),
+    }
+}
+
+/// Serve only `/metrics` on a TCP address, for scrapers that can't reach the admin API
+pub async fn serve_metrics(mgr: NetworkManager, addr: SocketAddr, rx: oneshot::Receiver<()>) {
+    let filter = warp::any().map(move || mgr.clone());
+    let metrics = warp::get()
+        .and(warp::path!("metrics"))
+        .and(warp::header::optional::<String>("accept"))
+        .and(filter)
+        .map(metrics_reply);
+
+    match warp::serve(metrics).try_bind_with_graceful_shutdown(addr, async {
+        rx.await.ok();
+    }) {
+        Ok((bound, server)) => {
+            info!("Metrics listening on {}", bound);
+            server.await;
+        }
+        Err(e) => error!("Unable to bind metrics address {}: {}", addr, e),
+    }
+}
+
+pub async fn serve(mgr: NetworkManager, rx: oneshot::Receiver<()>) {
+    let addr = mgr.config().admin_addr.clone();
+    let authorized = authorized(mgr.config().admin_token.clone());
+    let filter = warp::any().map(move || mgr.clone());
+
+    let inspect_ifc = warp::get()
+        .and(warp::path!("interfaces" / String))
+        .and(filter.clone())
+        .and_then(api_inspect_interface);
+
+    let list_nws = warp::get()
+        .and(warp::path!("networks"))
+        .and(filter.clone())
+        .and_then(api_list_networks);
+
+    let describe_nw = warp::get()
+        .and(warp::path!("networks" / String))
+        .and(filter.clone())
+        .and_then(api_describe_network);
+
+    let rule_stats = warp::get()
+        .and(warp::path!("networks" / String / "stats"))
+        .and(filter.clone())
+        .and_then(api_rule_stats);
+
+    let diagnose_rules = warp::get()
+        .and(warp::path!("networks" / String / "rules"))
+        .and(filter.clone())
+        .and_then(api_diagnose_rules);
+
+    let create_nws = warp::post()
+        .and(warp::path!("networks"))
+        .and(authorized.clone())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(warp::body::json())
+        .and(filter.clone())
+        .and_then(api_create_networks);
+
+    let clone_nw = warp::post()
+        .and(warp::path!("networks" / String / "clone"))
+        .and(authorized.clone())
+        .and(warp::body::content_length_limit(1024 * 64))
+        .and(warp::body::json())
+        .and(filter.clone())
+        .and_then(api_clone_network);
+
+    let busoff = warp::post()
+        .and(warp::path!("networks" / String / "busoff"))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_trigger_busoff);
+
+    let recover = warp::delete()
+        .and(warp::path!("networks" / String / "busoff"))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_recover_busoff);
+
+    let detach_all = warp::post()
+        .and(warp::path!("networks" / String / "detach"))
+        .and(authorized.clone())
+        .and(warp::query::<HashMap<String, String>>())
+        .and(filter.clone())
+        .and_then(api_detach_all);
+
+    let reconcile = warp::post()
+        .and(warp::path!("networks" / String / "reconcile"))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_reconcile_rules);
+
+    let reconcile_all = warp::post()
+        .and(warp::path!("reconcile"))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_reconcile);
+
+    let maintenance_status = warp::get()
+        .and(warp::path!("maintenance"))
+        .and(filter.clone())
+        .and_then(api_maintenance_status);
+
+    let maintenance_on = warp::post()
+        .and(warp::path!("maintenance"))
+        .and(authorized.clone())
+        .map(|| true)
+        .and(filter.clone())
+        .and_then(api_set_maintenance);
+
+    let maintenance_off = warp::delete()
+        .and(warp::path!("maintenance"))
+        .and(authorized.clone())
+        .map(|| false)
+        .and(filter.clone())
+        .and_then(api_set_maintenance);
+
+    let status = warp::get()
+        .and(warp::path!("status"))
+        .and(filter.clone())
+        .map(|mgr: NetworkManager| reply_json(&serde_json::json!({ "Phase": mgr.phase() })));
+
+    let trace_status = warp::get()
+        .and(warp::path!("trace"))
+        .map(|| reply_json(&command::trace_status()));
+
+    let trace_start = warp::post()
+        .and(warp::path!("trace" / String))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_start_trace);
+
+    let trace_stop = warp::delete()
+        .and(warp::path!("trace"))
+        .and(authorized.clone())
+        .and_then(api_stop_trace);
+
+    let health = warp::get()
+        .and(warp::path!("health"))
+        .and(filter.clone())
+        .map(health_reply);
+
+    let resources = warp::get()
+        .and(warp::path!("resources"))
+        .and(filter.clone())
+        .map(|mgr: NetworkManager| reply_json(&mgr.resource_report()));
+
+    let release_warmup = warp::post()
+        .and(warp::path!("warmup" / "release"))
+        .and(authorized.clone())
+        .and(filter.clone())
+        .and_then(api_release_warmup);
+
+    let gateway = warp::get()
+        .and(warp::path!("cangw"))
+        .and(filter.clone())
+        .map(|mgr: NetworkManager| reply_json(mgr.gateway_params()));
+
+    let orphans = warp::get()
+        .and(warp::path!("orphans"))
+        .and(filter.clone())
+        .and_then(api_list_orphans);
+
+    let events = warp::get()
+        .and(warp::path!("events"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and(filter.clone())
+        .and_then(api_recent_events);
+
+    let metrics = warp::get()
+        .and(warp::path!("metrics"))
+        .and(warp::header::optional::<String>("accept"))
+        .and(filter.clone())
+        .map(metrics_reply);
+
+    let routes = inspect_ifc
+        .or(list_nws)
+        .or(describe_nw)
+        .or(rule_stats)
+        .or(diagnose_rules)
+        .or(create_nws)
+        .or(clone_nw)
+        .or(busoff)
+        .or(recover)
+        .or(detach_all)
+        .or(reconcile)
+        .or(reconcile_all)
+        .or(maintenance_status)
+        .or(maintenance_on)
+        .or(maintenance_off)
+        .or(status)
+        .or(trace_status)
+        .or(trace_start)
+        .or(trace_stop)
+        .or(health)
+        .or(resources)
+        .or(release_warmup)
+        .or(gateway)
+        .or(orphans)
+        .or(events)
+        .or(metrics)
+        .recover(handle_rejection);
+
+    // A TCP address, otherwise a UNIX socket path
+    if let Ok(tcp) = addr.parse::<SocketAddr>() {
+        match warp::serve(routes).try_bind_with_graceful_shutdown(tcp, async {
+            rx.await.ok();
+        }) {
+            Ok((bound, server)) => {
+                info!("Introspection API listening on {}", bound);
+                server.await;
+            }
+            Err(e) => error!("Unable to bind introspection address {}: {}", tcp, e),
+        }
+        return;
+    }
+
+    // Remove a stale socket left behind by an unclean exit
+    if let Some(parent) = std::path::Path::new(&addr).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = fs::remove_file(&addr);
+
+    let listener = match UnixListener::bind(&addr) {
+        Ok(l) => l,
+        Err(e) => {
+            error!("Unable to bind introspection socket {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Introspection API listening on {}", addr);
+
+    warp::serve(routes)
+        .serve_incoming_with_graceful_shutdown(UnixListenerStream::new(listener), async {
+            rx.await.ok();
+        })
+        .await;
+}
@@ -0,0 +1,341 @@
+/*
+ * Filename: link.rs
+ * Created Date: Friday, October 16th 2026, 9:12:40 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::error::RustyCanError;
+use crate::metrics::METRICS;
+use crate::netlink;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+const SYSFS_NET: &str = "/sys/class/net";
+
+/// Size of the kernel's interface name buffer, including the terminating NUL
+pub const IFNAMSIZ: usize = 16;
+
+// Interface flag bit for an administratively up link (see if.h)
+const IFF_UP: u32 = 0x1;
+
+// Most `ip link add` commands allowed to run at once; 0 means unlimited
+static CREATE_LIMIT: AtomicUsize = AtomicUsize::new(0);
+static CREATES_IN_USE: Mutex<usize> = Mutex::new(0);
+static CREATE_SLOT_FREED: Condvar = Condvar::new();
+
+/// Limit how many interfaces are created concurrently; 0 removes the limit
+pub fn set_create_limit(limit: usize) {
+    CREATE_LIMIT.store(limit, Ordering::Relaxed);
+    CREATE_SLOT_FREED.notify_all();
+}
+
+/// Held while an interface is being created; releases its slot on drop
+struct CreateSlot;
+
+impl CreateSlot {
+    fn acquire() -> Self {
+        let mut in_use = CREATES_IN_USE.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let limit = CREATE_LIMIT.load(Ordering::Relaxed);
+            if limit == 0 || *in_use < limit {
+                break;
+            }
+            in_use = CREATE_SLOT_FREED
+                .wait(in_use)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *in_use += 1;
+        METRICS.interface_creates_in_use.set(*in_use as u64);
+        CreateSlot
+    }
+}
+
+impl Drop for CreateSlot {
+    fn drop(&mut self) {
+        let mut in_use = CREATES_IN_USE.lock().unwrap_or_else(|e| e.into_inner());
+        *in_use -= 1;
+        METRICS.interface_creates_in_use.set(*in_use as u64);
+        CREATE_SLOT_FREED.notify_one();
+    }
+}
+
+/// Why an interface is being created, for logs and the creation metrics
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    // CreateNetwork/CreateEndpoint (or the admin API) asked for it
+    Request,
+    // Restoring a network from Docker or the state file after a restart
+    Recovery,
+    // Replacing an interface that went missing under a live network or endpoint
+    Rebuild,
+    // Pre-created for the vcan pool
+    Pool,
+}
+
+impl Origin {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Origin::Request => "request",
+            Origin::Recovery => "recovery",
+            Origin::Rebuild => "rebuild",
+            Origin::Pool => "pool",
+        }
+    }
+}
+
+/// Create a vcan interface (left down), queuing behind other creations when the
+/// concurrency limit is reached
+/// Successful creations are counted in `rustycan_interfaces_created_total` by origin
+pub fn create_vcan(name: &str, mtu: u32, origin: Origin) -> Result<(), RustyCanError> {
+    let _slot = CreateSlot::acquire();
+    netlink::create_vcan(name, mtu)?;
+    created(name, origin);
+    Ok(())
+}
+
+/// Create a vxcan pair (both ends left down), under the same limit as `create_vcan`
+pub fn create_vxcan_pair(
+    dev: &str,
+    peer: &str,
+    mtu: u32,
+    origin: Origin,
+) -> Result<(), RustyCanError> {
+    let _slot = CreateSlot::acquire();
+    netlink::create_vxcan_pair(dev, peer, mtu)?;
+    created(dev, origin);
+    Ok(())
+}
+
+fn created(name: &str, origin: Origin) {
+    info!(
+        "Created interface {} (origin: {})",
+        name,
+        origin.as_str()
+    );
+    METRICS.interfaces_created.inc(origin.as_str());
+}
+
+/// Check that the kernel would accept `name` for an interface (see dev_valid_name)
+/// `option` names where the name came from, so the error points at what to change
+pub fn validate_interface_name(name: &str, option: &str) -> Result<(), RustyCanError> {
+    let reason = if name.is_empty() {
+        String::from("it is empty")
+    } else if name.len() > IFNAMSIZ - 1 {
+        format!(
+            "it is {} bytes, over the {} byte limit",
+            name.len(),
+            IFNAMSIZ - 1
+        )
+    } else if name == "." || name == ".." {
+        String::from("it is reserved")
+    } else if let Some(c) = name
+        .chars()
+        .find(|c| *c == '/' || *c == ':' || c.is_whitespace())
+    {
+        format!("it contains {c:?}")
+    } else {
+        return Ok(());
+    };
+    Err(RustyCanError::InvalidInterfaceName {
+        name: name.to_string(),
+        option: option.to_string(),
+        reason,
+    })
+}
+
+/// Names of all interfaces in the host namespace, including those a dry run
+/// pretended to create
+pub fn interface_names() -> Result<Vec<String>, RustyCanError> {
+    let mut names: Vec<String> = interfaces::Interface::get_all()
+        .map(|ifcs| ifcs.iter().map(|i| i.name.clone()).collect())
+        .map_err(|e| RustyCanError::InterfaceQuery(e.to_string()))?;
+    names.extend(netlink::dry_run_links().unwrap_or_default());
+    Ok(names)
+}
+
+/// Check whether an interface exists in the host namespace
+/// An enumeration failure is an error, so callers can tell it apart from "absent"
+pub fn interface_exists(name: &str) -> Result<bool, RustyCanError> {
+    Ok(interface_names()?.iter().any(|i| i == name))
+}
+
+/// Check that an existing interface is of one of the `expected` link types, so a
+/// leftover interface of another type (e.g. `dummy`) that happens to have the name
+/// is refused rather than silently used
+pub fn check_kind(name: &str, expected: &[&str]) -> Result<(), RustyCanError> {
+    let kind = netlink::link_kind(name)?.unwrap_or_else(|| String::from("unknown"));
+    if expected.contains(&kind.as_str()) {
+        return Ok(());
+    }
+    Err(RustyCanError::InterfaceKind {
+        name: name.to_string(),
+        kind,
+        expected: expected.join(" or "),
+    })
+}
+
+/// Kernel view of a single network interface in the host namespace
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LinkStatus {
+    pub exists: bool,
+    pub up: bool,
+    pub ifindex: Option<u32>,
+    pub mtu: Option<u32>,
+}
+
+impl LinkStatus {
+    /// Query sysfs for the current state of the named interface
+    /// A missing interface is reported with exists=false rather than an error
+    pub fn query(name: &str) -> Self {
+        if name.is_empty() || name.contains('/') {
+            return LinkStatus::default();
+        }
+
+        let ifindex = read_attr(name, "ifindex").and_then(|v| v.parse::<u32>().ok());
+        if ifindex.is_none() {
+            return LinkStatus::default();
+        }
+
+        let flags = read_attr(name, "flags")
+            .and_then(|v| u32::from_str_radix(v.trim_start_matches("0x"), 16).ok())
+            .unwrap_or(0);
+
+        LinkStatus {
+            exists: true,
+            up: flags & IFF_UP != 0,
+            ifindex,
+            mtu: read_attr(name, "mtu").and_then(|v| v.parse::<u32>().ok()),
+        }
+    }
+}
+
+/// Kernel ifindex of an interface in the host namespace, None when it isn't there
+pub fn ifindex(name: &str) -> Option<u32> {
+    LinkStatus::query(name).ifindex
+}
+
+/// Whether the interface is backed by a physical device (a real CAN controller
+/// rather than a vcan/vxcan virtual interface)
+pub fn is_hardware(name: &str) -> bool {
+    !name.is_empty()
+        && !name.contains('/')
+        && std::path::Path::new(&format!("{SYSFS_NET}/{name}/device")).exists()
+}
+
+/// Bring a freshly created interface up, deleting it again if that fails so a
+/// half-configured interface isn't left behind
+pub fn bring_up_new(name: &str) -> Result<(), RustyCanError> {
+    netlink::set_link_up(name).map_err(|e| {
+        if let Err(del) = netlink::delete_link(name) {
+            error!(
+                "Unable to remove interface {} after failing to bring it up: {}",
+                name, del
+            );
+        }
+        RustyCanError::InterfaceUp {
+            name: name.to_string(),
+            reason: e.to_string(),
+        }
+    })
+}
+
+// MTU of a classic CAN interface, which drops CAN FD frames
+pub const CAN_MTU: u32 = 16;
+// MTU of an interface that carries CAN FD frames
+pub const CANFD_MTU: u32 = 72;
+
+/// MTU for the virtual interfaces of a network, by whether it carries CAN FD
+pub fn can_mtu(fd: bool) -> u32 {
+    if fd {
+        CANFD_MTU
+    } else {
+        CAN_MTU
+    }
+}
+
+/// Change the MTU of an existing CAN interface, leaving it down
+/// CAN devices refuse an MTU change while up, so the interface is taken down first
+pub fn set_can_mtu(name: &str, mtu: u32) -> Result<(), RustyCanError> {
+    netlink::set_link_down(name)?;
+    netlink::set_mtu(name, mtu)
+}
+
+/// Nominal bitrates accepted for real CAN interfaces, in bit/s
+pub const STANDARD_BITRATES: &[u32] = &[
+    10_000, 20_000, 50_000, 100_000, 125_000, 250_000, 500_000, 800_000, 1_000_000,
+];
+
+/// CAN FD data-phase bitrates accepted for real CAN interfaces, in bit/s
+pub const STANDARD_DBITRATES: &[u32] = &[1_000_000, 2_000_000, 4_000_000, 5_000_000, 8_000_000];
+
+/// Set the bitrate of a real CAN interface, switching it to CAN FD when a data
+/// bitrate is given; the link is taken down to do so and brought back up afterwards
+pub fn set_can_bitrate(
+    name: &str,
+    bitrate: u32,
+    dbitrate: Option<u32>,
+) -> Result<(), RustyCanError> {
+    let bitrate = bitrate.to_string();
+    let dbitrate = dbitrate.map(|d| d.to_string());
+    let mut args = vec![
+        "link", "set", "dev", name, "type", "can", "bitrate", &bitrate,
+    ];
+    if let Some(d) = &dbitrate {
+        args.extend_from_slice(&["dbitrate", d, "fd", "on"]);
+    }
+
+    ip(&["link", "set", "dev", name, "down"]).map_err(RustyCanError::LinkCommand)?;
+    let result = ip(&args);
+    // Bring the link back up even if the bitrate was refused
+    ip(&["link", "set", "dev", name, "up"]).map_err(RustyCanError::LinkCommand)?;
+    result.map_err(RustyCanError::LinkCommand)
+}
+
+/// Switch the controller loopback mode (CAN_CTRLMODE_LOOPBACK) of a real CAN interface
+/// The mode can only change while the link is down, so it is cycled like a bitrate change
+pub fn set_can_loopback(name: &str, on: bool) -> Result<(), RustyCanError> {
+    let mode = if on { "on" } else { "off" };
+    ip(&["link", "set", "dev", name, "down"]).map_err(RustyCanError::LinkCommand)?;
+    let result = ip(&["link", "set", "dev", name, "type", "can", "loopback", mode]);
+    ip(&["link", "set", "dev", name, "up"]).map_err(RustyCanError::LinkCommand)?;
+    result.map_err(RustyCanError::LinkCommand)
+}
+
+/// Run `ip` with the given arguments, returning its stderr on failure
+pub fn ip(args: &[&str]) -> Result<(), String> {
+    match command::output("ip", args) {
+        Ok(result) if result.status.success() => Ok(()),
+        Ok(result) => Err(String::from_utf8_lossy(&result.stderr).trim().to_string()),
+        Err(e) => Err(format!("failed to execute ip: {e}")),
+    }
+}
+
+fn read_attr(name: &str, attr: &str) -> Option<String> {
+    fs::read_to_string(format!("{SYSFS_NET}/{name}/{attr}"))
+        .ok()
+        .map(|v| v.trim().to_string())
+}
@@ -0,0 +1,96 @@
+/*
+ * Filename: logging.rs
+ * Created Date: Saturday, October 17th 2026, 3:41:06 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::config::LogFormat;
+use log::kv::{Error, Key, Value, VisitSource};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value as Json};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes info and debug records to stdout, warnings and errors to stderr
+struct Logger {
+    format: LogFormat,
+}
+
+/// Install the process-wide logger; must run before anything logs
+pub fn init(format: LogFormat) {
+    if log::set_logger(Box::leak(Box::new(Logger { format }))).is_ok() {
+        log::set_max_level(LevelFilter::Debug);
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Debug
+    }
+
+    fn log(&self, record: &Record) {
+        let line = match self.format {
+            LogFormat::Text => {
+                let prefix = match record.level() {
+                    Level::Error | Level::Warn => "!!",
+                    Level::Info => "->",
+                    Level::Debug | Level::Trace => "..",
+                };
+                format!(" {} {}", prefix, record.args())
+            }
+            LogFormat::Json => json_line(record),
+        };
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{}", line),
+            _ => println!("{}", line),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Key-values attached to a record, e.g. `network_id`, become fields of the object
+fn json_line(record: &Record) -> String {
+    let mut fields = Map::new();
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+    fields.insert(String::from("ts"), Json::from(ts));
+    fields.insert(
+        String::from("level"),
+        Json::from(record.level().as_str().to_ascii_lowercase()),
+    );
+    fields.insert(String::from("msg"), Json::from(record.args().to_string()));
+    let _ = record.key_values().visit(&mut Fields(&mut fields));
+    Json::Object(fields).to_string()
+}
+
+struct Fields<'a>(&'a mut Map<String, Json>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        self.0
+            .insert(key.as_str().to_string(), Json::from(value.to_string()));
+        Ok(())
+    }
+}
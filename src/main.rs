@@ -24,8 +24,11 @@
  * SOFTWARE.
  */
 
-use crate::manager::NetworkManager;
+use crate::error::RustyCanError;
+use crate::manager::{NetworkManager, StartupPhase};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ip_based_plugin")]
 use std::fs;
 use std::vec::Vec;
 use tokio::net::UnixListener;
@@ -34,15 +37,34 @@ use tokio::sync::oneshot;
 use tokio_stream::wrappers::UnixListenerStream;
 use warp::{http, Filter};
 
+pub mod cangw;
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod declare;
 pub mod endpoint;
+pub mod error;
+pub mod events;
+pub mod expiry;
+pub mod introspect;
+pub mod link;
+pub mod logging;
 pub mod manager;
+pub mod metrics;
+pub mod netem;
+pub mod netlink;
 pub mod network;
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
-struct ErrorResponse {
-    name: String,
-    msg: String,
-}
+pub mod pool;
+pub mod preflight;
+pub mod probe;
+pub mod rulewatch;
+pub mod selfheal;
+pub mod state;
+pub mod verify;
+pub mod warmup;
+
+// Media type of the plugin API handshake, per the Docker plugin protocol
+const PLUGIN_CONTENT_TYPE: &str = "application/vnd.docker.plugins.v1.2+json";
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -61,10 +83,18 @@ struct SetCapabilityResponse {
 #[derive(Debug, Deserialize, Serialize, Clone)]
 struct JoinResponse {
     InterfaceName: network::JoinResponse,
+    // A CAN interface carries no IP traffic: no gateway, no routes, and no
+    // docker_gwbridge connection for external access
+    Gateway: String,
+    GatewayIPv6: String,
+    StaticRoutes: Vec<serde_json::Value>,
+    DisableGatewayService: bool,
 }
 
 async fn api_plugin_activate(payload: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
     log_body(&payload);
+    // Only the network driver API is served; advertising IpamDriver would make
+    // Docker route address requests here
     let rsp = HandshakeResponse {
         Implements: vec![String::from("NetworkDriver")],
     };
@@ -77,15 +107,23 @@ async fn api_plugin_activate(payload: bytes::Bytes) -> Result<impl warp::Reply,
             String::from(r#"{"Err":"Serializing response to Plugin.Activate"}"#)
         }
     };
-    println!("Plugin.Activate: {}", jrsp);
-    Ok(warp::reply::with_status(jrsp, status))
+    info!("Plugin.Activate: {}", jrsp);
+    Ok(warp::reply::with_header(
+        warp::reply::with_status(jrsp, status),
+        http::header::CONTENT_TYPE,
+        PLUGIN_CONTENT_TYPE,
+    ))
 }
 
-async fn api_get_capabilities(payload: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+async fn api_get_capabilities(
+    payload: bytes::Bytes,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
     log_body(&payload);
+    let scope = mgr.config().scope.as_str();
     let rsp = SetCapabilityResponse {
-        Scope: String::from("local"),
-        ConnectivityScope: String::from("local"),
+        Scope: String::from(scope),
+        ConnectivityScope: String::from(scope),
     };
 
     let mut status: http::StatusCode = http::StatusCode::OK;
@@ -97,7 +135,7 @@ async fn api_get_capabilities(payload: bytes::Bytes) -> Result<impl warp::Reply,
         }
     };
 
-    println!("NetworkDriver.GetCapabilities: {}", jrsp);
+    info!("NetworkDriver.GetCapabilities: {}", jrsp);
     Ok(warp::reply::with_status(jrsp, status))
 }
 
@@ -114,7 +152,7 @@ async fn api_network_create(
             let uid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
@@ -124,17 +162,19 @@ async fn api_network_create(
                 None => v["Options"]["com.docker.network.generic"].to_string(),
             };
             if !error {
-                mgr.network_create(uid, opt);
-                "{}"
+                match mgr.check_ip_data(&v).and_then(|()| mgr.network_create(uid, opt)) {
+                    Ok(()) => String::from("{}"),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
-                r#"{"Err":"Invalid network ID"}"#
+                String::from(r#"{"Err":"Invalid network ID"}"#)
             }
         }
-        Err(_) => r#"{"Err":"Unable to parse JSON payload"}"#,
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.CreateNetwork: {}", reply);
+    info!("NetworkDriver.CreateNetwork: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
@@ -151,23 +191,92 @@ async fn api_network_delete(
             let uid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
+                    error = true;
+                    String::new()
+                }
+            };
+            if !error {
+                match mgr.network_delete(uid) {
+                    Ok(()) => String::from("{}"),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
+            } else {
+                status = http::StatusCode::BAD_REQUEST;
+                String::from(r#"{"Err":"Invalid network ID"}"#)
+            }
+        }
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
+    };
+
+    info!("NetworkDriver.DeleteNetwork: {}", reply);
+    Ok(warp::reply::with_status(reply, status))
+}
+
+// Global scope only: called once on a swarm manager before nodes create the network
+async fn api_network_allocate(
+    payload: bytes::Bytes,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log_body(&payload);
+
+    let mut status: http::StatusCode = http::StatusCode::OK;
+    let reply = match serde_json::from_slice::<serde_json::Value>(&payload) {
+        Ok(v) => {
+            let mut error = false;
+            let uid = match v["NetworkID"].as_str() {
+                Some(u) => u.to_string(),
+                None => {
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
             };
+            // Driver options, possibly still wrapped as generic options
+            let options = match &v["Options"]["com.docker.network.generic"] {
+                serde_json::Value::Object(o) => o.clone(),
+                _ => v["Options"].as_object().cloned().unwrap_or_default(),
+            };
             if !error {
-                mgr.network_delete(uid);
-                "{}"
+                match mgr.network_allocate(uid, options) {
+                    Ok(options) => serde_json::json!({ "Options": options }).to_string(),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
-                r#"{"Err":"Invalid network ID"}"#
+                String::from(r#"{"Err":"Invalid network ID"}"#)
             }
         }
-        Err(_) => r#"{"Err":"Unable to parse JSON payload"}"#,
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.DeleteNetwork: {}", reply);
+    info!("NetworkDriver.AllocateNetwork: {}", reply);
+    Ok(warp::reply::with_status(reply, status))
+}
+
+async fn api_network_free(
+    payload: bytes::Bytes,
+    mgr: NetworkManager,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log_body(&payload);
+
+    let mut status: http::StatusCode = http::StatusCode::OK;
+    let reply = match serde_json::from_slice::<serde_json::Value>(&payload) {
+        Ok(v) => match v["NetworkID"].as_str() {
+            Some(uid) => match mgr.network_free(uid) {
+                Ok(()) => String::from("{}"),
+                Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+            },
+            None => {
+                error!("Error parsing network ID: {}", v["NetworkID"]);
+                status = http::StatusCode::BAD_REQUEST;
+                String::from(r#"{"Err":"Invalid network ID"}"#)
+            }
+        },
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
+    };
+
+    info!("NetworkDriver.FreeNetwork: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
@@ -184,7 +293,7 @@ async fn api_endpoint_create(
             let nuid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
@@ -192,23 +301,25 @@ async fn api_endpoint_create(
             let epuid = match v["EndpointID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing endpoint ID: {}", v["EndpointID"]);
+                    error!("Error parsing endpoint ID: {}", v["EndpointID"]);
                     error = true;
                     String::new()
                 }
             };
             if !error {
-                mgr.endpoint_create(nuid, epuid);
-                "{}"
+                match mgr.endpoint_create(nuid, epuid, v["Options"].clone()) {
+                    Ok(()) => String::from("{}"),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
-                r#"{"Err":"Invalid network ID or endpoint ID"}"#
+                String::from(r#"{"Err":"Invalid network ID or endpoint ID"}"#)
             }
         }
-        Err(_) => r#"{"Err":"Unable to parse JSON payload"}"#,
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.CreateEndpoint: {}", reply);
+    info!("NetworkDriver.CreateEndpoint: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
@@ -225,7 +336,7 @@ async fn api_endpoint_delete(
             let nuid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
@@ -233,34 +344,51 @@ async fn api_endpoint_delete(
             let epuid = match v["EndpointID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing endpoint ID: {}", v["EndpointID"]);
+                    error!("Error parsing endpoint ID: {}", v["EndpointID"]);
                     error = true;
                     String::new()
                 }
             };
             if !error {
-                mgr.endpoint_delete(nuid, epuid);
-                "{}"
+                match mgr.endpoint_delete(nuid, epuid) {
+                    Ok(()) => String::from("{}"),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
-                r#"{"Err":"Invalid network ID or endpoint ID"}"#
+                String::from(r#"{"Err":"Invalid network ID or endpoint ID"}"#)
             }
         }
-        Err(_) => r#"{"Err":"Unable to parse JSON payload"}"#,
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.DeleteEndpoint: {}", reply);
+    info!("NetworkDriver.DeleteEndpoint: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
 async fn api_endpoint_info(
     payload: bytes::Bytes,
-    _mgr: NetworkManager,
+    mgr: NetworkManager,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log_body(&payload);
 
-    // ! TODO Add error handling
-    Ok(warp::reply::with_status("{}", http::StatusCode::OK))
+    let mut status: http::StatusCode = http::StatusCode::OK;
+    let reply = match serde_json::from_slice::<serde_json::Value>(&payload) {
+        Ok(v) => match (v["NetworkID"].as_str(), v["EndpointID"].as_str()) {
+            (Some(nuid), Some(epuid)) => match mgr.endpoint_oper_info(nuid, epuid) {
+                Some(info) => serde_json::json!({ "Value": info }).to_string(),
+                None => String::from(r#"{"Value":{}}"#),
+            },
+            _ => {
+                status = http::StatusCode::BAD_REQUEST;
+                String::from(r#"{"Err":"Invalid network ID or endpoint ID"}"#)
+            }
+        },
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
+    };
+
+    info!("NetworkDriver.EndpointOperInfo: {}", reply);
+    Ok(warp::reply::with_status(reply, status))
 }
 
 async fn api_network_join(
@@ -276,7 +404,7 @@ async fn api_network_join(
             let nuid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
@@ -284,7 +412,7 @@ async fn api_network_join(
             let epuid = match v["EndpointID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing endpoint ID: {}", v["EndpointID"]);
+                    error!("Error parsing endpoint ID: {}", v["EndpointID"]);
                     error = true;
                     String::new()
                 }
@@ -292,7 +420,7 @@ async fn api_network_join(
             let sbox = match v["SandboxKey"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing sandbox key: {}", v["SandboxKey"]);
+                    error!("Error parsing sandbox key: {}", v["SandboxKey"]);
                     error = true;
                     String::new()
                 }
@@ -302,10 +430,25 @@ async fn api_network_join(
                 None => v["Options"].to_string(),
             };
             if !error {
-                match mgr.endpoint_attach(nuid, epuid, sbox, opt) {
+                match attach_with_timeout(&mgr, &nuid, &epuid, sbox, opt).await {
                     Ok(joinrsp) => {
+                        // Docker moves the peer into the container after we reply
+                        if let Some(delay) = mgr.config().peer_check_delay {
+                            let mgr = mgr.clone();
+                            tokio::spawn(async move {
+                                tokio::time::sleep(delay).await;
+                                let _ = tokio::task::spawn_blocking(move || {
+                                    mgr.verify_peer_placement(&nuid, &epuid)
+                                })
+                                .await;
+                            });
+                        }
                         let rsp = JoinResponse {
                             InterfaceName: joinrsp,
+                            Gateway: String::new(),
+                            GatewayIPv6: String::new(),
+                            StaticRoutes: Vec::new(),
+                            DisableGatewayService: true,
                         };
                         match serde_json::to_string(&rsp) {
                             Ok(jrsp) => jrsp,
@@ -314,7 +457,7 @@ async fn api_network_join(
                             ),
                         }
                     }
-                    Err(_) => String::from(r#"{"Err":"Error attaching endpoint to network"}"#),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
                 }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
@@ -324,10 +467,45 @@ async fn api_network_join(
         Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.Join: {}", reply);
+    info!("NetworkDriver.Join: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
+// A Join stuck on the kernel answers Docker with an error once the attach timeout
+// passes; the attach itself rolls back if it completes after that
+async fn attach_with_timeout(
+    mgr: &NetworkManager,
+    nuid: &str,
+    epuid: &str,
+    sbox: String,
+    opt: String,
+) -> Result<network::JoinResponse, RustyCanError> {
+    let task = {
+        let (mgr, nuid, epuid) = (mgr.clone(), nuid.to_string(), epuid.to_string());
+        tokio::task::spawn_blocking(move || mgr.endpoint_attach(nuid, epuid, sbox, opt))
+    };
+    let failed = || RustyCanError::AttachFailed {
+        epuid: epuid.to_string(),
+        source: None,
+    };
+    match mgr.config().attach_timeout {
+        Some(limit) => match tokio::time::timeout(limit, task).await {
+            Ok(joined) => joined.unwrap_or_else(|_| Err(failed())),
+            Err(_) => {
+                error!(
+                    network_id = nuid, endpoint_id = epuid;
+                    "Join of endpoint {} timed out after {}ms", epuid, limit.as_millis()
+                );
+                Err(RustyCanError::AttachTimeout {
+                    epuid: epuid.to_string(),
+                    after: limit,
+                })
+            }
+        },
+        None => task.await.unwrap_or_else(|_| Err(failed())),
+    }
+}
+
 async fn api_network_leave(
     payload: bytes::Bytes,
     mgr: NetworkManager,
@@ -341,7 +519,7 @@ async fn api_network_leave(
             let nuid = match v["NetworkID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing network ID: {}", v["NetworkID"]);
+                    error!("Error parsing network ID: {}", v["NetworkID"]);
                     error = true;
                     String::new()
                 }
@@ -349,34 +527,61 @@ async fn api_network_leave(
             let epuid = match v["EndpointID"].as_str() {
                 Some(u) => u.to_string(),
                 None => {
-                    println!("Error parsing endpoint ID: {}", v["EndpointID"]);
+                    error!("Error parsing endpoint ID: {}", v["EndpointID"]);
                     error = true;
                     String::new()
                 }
             };
             if !error {
-                mgr.endpoint_detach(nuid, epuid);
-                "{}"
+                match mgr.endpoint_detach(nuid, epuid) {
+                    Ok(()) => String::from("{}"),
+                    Err(e) => serde_json::json!({ "Err": e.to_string() }).to_string(),
+                }
             } else {
                 status = http::StatusCode::BAD_REQUEST;
-                r#"{"Err":"Invalid network ID or endpoint ID"}"#
+                String::from(r#"{"Err":"Invalid network ID or endpoint ID"}"#)
             }
         }
-        Err(_) => r#"{"Err":"Unable to parse JSON payload"}"#,
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
     };
 
-    println!("NetworkDriver.Leave: {}", reply);
+    info!("NetworkDriver.Leave: {}", reply);
     Ok(warp::reply::with_status(reply, status))
 }
 
+// CAN networks have no external connectivity, so there is nothing to program or revoke
+async fn api_program_external(payload: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+    log_body(&payload);
+    Ok(warp::reply::with_status("{}", http::StatusCode::OK))
+}
+
+async fn api_revoke_external(payload: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+    log_body(&payload);
+    Ok(warp::reply::with_status("{}", http::StatusCode::OK))
+}
+
+// Discovery events (e.g. a node joining the cluster) need no action: CAN networks are
+// local to each host, so they are only logged
+fn discovery_reply(call: &str, payload: &bytes::Bytes) -> String {
+    match serde_json::from_slice::<serde_json::Value>(payload) {
+        Ok(v) => {
+            debug!(
+                "NetworkDriver.{}: type {} data {}",
+                call, v["DiscoveryType"], v["DiscoveryData"]
+            );
+            String::from("{}")
+        }
+        Err(_) => String::from(r#"{"Err":"Unable to parse JSON payload"}"#),
+    }
+}
+
 async fn api_discover_new(
     payload: bytes::Bytes,
     _mgr: NetworkManager,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log_body(&payload);
-
-    // ! TODO Add error handling
-    Ok(warp::reply::with_status("{}", http::StatusCode::OK))
+    let reply = discovery_reply("DiscoverNew", &payload);
+    Ok(warp::reply::with_status(reply, http::StatusCode::OK))
 }
 
 async fn api_discover_delete(
@@ -384,9 +589,24 @@ async fn api_discover_delete(
     _mgr: NetworkManager,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log_body(&payload);
+    let reply = discovery_reply("DiscoverDelete", &payload);
+    Ok(warp::reply::with_status(reply, http::StatusCode::OK))
+}
 
-    // ! TODO Add error handling
-    Ok(warp::reply::with_status("{}", http::StatusCode::OK))
+/// Stop taking changes and let in-flight operations finish, then tear down if configured
+async fn quiesce(mgr: &NetworkManager) {
+    mgr.set_maintenance(true);
+    let timeout = mgr.config().shutdown_timeout;
+    if !mgr.drain(timeout).await {
+        warn!(
+            "{} operations still in flight after {}s, shutting down anyway",
+            mgr.in_flight(),
+            timeout.as_secs()
+        );
+    }
+    if mgr.config().shutdown_teardown {
+        mgr.shutdown();
+    }
 }
 
 async fn shutdown_signal() {
@@ -409,10 +629,10 @@ async fn shutdown_signal() {
 
     tokio::select! {
         _ = ctrl_c => {
-            println!("Received Ctrl+C signal, shutting down gracefully...");
+            info!("Received Ctrl+C signal, shutting down gracefully...");
         },
         _ = terminate => {
-            println!("Received SIGTERM signal, shutting down gracefully...");
+            info!("Received SIGTERM signal, shutting down gracefully...");
         },
     }
 }
@@ -428,22 +648,162 @@ fn process_body() -> impl Filter<Extract = (bytes::Bytes,), Error = warp::Reject
     warp::body::content_length_limit(1024 * 16).and(warp::body::bytes())
 }
 
+/// The handshake Docker sends before any driver call, which needs no manager
+fn plugin_activate() -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone
+{
+    warp::post()
+        .and(warp::path("Plugin.Activate"))
+        .and(warp::path::end())
+        .and(process_body())
+        .and_then(api_plugin_activate)
+}
+
 #[tokio::main]
 async fn main() {
+    let log_format = config::LogFormat::from_env();
+    logging::init(log_format.clone().unwrap_or_default());
+    if let Err(v) = log_format {
+        warn!("Ignoring invalid value '{}' for RUSTYCAN_LOG_FORMAT", v);
+    }
+
+    // `rustycan4docker status` queries a running plugin instead of starting one
+    if std::env::args().nth(1).as_deref() == Some("status") {
+        let addr = config::PluginConfig::from_env().admin_addr;
+        std::process::exit(introspect::status_command(&addr));
+    }
+
+    // `rustycan4docker reconcile` asks a running plugin to repair drift from the kernel
+    if std::env::args().nth(1).as_deref() == Some("reconcile") {
+        let config = config::PluginConfig::from_env();
+        let token = config.admin_token.as_deref();
+        std::process::exit(introspect::reconcile_command(&config.admin_addr, token));
+    }
+
+    // `rustycan4docker trace-diff <expected> <actual>` compares two recorded traces
+    if std::env::args().nth(1).as_deref() == Some("trace-diff") {
+        let args: Vec<String> = std::env::args().skip(2).collect();
+        if args.len() != 2 {
+            eprintln!("usage: rustycan4docker trace-diff <expected.jsonl> <actual.jsonl>");
+            std::process::exit(2);
+        }
+        std::process::exit(command::trace_diff_command(&args[0], &args[1]));
+    }
+
+    // `rustycan4docker verify` checks persisted networks against the kernel without
+    // changing anything, for pre-flight validation of a canary deployment
+    if std::env::args().nth(1).as_deref() == Some("verify") {
+        std::process::exit(verify::verify_command(&config::PluginConfig::from_env()));
+    }
+
+    // Fail fast when the CAN modules are missing rather than on the first network
+    // A dry run changes nothing on the host, modprobe included, so needs no modules
+    let missing = if config::PluginConfig::from_env().dry_run {
+        warn!("Dry-run mode: skipping the kernel module check");
+        Vec::new()
+    } else {
+        preflight::missing_modules()
+    };
+    if !missing.is_empty() {
+        preflight::report_missing(&missing);
+        std::process::exit(preflight::EXIT_MISSING_MODULES);
+    }
+
     let mgr = NetworkManager::new();
+
+    // Serve the admin API from the start so the startup phase can be queried
+    let (itx, irx) = oneshot::channel::<()>();
+    let introspect_task = tokio::spawn(introspect::serve(mgr.clone(), irx));
+
+    let (mtx, mrx) = oneshot::channel::<()>();
+    if let Some(addr) = mgr.config().metrics_addr {
+        tokio::spawn(introspect::serve_metrics(mgr.clone(), addr, mrx));
+    }
+
+    let (ltx, lrx) = oneshot::channel::<()>();
+    if let Some(addr) = mgr.config().health_addr {
+        tokio::spawn(introspect::serve_health(mgr.clone(), addr, lrx));
+    }
+
     mgr.network_load().await;
+    mgr.set_phase(StartupPhase::RecoveringInterfaces);
+
+    if let Some(path) = mgr.config().declared_networks.clone() {
+        match declare::DeclaredNetworks::load(&path) {
+            Ok(declared) => {
+                let report = declare::apply(&mgr, &declared, mgr.config().declared_prune);
+                info!(
+                    "Applied {}: {} created, {} drifted, {} pruned, {} failed",
+                    path,
+                    report.created.len(),
+                    report.drifted.len(),
+                    report.pruned.len(),
+                    report.failed.len()
+                );
+            }
+            Err(e) => error!("Unable to load declared networks: {}", e),
+        }
+    }
+
+    // Rebuild endpoints from the kernel first, picking up their interfaces and rules
+    match mgr.reconstruct_from_kernel() {
+        Ok(report) => info!(
+            "Reconstructed {} endpoints and {} rules from kernel state ({} unmatched buses)",
+            report.endpoints.len(),
+            report.rules,
+            report.unmatched_buses.len()
+        ),
+        Err(e) => error!("Unable to reconstruct endpoints from kernel state: {}", e),
+    }
+
+    // Then bring back the saved endpoints the kernel had nothing left of
+    let restored = mgr.restore_endpoints();
+    if restored > 0 {
+        info!("Restored {} saved endpoints", restored);
+    }
+
+    let (wtx, wrx) = oneshot::channel::<()>();
+    if let Some(interval) = mgr.config().rule_watch_interval {
+        let strict = mgr.config().rule_watch_strict;
+        tokio::spawn(rulewatch::run(mgr.clone(), interval, strict, wrx));
+    }
+
+    let (htx, hrx) = oneshot::channel::<()>();
+    if let Some(interval) = mgr.config().self_heal_interval {
+        tokio::spawn(selfheal::run(mgr.clone(), interval, hrx));
+    }
+
+    let (xtx, xrx) = oneshot::channel::<()>();
+    let interval = mgr.config().ttl_sweep_interval;
+    tokio::spawn(expiry::run(mgr.clone(), interval, xrx));
+
+    // The admin API is already up, so an operator can inspect or release a closed gate
+    mgr.set_phase(StartupPhase::Validating);
+    tokio::select! {
+        _ = warmup::gate(&mgr) => {}
+        _ = shutdown_signal() => {
+            let _ = wtx.send(());
+            let _ = htx.send(());
+            let _ = xtx.send(());
+            let _ = mtx.send(());
+            let _ = ltx.send(());
+            let _ = itx.send(());
+            let _ = introspect_task.await;
+            info!("Server shutdown complete");
+            return;
+        }
+    }
+    mgr.set_phase(StartupPhase::Ready);
+
+    let shutdown_mgr = mgr.clone();
     let filter = warp::any().map(move || mgr.clone());
 
-    let payload = warp::post()
-        .and(warp::path("Plugin.Activate"))
-        .and(warp::path::end())
-        .and(process_body())
-        .and_then(api_plugin_activate);
+    let payload = plugin_activate();
 
     let get_cap = warp::post()
         .and(warp::path("NetworkDriver.GetCapabilities"))
         .and(warp::path::end())
         .and(process_body())
+        .and(filter.clone())
         .and_then(api_get_capabilities);
 
     let nw_create = warp::post()
@@ -460,6 +820,20 @@ async fn main() {
         .and(filter.clone())
         .and_then(api_network_delete);
 
+    let nw_alloc = warp::post()
+        .and(warp::path("NetworkDriver.AllocateNetwork"))
+        .and(warp::path::end())
+        .and(process_body())
+        .and(filter.clone())
+        .and_then(api_network_allocate);
+
+    let nw_free = warp::post()
+        .and(warp::path("NetworkDriver.FreeNetwork"))
+        .and(warp::path::end())
+        .and(process_body())
+        .and(filter.clone())
+        .and_then(api_network_free);
+
     let endp_create = warp::post()
         .and(warp::path("NetworkDriver.CreateEndpoint"))
         .and(warp::path::end())
@@ -495,6 +869,18 @@ async fn main() {
         .and(filter.clone())
         .and_then(api_network_leave);
 
+    let ext_program = warp::post()
+        .and(warp::path("NetworkDriver.ProgramExternalConnectivity"))
+        .and(warp::path::end())
+        .and(process_body())
+        .and_then(api_program_external);
+
+    let ext_revoke = warp::post()
+        .and(warp::path("NetworkDriver.RevokeExternalConnectivity"))
+        .and(warp::path::end())
+        .and(process_body())
+        .and_then(api_revoke_external);
+
     let dsc_new = warp::post()
         .and(warp::path("NetworkDriver.DiscoverNew"))
         .and(warp::path::end())
@@ -513,11 +899,15 @@ async fn main() {
         .or(get_cap)
         .or(nw_create)
         .or(nw_del)
+        .or(nw_alloc)
+        .or(nw_free)
         .or(endp_create)
         .or(endp_del)
         .or(endp_info)
         .or(nw_join)
         .or(nw_leave)
+        .or(ext_program)
+        .or(ext_revoke)
         .or(dsc_new)
         .or(dsc_del);
     let (tx, rx) = oneshot::channel::<()>();
@@ -534,6 +924,7 @@ async fn main() {
         let server_task = tokio::spawn(server);
 
         shutdown_signal().await;
+        quiesce(&shutdown_mgr).await;
         let _ = tx.send(());
         server_task.await.unwrap();
     }
@@ -544,7 +935,7 @@ async fn main() {
     \"Name\": \"rustyvxcan\",
     \"Addr\": \"http://127.0.0.1:7373\"
 }";
-        fs::create_dir_all("/etc/docker/plugins");
+        let _ = fs::create_dir_all("/etc/docker/plugins");
         fs::write("/etc/docker/plugins/rustyvxcan.json", content)
             .expect("Unable to write docker plugin file");
         let (_addr, server) =
@@ -555,9 +946,48 @@ async fn main() {
         let server_task = tokio::spawn(server);
 
         shutdown_signal().await;
+        quiesce(&shutdown_mgr).await;
         let _ = tx.send(());
         server_task.await.unwrap();
     }
 
-    println!("Server shutdown complete");
+    let _ = wtx.send(());
+    let _ = htx.send(());
+    let _ = xtx.send(());
+    let _ = mtx.send(());
+    let _ = ltx.send(());
+    let _ = itx.send(());
+    let _ = introspect_task.await;
+
+    info!("Server shutdown complete");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn activate_advertises_only_the_network_driver() {
+        let rsp = warp::test::request()
+            .method("POST")
+            .path("/Plugin.Activate")
+            .body("{}")
+            .reply(&plugin_activate())
+            .await;
+        assert_eq!(rsp.status(), http::StatusCode::OK);
+        assert_eq!(rsp.headers()[http::header::CONTENT_TYPE], PLUGIN_CONTENT_TYPE);
+
+        let body: serde_json::Value = serde_json::from_slice(rsp.body()).unwrap();
+        assert_eq!(body, serde_json::json!({ "Implements": ["NetworkDriver"] }));
+    }
+
+    #[tokio::test]
+    async fn activate_only_answers_post() {
+        let rsp = warp::test::request()
+            .method("GET")
+            .path("/Plugin.Activate")
+            .reply(&plugin_activate())
+            .await;
+        assert_eq!(rsp.status(), http::StatusCode::METHOD_NOT_ALLOWED);
+    }
 }
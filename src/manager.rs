@@ -24,76 +24,514 @@
  * SOFTWARE.
  */
 
-use crate::endpoint::Endpoint;
-use crate::network::{JoinResponse, Network};
+use crate::cangw::{self, KernelRule, ModuleParams, RuleStats};
+use crate::clock::{Clock, SystemClock};
+use crate::command;
+use crate::config::{DuplicateEndpointPolicy, MissingNetworkPolicy, PluginConfig};
+use crate::endpoint::{Endpoint, DEFAULT_PREFIX};
+use crate::error::RustyCanError;
+use crate::events::{EventLog, LifecycleEvent, TimedEvent};
+use crate::link::{self, LinkStatus, Origin};
+use crate::metrics::{Format, StateGauge, METRICS};
+use crate::netlink;
+use crate::pool::InterfacePool;
+use crate::probe;
+use crate::rulewatch::diff_rules;
+use crate::network::{
+    EndpointSummary, InterfaceInfo, InterfaceRole, JoinResponse, Network, NetworkDescription,
+    NetworkSummary, RuleDiagnosis,
+};
 use bollard::network::ListNetworksOptions;
 use bollard::Docker;
-use parking_lot::{RwLock, Mutex};
+use log::{error, info, warn};
+use parking_lot::{Condvar, RwLock, RwLockWriteGuard, Mutex};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fmt::Error;
-use std::fs;
+#[cfg(debug_assertions)]
+use std::cell::Cell;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
+use crate::state::{NetworkConfig, NetworkMode, PersistBatch, SavedEndpoint, StateStore};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-// Persisted network configuration
-#[derive(Serialize, Deserialize, Clone, Debug)]
-struct NetworkConfig {
-    device: String,
-    peer: String,
-    canid: String,
+/// A single network to create as part of a `create_networks` batch
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CreateNetworkRequest {
+    pub nuid: String,
+    // Same shape as the com.docker.network.generic options, e.g. {"vxcan.id": "1"}
+    pub options: serde_json::Value,
 }
 
-const NETWORK_STATE_FILE: &str = "/var/lib/docker/network/files/rustycan4docker-networks.json";
+/// Copy of an existing network under a new id; see `NetworkManager::clone_network`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CloneNetworkRequest {
+    pub nuid: String,
+    // Options replacing the source network's, e.g. {"vxcan.id": "2"}
+    #[serde(default)]
+    pub overrides: serde_json::Value,
+}
+
+/// Why an interface is considered orphaned
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrphanReason {
+    // Endpoint-named vxcan device with no tracked endpoint
+    UntrackedEndpointDevice,
+    // Endpoint peer left in the host namespace with no tracked endpoint
+    UntrackedEndpointPeer,
+    // Pool-named vcan interface the pool is not holding
+    UntrackedPoolInterface,
+}
+
+/// Kernel interface following the plugin's naming scheme that nothing owns
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OrphanInterface {
+    pub name: String,
+    pub reason: OrphanReason,
+    pub kernel: LinkStatus,
+}
+
+/// Where the plugin is in its startup sequence, in order
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupPhase {
+    // Reading the state file and restoring its networks
+    LoadingState,
+    // Listing the plugin's networks from the Docker daemon
+    ConnectingDocker,
+    // Declared networks, then endpoints and rules rebuilt from the kernel
+    RecoveringInterfaces,
+    // Warm-up health check of the recovered state
+    Validating,
+    // Serving Docker requests
+    Ready,
+}
+
+impl StartupPhase {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => StartupPhase::LoadingState,
+            1 => StartupPhase::ConnectingDocker,
+            2 => StartupPhase::RecoveringInterfaces,
+            3 => StartupPhase::Validating,
+            _ => StartupPhase::Ready,
+        }
+    }
+}
+
+/// Whether recovered networks and endpoints are backed by kernel interfaces
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct HealthReport {
+    pub healthy: bool,
+    // Networks whose bus interface is missing from the kernel
+    pub missing_networks: Vec<String>,
+    // (network id, endpoint device) for endpoints whose vxcan device is missing
+    pub missing_endpoints: Vec<(String, String)>,
+    // Networks that could not be restored at all, with the reason
+    pub failed_networks: BTreeMap<String, String>,
+}
+
+/// Host resources held by the plugin, for capacity planning and leak detection
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ResourceReport {
+    pub networks: usize,
+    pub endpoints: usize,
+    // Kernel interfaces the plugin created and still owns, including pooled vcans
+    pub interfaces_created: usize,
+    pub rules: usize,
+    // Kernel cangw jobs behind those rules (classic and FD for each, per ingress filter)
+    pub cangw_jobs: usize,
+    // Size of the state file; None in memory-only mode or before the first save
+    pub state_bytes: Option<u64>,
+    // Approximate size of the in-memory network, endpoint and rule records
+    pub memory_bytes: usize,
+}
+
+/// What `reconcile` repaired on one network
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct NetworkReconcile {
+    // The bus interface was missing and has been recreated
+    pub interface_recreated: bool,
+    // Gateway jobs that were missing from the kernel and have been re-installed
+    pub rules_reinstalled: usize,
+    // Saved endpoints that were missing from memory and have been recreated
+    pub endpoints_restored: usize,
+    // Why the network could not be fully reconciled
+    pub error: Option<String>,
+}
+
+/// Outcome of rebuilding endpoints from kernel interfaces and cangw rules
+#[derive(Debug, Clone, Default)]
+pub struct ReconstructReport {
+    // (network id, endpoint device) for every endpoint added back
+    pub endpoints: Vec<(String, String)>,
+    // Gateway rules adopted into a network's tracked rules
+    pub rules: usize,
+    // Bus interfaces with endpoint rules that match no known network
+    pub unmatched_buses: Vec<String>,
+}
 
 #[derive(Clone)]
 pub struct NetworkManager {
-    network_list: Arc<RwLock<HashMap<String, Network>>>,
+    network_list: Arc<RwLock<NetworkMap>>,
     // Mutex to prevent concurrent network_load operations
     // This prevents race conditions when multiple containers start simultaneously
     load_mutex: Arc<Mutex<()>>,
+    state: StateStore,
+    config: Arc<PluginConfig>,
+    events: Arc<EventLog>,
+    pool: Option<Arc<InterfacePool>>,
+    // While set, every operation that changes networks or endpoints is rejected
+    maintenance: Arc<AtomicBool>,
+    // can-gw module parameters, read once at startup
+    gw_params: Arc<ModuleParams>,
+    // Networks that failed to restore from the state file or Docker, by id
+    restore_failures: Arc<RwLock<BTreeMap<String, String>>>,
+    // Set by an operator to let a fail-closed warm-up proceed despite an unhealthy state
+    warmup_released: Arc<AtomicBool>,
+    // Current StartupPhase, stored as its discriminant
+    phase: Arc<AtomicU8>,
+    // Change operations currently running; waited on at shutdown
+    in_flight: Arc<AtomicUsize>,
+    // Joins in progress by (network id, endpoint id)
+    attaching: Arc<AttachMap>,
+    // Time source for network TTLs
+    clock: Arc<dyn Clock>,
+    // (vxcan.dev, vxcan.id) handed out by AllocateNetwork, by network id; global scope only
+    allocations: Arc<Mutex<BTreeMap<String, (String, String)>>>,
+}
+
+/// Result of a Join shared with concurrent Joins for the same endpoint
+#[derive(Default)]
+struct AttachSlot {
+    result: Mutex<Option<Result<JoinResponse, RustyCanError>>>,
+    done: Condvar,
+}
+
+type AttachMap = Mutex<HashMap<(String, String), Arc<AttachSlot>>>;
+
+/// Networks by id, each behind its own lock so operations on different networks don't
+/// wait on each other; the map lock only guards inserting and removing networks
+/// Locks are always taken in this order, skipping any not needed:
+/// load_mutex, the map, one network, its endpoint_list, its rules_list
+/// Never take the map lock while holding a network's lock, and never hold two
+/// networks' locks at once; a network taken out of the map is only dropped, which
+/// tears it down, after the map lock is released (checked in debug builds)
+type NetworkMap = HashMap<String, Arc<Mutex<Network>>>;
+
+// Set while this thread holds the network map's write lock
+#[cfg(debug_assertions)]
+thread_local! {
+    static MAP_WRITE_HELD: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether this thread holds the network map's write lock, which a network must never
+/// be torn down under: removing its rules and interface would stall every other request
+#[cfg(debug_assertions)]
+pub fn map_write_held() -> bool {
+    MAP_WRITE_HELD.with(Cell::get)
+}
+
+/// Write lock on the network map, recorded in debug builds so teardown can check for it
+struct MapWriteGuard<'a>(RwLockWriteGuard<'a, NetworkMap>);
+
+impl<'a> MapWriteGuard<'a> {
+    fn new(guard: RwLockWriteGuard<'a, NetworkMap>) -> Self {
+        #[cfg(debug_assertions)]
+        MAP_WRITE_HELD.with(|held| held.set(true));
+        MapWriteGuard(guard)
+    }
+}
+
+impl Deref for MapWriteGuard<'_> {
+    type Target = NetworkMap;
+
+    fn deref(&self) -> &NetworkMap {
+        &self.0
+    }
+}
+
+impl DerefMut for MapWriteGuard<'_> {
+    fn deref_mut(&mut self) -> &mut NetworkMap {
+        &mut self.0
+    }
+}
+
+impl Drop for MapWriteGuard<'_> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        MAP_WRITE_HELD.with(|held| held.set(false));
+    }
+}
+
+/// The Join doing the work for an endpoint; hands its result to any waiters on drop
+struct AttachLeader<'a> {
+    attaching: &'a AttachMap,
+    key: (String, String),
+    result: Result<JoinResponse, RustyCanError>,
+}
+
+impl Drop for AttachLeader<'_> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.attaching.lock().remove(&self.key) {
+            *slot.result.lock() = Some(self.result.clone());
+            slot.done.notify_all();
+        }
+    }
+}
+
+/// Counts a change operation as in flight until dropped
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for NetworkManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NetworkManager {
     pub fn new() -> Self {
+        Self::with_config(PluginConfig::from_env())
+    }
+
+    /// Manager with the given settings, recovering the networks in its state file
+    pub fn with_config(config: PluginConfig) -> Self {
+        command::set_logging(config.log_commands);
+        if config.dry_run {
+            warn!("Dry-run mode: no commands are run and no interfaces are changed");
+            command::set_runner(Box::new(command::DryRunner));
+            netlink::set_dry_run(true);
+        } else if config.dry_run_commands {
+            warn!("Dry-run mode: ip, cangw, tc and can-utils commands are logged but not run");
+            command::set_runner(Box::new(command::DryRunner));
+        }
+        link::set_create_limit(config.max_concurrent_creates);
+        cangw::set_retries(config.cangw_retries);
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
         let mgr = NetworkManager {
             network_list: Arc::new(RwLock::new(HashMap::new())),
             load_mutex: Arc::new(Mutex::new(())),
+            // A dry run must not leave behind state describing networks that don't exist
+            state: StateStore::probe(if config.dry_run { &[] } else { &config.state_files }),
+            events: Arc::new(EventLog::new(config.event_log_size)),
+            pool: match config.vcan_pool_size {
+                0 => None,
+                n => Some(Arc::new(InterfacePool::new(n))),
+            },
+            config: Arc::new(config),
+            maintenance: Arc::new(AtomicBool::new(false)),
+            gw_params: Arc::new(ModuleParams::probe()),
+            restore_failures: Arc::new(RwLock::new(BTreeMap::new())),
+            warmup_released: Arc::new(AtomicBool::new(false)),
+            phase: Arc::new(AtomicU8::new(StartupPhase::LoadingState as u8)),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            attaching: Arc::new(Mutex::new(HashMap::new())),
+            clock,
+            allocations: Arc::new(Mutex::new(BTreeMap::new())),
         };
+
+        if mgr.gw_params.loaded {
+            info!("can-gw module parameters: {:?}", mgr.gw_params.params);
+        } else {
+            info!("can-gw module parameters unavailable; hop limits are not validated");
+        }
         
         // Try to load persisted networks from file
         mgr.load_networks_from_file();
+
+        // Pre-create pooled interfaces after recovered networks have claimed their names
+        if let Some(pool) = &mgr.pool {
+            pool.fill();
+        }
         
         mgr
     }
     
+    pub fn config(&self) -> &PluginConfig {
+        &self.config
+    }
+
+    /// Lock the network map for inserting or removing networks; one removed must only
+    /// be dropped once the lock is released
+    fn map_write(&self) -> MapWriteGuard<'_> {
+        MapWriteGuard::new(self.network_list.write())
+    }
+
+    /// Handle to one network, so it can be locked without holding the map lock
+    fn network(&self, nuid: &str) -> Option<Arc<Mutex<Network>>> {
+        self.network_list.read().get(nuid).cloned()
+    }
+
+    /// Handles to every network, taken without holding the map lock while they're used
+    fn networks(&self) -> Vec<(String, Arc<Mutex<Network>>)> {
+        self.network_list
+            .read()
+            .iter()
+            .map(|(nuid, n)| (nuid.clone(), n.clone()))
+            .collect()
+    }
+
+    /// Enter or leave maintenance mode; read-only APIs keep working either way
+    pub fn set_maintenance(&self, enabled: bool) {
+        let was = self.maintenance.swap(enabled, Ordering::SeqCst);
+        if was != enabled {
+            info!(
+                "Maintenance mode {}",
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance.load(Ordering::SeqCst)
+    }
+
+    /// Start a change operation, counted as in flight until the guard is dropped
+    fn begin_change(&self) -> Result<InFlight, RustyCanError> {
+        // Count first, so a drain starting after the maintenance check still waits for us
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let op = InFlight(self.in_flight.clone());
+        if self.in_maintenance() {
+            warn!("Rejecting change while in maintenance mode");
+            return Err(RustyCanError::Maintenance);
+        }
+        Ok(op)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait for in-flight change operations to finish
+    /// Returns false if some were still running when the timeout expired
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while self.in_flight() > 0 {
+            if tokio::time::Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        true
+    }
+
+    /// Remove every network's rules and endpoints, along with the interfaces the
+    /// plugin created for them (unless persistent)
+    /// The state file is left alone, so the networks come back on the next start
+    /// Safe to call more than once: torn-down networks leave the map, so a later call
+    /// (or the map's own drop) finds nothing left to remove
+    pub fn shutdown(&self) {
+        // Taken out under the lock, torn down once it is released
+        let map = std::mem::take(&mut *self.map_write());
+        for (nuid, n) in map.iter() {
+            let mut n = n.lock();
+            // Saved as they were, so the next start recreates what is torn down here
+            self.save_endpoints(nuid, &n);
+            let detached = n.detach_all(true);
+            info!(
+                network_id = nuid.as_str();
+                "Tore down network {} ({} endpoints)", nuid, detached.len()
+            );
+        }
+        // Dropping the networks deletes the interfaces they own
+        drop(map);
+    }
+
+    pub fn phase(&self) -> StartupPhase {
+        StartupPhase::from_u8(self.phase.load(Ordering::SeqCst))
+    }
+
+    /// Move to the next startup phase
+    pub fn set_phase(&self, phase: StartupPhase) {
+        let was = StartupPhase::from_u8(self.phase.swap(phase as u8, Ordering::SeqCst));
+        if was != phase {
+            info!("Startup phase {:?} -> {:?}", was, phase);
+        }
+    }
+
+    /// Check every restored network and endpoint against the kernel's interfaces
+    /// Built from the `list_networks` snapshot, so it agrees with `/networks`
+    pub fn health_report(&self) -> HealthReport {
+        let mut report = HealthReport {
+            failed_networks: self.restore_failures.read().clone(),
+            ..Default::default()
+        };
+
+        for nw in self.list_networks() {
+            if nw.mode != NetworkMode::Direct && nw.ifindex.is_none() {
+                report.missing_networks.push(nw.nuid.clone());
+            }
+            // An interface list that can't be read counts as missing
+            for ep in nw.endpoints.iter().filter(|ep| ep.interface_exists != Some(true)) {
+                report.missing_endpoints.push((nw.nuid.clone(), ep.device.clone()));
+            }
+        }
+
+        report.healthy = report.missing_networks.is_empty()
+            && report.missing_endpoints.is_empty()
+            && report.failed_networks.is_empty();
+        report
+    }
+
+    /// Let a fail-closed warm-up gate start serving even though the state is unhealthy
+    pub fn release_warmup(&self) {
+        if !self.warmup_released.swap(true, Ordering::SeqCst) {
+            info!("Warm-up gate released by operator");
+        }
+    }
+
+    pub fn warmup_released(&self) -> bool {
+        self.warmup_released.load(Ordering::SeqCst)
+    }
+
+    /// can-gw kernel module parameters as probed at startup
+    pub fn gateway_params(&self) -> &ModuleParams {
+        &self.gw_params
+    }
+
+    /// The most recent `n` lifecycle events, oldest first
+    pub fn recent_events(&self, n: usize) -> Vec<TimedEvent> {
+        self.events.recent(n)
+    }
+
     /// Load network configurations from persistent storage
     fn load_networks_from_file(&self) {
-        // Create directory if it doesn't exist
-        if let Some(parent) = std::path::Path::new(NETWORK_STATE_FILE).parent() {
-            let _ = fs::create_dir_all(parent);
+        if let Err(e) = self.state.upgrade() {
+            error!("Failed to migrate network state file: {}", e);
         }
-        
-        match fs::read_to_string(NETWORK_STATE_FILE) {
-            Ok(contents) => {
-                match serde_json::from_str::<HashMap<String, NetworkConfig>>(&contents) {
-                    Ok(configs) => {
-                        println!(" -> Loaded {} network configurations from file", configs.len());
-                        let mut map = self.network_list.write();
-                        for (nuid, config) in configs {
-                            let nw = Network::new(config.device, config.peer, config.canid);
-                            map.insert(nuid, nw);
-                        }
+        match self.state.load() {
+            Ok(Some(configs)) => {
+                info!("Loaded {} network configurations from file", configs.len());
+                let mut map = self.map_write();
+                for (nuid, config) in configs {
+                    if config.ephemeral {
+                        info!("Skipping ephemeral network {} found in state file", nuid);
+                        continue;
                     }
-                    Err(e) => {
-                        eprintln!(" !! Failed to parse network state file: {}", e);
+                    match Network::new(&config, Origin::Recovery, self.clock.now()) {
+                        Ok(nw) => {
+                            map.insert(nuid, Arc::new(Mutex::new(nw)));
+                        }
+                        Err(e) => {
+                            error!("Failed to restore network {}: {}", nuid, e);
+                            self.restore_failures.write().insert(nuid, e.to_string());
+                        }
                     }
                 }
             }
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                println!(" -> No persisted network state found (first run)");
+            Ok(None) => {
+                info!("No persisted network state found (first run)");
             }
             Err(e) => {
-                eprintln!(" !! Failed to read network state file: {}", e);
+                error!("Failed to load network state file: {}", e);
             }
         }
     }
@@ -101,12 +539,13 @@ impl NetworkManager {
     pub async fn network_load(&self) {
         // Check if persisted state file exists
         // If it doesn't exist, skip loading from Docker (fresh start scenario)
-        if !std::path::Path::new(NETWORK_STATE_FILE).exists() {
-            println!(" -> No persisted network state found, starting fresh (skipping Docker network load)");
+        if !self.state.exists() {
+            info!("No persisted network state found, starting fresh (skipping Docker network load)");
             return;
         }
 
-        println!(" -> Persisted state file found, loading networks from Docker...");
+        info!("Persisted state file found, loading networks from Docker...");
+        self.set_phase(StartupPhase::ConnectingDocker);
         let connection = Docker::connect_with_unix_defaults().unwrap();
 
         let list_networks_filters: HashMap<&str, Vec<&str>> = HashMap::new();
@@ -115,213 +554,707 @@ impl NetworkManager {
         };
         match connection.list_networks(Some(config)).await {
             Ok(networks) => {
+                // Build everything first so the map is locked once for the whole load,
+                // rather than once per network while attaches are waiting
+                let mut loaded: Vec<(String, Arc<Mutex<Network>>)> = Vec::new();
+                let mut failed: Vec<(String, String)> = Vec::new();
                 for n in networks {
-                    match (n.driver, n.options, n.id) {
-                        (Some(driver), Some(options), Some(nid)) => {
-                            if driver.eq("rustyvxcan") {
-                                let device = if options.contains_key("vxcan.dev") {
-                                    options["vxcan.dev"].clone()
-                                } else {
-                                    String::from("vcan")
-                                };
-                                let peer = if options.contains_key("vxcan.peer") {
-                                    options["vxcan.peer"].clone()
-                                } else {
-                                    String::from("vcan")
-                                };
-                                let canid = if options.contains_key("vxcan.id") {
-                                    options["vxcan.id"].clone()
-                                } else {
-                                    String::from("0")
-                                };
+                    if let (Some(driver), Some(options), Some(nid)) = (n.driver, n.options, n.id) {
+                        if driver.eq("rustyvxcan") {
+                            // A network already loaded from the state file is kept: replacing
+                            // it would drop it, and with it the kernel interfaces it owns
+                            if self.network_list.read().contains_key(&nid) {
+                                continue;
+                            }
+
+                            // Docker hands back the options CreateNetwork was given
+                            let config = match serde_json::to_string(&options)
+                                .map_err(|e| RustyCanError::InvalidOptions(e.to_string()))
+                                .and_then(|options| self.options_parse(options))
+                            {
+                                Ok(config) => config,
+                                Err(e) => {
+                                    error!("Failed to load network {}: {}", nid, e);
+                                    failed.push((nid, e.to_string()));
+                                    continue;
+                                }
+                            };
+
+                            // Ephemeral networks are never brought back after a restart
+                            if config.ephemeral {
+                                info!("Not loading ephemeral network {}", nid);
+                                continue;
+                            }
 
-                                let nw = Network::new(device, peer, canid);
-                                self.network_list.write().insert(nid, nw);
+                            match Network::new(&config, Origin::Recovery, self.clock.now()) {
+                                Ok(nw) => loaded.push((nid, Arc::new(Mutex::new(nw)))),
+                                Err(e) => {
+                                    error!("Failed to load network {}: {}", nid, e);
+                                    failed.push((nid, e.to_string()));
+                                }
                             }
                         }
-                        _ => {}
                     }
                 }
+
+                {
+                    let mut failures = self.restore_failures.write();
+                    for (nid, _) in &loaded {
+                        failures.remove(nid);
+                    }
+                    failures.extend(failed);
+                }
+                // One loaded again meanwhile is dropped once the map lock is released
+                let mut map = self.map_write();
+                let duplicates: Vec<_> = loaded
+                    .into_iter()
+                    .filter_map(|(nid, network)| match map.entry(nid) {
+                        Entry::Vacant(slot) => {
+                            slot.insert(network);
+                            None
+                        }
+                        Entry::Occupied(_) => Some(network),
+                    })
+                    .collect();
+                drop(map);
+                drop(duplicates);
             }
-            Err(e) => eprintln!(" !! Unable to get docker networks: {}", e),
+            Err(e) => error!("Unable to get docker networks: {}", e),
         }
     }
 
-    pub fn network_create(&self, uid: String, options: String) {
-        // Print the options and extract the right values
-        // Add the network to the hashmap
-        println!(
-            " -> Adding network with id '{}' with options '{}'",
-            uid, options
+    /// Reserve a CAN id for a global-scope network on the swarm manager, before any
+    /// node creates it; no interface is created here
+    /// A `vxcan.id` in the options is reserved as given, otherwise the lowest id not yet
+    /// allocated on the same `vxcan.dev` is chosen. Returns the options every node's
+    /// CreateNetwork should get, with `vxcan.id` filled in
+    pub fn network_allocate(
+        &self,
+        nuid: String,
+        mut options: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, RustyCanError> {
+        let _op = self.begin_change()?;
+        let device = options
+            .get("vxcan.dev")
+            .and_then(|d| d.as_str())
+            .unwrap_or("vcan")
+            .to_string();
+
+        let mut allocations = self.allocations.lock();
+        let taken = |canid: &str, allocations: &BTreeMap<String, (String, String)>| {
+            allocations
+                .iter()
+                .find(|(other, (d, c))| **other != nuid && *d == device && c == canid)
+                .map(|(other, _)| other.clone())
+        };
+        // Compared in the canonical form CreateNetwork will use
+        let v = serde_json::Value::Object(options.clone());
+        let extended = option_bool(&v, "vxcan.extended")?.unwrap_or(false);
+        let requested = option_canid(&v, extended)?.map(|n| n.to_string());
+        let canid = match requested.as_deref() {
+            Some(canid) => {
+                if let Some(other) = taken(canid, &allocations) {
+                    return Err(RustyCanError::InterfaceInUse {
+                        name: format!("{device}{canid}"),
+                        nuid: other,
+                    });
+                }
+                canid.to_string()
+            }
+            None => match allocations.get(&nuid) {
+                Some((_, canid)) => canid.clone(),
+                None => (0u32..)
+                    .map(|id| id.to_string())
+                    .find(|id| taken(id, &allocations).is_none())
+                    .unwrap_or_default(),
+            },
+        };
+        options.insert(String::from("vxcan.id"), serde_json::json!(canid));
+
+        // Reject options CreateNetwork would refuse now, rather than on every node
+        self.options_parse(serde_json::Value::Object(options.clone()).to_string())?;
+
+        info!(
+            network_id = nuid.as_str();
+            "Allocated {}{} to network {}", device, canid, nuid
         );
+        allocations.insert(nuid, (device, canid));
+        Ok(options)
+    }
 
-        match self.options_parse(options) {
-            Ok((d, p, c)) => {
-                let nw = Network::new(d.clone(), p.clone(), c.clone());
-                self.network_list.write().insert(uid.clone(), nw);
-                
-                // Persist network configuration to file
-                self.persist_network_config(uid, d, p, c);
+    /// Release what `network_allocate` reserved for a network; unknown ids are ignored
+    pub fn network_free(&self, nuid: &str) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        if let Some((device, canid)) = self.allocations.lock().remove(nuid) {
+            info!(network_id = nuid; "Freed {}{} from network {}", device, canid, nuid);
+        }
+        Ok(())
+    }
+
+    pub fn network_create(&self, uid: String, options: String) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        let mut batch = self.state.batch();
+        self.network_create_staged(uid.clone(), options, &mut batch)?;
+
+        // Persist network configuration to file, undoing the create if that fails
+        if let Err(e) = batch.commit() {
+            error!("Failed to persist network configuration: {}", e);
+            // Torn down once the map lock is released
+            let removed = self.map_write().remove(&uid);
+            drop(removed);
+            self.events.record(LifecycleEvent::NetworkDeleted { nuid: uid });
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Check a CreateNetwork request carries no IP address pool
+    /// CAN networks have no addressing, but Docker's default IPAM driver always hands
+    /// out a subnet, so this only applies with `require_null_ipam`; the null driver
+    /// passes the 0.0.0.0/0 placeholder
+    pub fn check_ip_data(&self, request: &serde_json::Value) -> Result<(), RustyCanError> {
+        if !self.config.require_null_ipam {
+            return Ok(());
+        }
+        let pools = ["IPv4Data", "IPv6Data"]
+            .iter()
+            .filter_map(|key| request[key].as_array())
+            .flatten()
+            .filter_map(|data| data["Pool"].as_str());
+        for pool in pools {
+            if pool != "0.0.0.0/0" && pool != "::/0" {
+                return Err(RustyCanError::InvalidOptions(format!(
+                    "CAN networks have no IP addressing, but the request carries subnet {pool}; \
+                     create the network with --ipam-driver null and without --subnet"
+                )));
             }
-            Err(_) => {}
         }
+        Ok(())
     }
-    
-    /// Persist a single network configuration
-    fn persist_network_config(&self, nuid: String, device: String, peer: String, canid: String) {
-        // Create directory if it doesn't exist
-        if let Some(parent) = std::path::Path::new(NETWORK_STATE_FILE).parent() {
-            let _ = fs::create_dir_all(parent);
+
+    /// Create several networks as a unit: either all are created, or none are
+    /// The state file is written once after every network has been created
+    pub fn create_networks(&self, requests: Vec<CreateNetworkRequest>) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        let mut batch = self.state.batch();
+        let mut created: Vec<String> = Vec::new();
+
+        let mut result = Ok(());
+        for req in requests {
+            let options = match req.options.as_str() {
+                Some(o) => o.to_string(),
+                None => req.options.to_string(),
+            };
+            match self.network_create_staged(req.nuid.clone(), options, &mut batch) {
+                Ok(()) => created.push(req.nuid),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
         }
-        
-        // Load existing configs
-        let mut configs: HashMap<String, NetworkConfig> = match fs::read_to_string(NETWORK_STATE_FILE) {
-            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-            Err(_) => HashMap::new(),
+        if result.is_ok() {
+            result = batch.commit();
+        }
+
+        if let Err(e) = result {
+            // Roll back in reverse order; dropping a Network tears down its interface,
+            // which happens once the map lock is released
+            error!("Batch network create failed ({}), rolling back {} networks", e, created.len());
+            let mut map = self.map_write();
+            let removed: Vec<_> =
+                created.iter().rev().filter_map(|nuid| map.remove(nuid)).collect();
+            drop(map);
+            drop(removed);
+            for nuid in created.into_iter().rev() {
+                self.events.record(LifecycleEvent::NetworkDeleted { nuid });
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Create a new network from an existing network's options with `overrides` applied
+    /// The override must move the clone onto its own interface (usually a new vxcan.id)
+    pub fn clone_network(
+        &self,
+        src_nuid: &str,
+        new_nuid: String,
+        overrides: serde_json::Value,
+    ) -> Result<(), RustyCanError> {
+        let mut options = match self.network(src_nuid) {
+            Some(n) => network_options(&n.lock().config()),
+            None => return Err(RustyCanError::NetworkNotFound(src_nuid.to_string())),
         };
-        
-        // Add/update this network
-        configs.insert(nuid, NetworkConfig { device, peer, canid });
-        
-        // Save back to file
-        match serde_json::to_string_pretty(&configs) {
-            Ok(json) => {
-                if let Err(e) = fs::write(NETWORK_STATE_FILE, json) {
-                    eprintln!(" !! Failed to persist network configuration: {}", e);
+
+        match overrides {
+            serde_json::Value::Object(map) => {
+                for (key, value) in map {
+                    options[key] = value;
                 }
             }
-            Err(e) => {
-                eprintln!(" !! Failed to serialize network configuration: {}", e);
+            serde_json::Value::Null => {}
+            other => {
+                return Err(RustyCanError::InvalidOptions(format!(
+                    "overrides must be an object, got {other}"
+                )))
             }
         }
+
+        info!("Cloning network {} as {}", src_nuid, new_nuid);
+        self.network_create(new_nuid, options.to_string())
+    }
+
+    /// Whether an existing network was created with the given options
+    /// Returns None when there is no such network
+    pub fn network_matches(
+        &self,
+        nuid: &str,
+        options: &str,
+    ) -> Result<Option<bool>, RustyCanError> {
+        let wanted = self.options_parse(options.to_string())?;
+        Ok(self.network(nuid).map(|n| n.lock().config() == wanted))
     }
 
-    pub fn network_delete(&self, uid: String) {
-        let mut map = self.network_list.write();
-        if map.contains_key(&uid) {
-            println!(" -> Network exists...removing!");
-            map.remove(&uid);
+    /// Create a network in memory and stage its configuration in a persistence batch
+    fn network_create_staged(
+        &self,
+        uid: String,
+        options: String,
+        batch: &mut PersistBatch,
+    ) -> Result<(), RustyCanError> {
+        // Print the options and extract the right values
+        // Add the network to the hashmap
+        info!(
+            network_id = uid.as_str();
+            "Adding network with id '{}' with options '{}'",
+            uid, options
+        );
+
+        if self.network_list.read().contains_key(&uid) {
+            return Err(RustyCanError::NetworkExists(uid));
+        }
+
+        let config = self.options_parse(options)?;
+
+        let ifname = Network::interface_name(&config);
+        if let Some(e) = interface_conflict(&self.network_list.read(), &uid, &ifname) {
+            return Err(e);
+        }
+
+        // Claim a pre-created interface when the pool is enabled
+        let pooled = match &self.pool {
+            Some(pool)
+                if !config.realname
+                    && !config.fd
+                    && config.mode == NetworkMode::Bus
+                    && !LinkStatus::query(&ifname).exists =>
+            {
+                pool.take(&ifname)
+            }
+            _ => false,
+        };
+
+        // A failed network is reported to Docker and never inserted; others are unaffected
+        let mut nw = Network::new(&config, Origin::Request, self.clock.now()).map_err(|e| {
+            error!(network_id = uid.as_str(); "Unable to create network {}: {}", uid, e);
+            e
+        })?;
+        if let (true, Some(pool)) = (pooled, &self.pool) {
+            nw.claim_from_pool(pool.clone());
+        }
+
+        // Another create may have passed the checks above while this network was built
+        let mut map = self.map_write();
+        let conflict = interface_conflict(&map, &uid, &ifname);
+        let rejected = match (conflict, map.entry(uid.clone())) {
+            (None, Entry::Vacant(slot)) => {
+                slot.insert(Arc::new(Mutex::new(nw)));
+                None
+            }
+            (None, Entry::Occupied(_)) => Some((RustyCanError::NetworkExists(uid.clone()), nw)),
+            (Some(e), _) => Some((e, nw)),
+        };
+        if let Some((e, mut nw)) = rejected {
+            // The interface this network just created may be the one the other now uses
+            if let RustyCanError::InterfaceInUse { nuid: other, .. } = &e {
+                if let Some(other) = map.get(other) {
+                    other.lock().inherit_interface(&mut nw);
+                }
+            }
+            drop(map);
+            warn!(network_id = uid.as_str(); "Network {} lost a concurrent create: {}", uid, e);
+            // Torn down outside the map lock
+            drop(nw);
+            return Err(e);
         }
         drop(map);
+        self.events.record(LifecycleEvent::NetworkCreated { nuid: uid.clone() });
+
+        if config.ephemeral {
+            info!("Network {} is ephemeral, not persisting its configuration", uid);
+        } else {
+            batch.upsert(uid, config);
+        }
+        Ok(())
+    }
+
+    pub fn network_delete(&self, uid: String) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        let mut map = self.map_write();
+        let removed = map.remove(&uid);
+        drop(map);
+        // Tearing down the interface and rules happens on drop, outside the map lock
+        if let Some(network) = removed {
+            info!("Network exists...removing!");
+            drop(network);
+            self.events.record(LifecycleEvent::NetworkDeleted { nuid: uid.clone() });
+        }
         
         // Remove from persisted configuration
-        let mut configs: HashMap<String, NetworkConfig> = match fs::read_to_string(NETWORK_STATE_FILE) {
-            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
-            Err(_) => HashMap::new(),
+        let mut batch = self.state.batch();
+        batch.remove(uid);
+        if let Err(e) = batch.commit() {
+            error!("Failed to remove network configuration: {}", e);
+        }
+        Ok(())
+    }
+
+    pub fn endpoint_create(
+        &self,
+        nuid: String,
+        epuid: String,
+        options: serde_json::Value,
+    ) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+
+        let Some(network) = self.network(&nuid) else {
+            return Ok(());
         };
-        
-        configs.remove(&uid);
-        
-        if let Ok(json) = serde_json::to_string_pretty(&configs) {
-            let _ = fs::write(NETWORK_STATE_FILE, json);
+        // Names held by every other network, gathered before this one is locked
+        let mut claimed = claimed_interface_names(&self.network_list.read(), Some(&nuid));
+
+        // Lock only this network
+        let mut n = network.lock();
+        if n.resolve_reconstructed(&epuid, self.config.endpoint_short_name_len) {
+            self.save_endpoints(&nuid, &n);
+        }
+
+        // A repeated CreateEndpoint must not leak or clobber the existing interface
+        let existing = n.endpoint_list.read().get(&epuid).map(|ep| ep.options.clone());
+        if let Some(existing) = existing {
+            if existing == options {
+                info!(
+                    endpoint_id = epuid.as_str();
+                    "Endpoint {} already exists with the same options, nothing to do", epuid
+                );
+                return Ok(());
+            }
+
+            match self.config.duplicate_endpoint_policy {
+                DuplicateEndpointPolicy::Reject => {
+                    warn!("Endpoint {} already exists with different options, rejecting", epuid);
+                    return Err(RustyCanError::EndpointConflict(epuid));
+                }
+                DuplicateEndpointPolicy::Recreate => {
+                    info!("Endpoint {} already exists with different options, recreating", epuid);
+                    // Dropping the old endpoint removes its interface before the new one is created
+                    n.endpoint_remove(epuid.clone());
+                }
+            }
+        }
+
+        // Names held by every network and endpoint, so the new pair can't collide with any
+        claimed.extend(n.interface_names());
+
+        // Create the endpoint and add it to the network
+        let ep = n.new_endpoint(
+            &nuid,
+            epuid.clone(),
+            options,
+            self.config.endpoint_short_name_len,
+            &claimed,
+            None,
+        )?;
+        n.endpoint_add(ep);
+        self.save_endpoints(&nuid, &n);
+        self.events.record(LifecycleEvent::EndpointCreated { nuid, epuid });
+        Ok(())
+    }
+
+    pub fn endpoint_delete(&self, nuid: String, epuid: String) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+
+        if let Some(network) = self.network(&nuid) {
+            // Remove the endpoint from the network
+            let mut n = network.lock();
+            n.resolve_reconstructed(&epuid, self.config.endpoint_short_name_len);
+            n.endpoint_remove(epuid.clone());
+            self.save_endpoints(&nuid, &n);
+            self.events.record(LifecycleEvent::EndpointDeleted { nuid, epuid });
         }
+        Ok(())
     }
 
-    pub fn endpoint_create(&self, nuid: String, epuid: String) {
-        // Create the endpoint
-        let ep = Endpoint::new(epuid);
+    // Record a network's endpoints in the state file; a failure only costs their
+    // proactive recreation after a restart, so it is logged rather than returned
+    fn save_endpoints(&self, nuid: &str, network: &Network) {
+        let config = network.saved_config();
+        if config.ephemeral {
+            return;
+        }
+        let mut batch = self.state.batch();
+        batch.upsert(nuid.to_string(), config);
+        if let Err(e) = batch.commit() {
+            error!(network_id = nuid; "Failed to save the endpoints of network {}: {}", nuid, e);
+        }
+    }
 
-        // Lock the network list
-        let mut map = self.network_list.write();
-        match map.get_mut(&nuid) {
-            Some(n) => {
-                // Add the endpoint to the network
-                n.endpoint_add(ep)
+    /// Recreate the saved endpoints of every network that are missing from memory,
+    /// creating their vxcan pairs where the kernel no longer has them
+    /// Returns the number of endpoints restored
+    pub fn restore_endpoints(&self) -> usize {
+        let saved = match self.state.load() {
+            Ok(saved) => saved.unwrap_or_default(),
+            Err(e) => {
+                error!("Unable to read saved endpoints: {}", e);
+                return 0;
             }
-            None => (),
         };
+        let mut restored = 0;
+        for (nuid, network) in self.networks() {
+            let Some(config) = saved.get(&nuid) else {
+                continue;
+            };
+            match self.restore_network_endpoints(&nuid, &network, &config.endpoints) {
+                Ok(n) => restored += n,
+                Err(e) => error!(
+                    network_id = nuid.as_str();
+                    "Unable to restore the endpoints of network {}: {}", nuid, e
+                ),
+            }
+        }
+        restored
     }
 
-    pub fn endpoint_delete(&self, nuid: String, epuid: String) {
-        // Lock the network list
-        let mut map = self.network_list.write();
-        match map.get_mut(&nuid) {
-            Some(n) => {
-                // Remove the endpoint from the network
-                n.endpoint_remove(epuid)
+    /// CreateEndpoint options an endpoint was saved with; Null when none were saved
+    fn saved_endpoint(&self, nuid: &str, epuid: &str) -> Option<SavedEndpoint> {
+        let saved = match self.state.load() {
+            Ok(saved) => saved.unwrap_or_default(),
+            Err(e) => {
+                warn!("Unable to read the saved options of endpoint {}: {}", epuid, e);
+                return None;
             }
-            None => (),
         };
+        saved
+            .get(nuid)
+            .and_then(|config| config.endpoints.iter().find(|ep| ep.uid == epuid))
+            .cloned()
+    }
+
+    fn restore_network_endpoints(
+        &self,
+        nuid: &str,
+        network: &Mutex<Network>,
+        saved: &[SavedEndpoint],
+    ) -> Result<usize, RustyCanError> {
+        let mut claimed = claimed_interface_names(&self.network_list.read(), Some(nuid));
+        let mut n = network.lock();
+        claimed.extend(n.interface_names());
+
+        let result = self.restore_saved_endpoints(nuid, &mut n, claimed, saved);
+        // Save what was restored or matched, even before a failure, under its current names
+        self.save_endpoints(nuid, &n);
+        result
+    }
+
+    fn restore_saved_endpoints(
+        &self,
+        nuid: &str,
+        n: &mut Network,
+        mut claimed: HashSet<String>,
+        saved: &[SavedEndpoint],
+    ) -> Result<usize, RustyCanError> {
+        let mut restored = 0;
+        for saved in saved {
+            let SavedEndpoint { uid, options, .. } = saved;
+            // One rebuilt from the kernel under its short name is the same endpoint
+            n.resolve_reconstructed(uid, self.config.endpoint_short_name_len);
+            if n.endpoint_list.read().contains_key(uid) {
+                n.restore_endpoint_options(uid, options.clone())?;
+                continue;
+            }
+            // Rebuilt with its saved options, so its direction, MTU and netem carry over
+            let ep = n.new_endpoint(
+                nuid,
+                uid.clone(),
+                options.clone(),
+                self.config.endpoint_short_name_len,
+                &claimed,
+                saved.names(),
+            )?;
+            info!(
+                network_id = nuid, endpoint_id = uid.as_str();
+                "Restored endpoint {} ({}) on network {}", uid, ep.device, nuid
+            );
+            claimed.extend([ep.device.clone(), ep.peer.clone()]);
+            self.events.record(LifecycleEvent::EndpointRecovered {
+                nuid: nuid.to_string(),
+                epuid: uid.clone(),
+            });
+            n.endpoint_add(ep);
+            restored += 1;
+        }
+        Ok(restored)
     }
 
-    /// Attach an endpoint to a network with full reboot resilience and race condition protection
-    /// 
-    /// This method implements a multi-stage synchronization strategy:
-    /// 1. Network loading is protected by load_mutex to prevent concurrent loads
-    /// 2. Endpoint creation uses double-checked locking pattern
-    /// 3. Locks are acquired in consistent order to prevent deadlocks
-    /// 4. Write locks are only held when necessary to minimize contention
-    /// 
-    /// Thread-safe for concurrent calls from multiple containers starting simultaneously
+    /// Join an endpoint to its network, recovering it and its network if a reboot lost them
+    /// Network loading is serialized by load_mutex and endpoint recreation is
+    /// double-checked under the network's lock; locks follow the order on NetworkMap
+    /// A Join arriving while another for the same endpoint is still running waits for
+    /// that one's result instead of repeating the recovery and rule installation
     pub fn endpoint_attach(
         &self,
         nuid: String,
         epuid: String,
-        _sbox: String,
+        sbox: String,
         options: String,
-    ) -> Result<JoinResponse, Error> {
-        // REBOOT RESILIENCE: Check if network exists in memory
-        // If network_load() failed during startup (Docker socket not ready),
-        // the network won't be in memory. We need to load it on-demand.
-        let network_exists = {
-            let map = self.network_list.read();
-            map.contains_key(&nuid)
+    ) -> Result<JoinResponse, RustyCanError> {
+        let key = (nuid.clone(), epuid.clone());
+        let existing = {
+            let mut attaching = self.attaching.lock();
+            match attaching.get(&key) {
+                Some(slot) => Some(slot.clone()),
+                None => {
+                    attaching.insert(key.clone(), Arc::new(AttachSlot::default()));
+                    None
+                }
+            }
         };
 
-        if !network_exists {
-            println!(" -> Network not found in memory (post-reboot recovery), loading from persisted state...");
-            
-            // CRITICAL SECTION: Use mutex to prevent concurrent network loads
+        if let Some(slot) = existing {
+            info!("Join for endpoint {} already in progress, waiting for its result", epuid);
+            let mut result = slot.result.lock();
+            loop {
+                if let Some(r) = result.as_ref() {
+                    return r.clone();
+                }
+                slot.done.wait(&mut result);
+            }
+        }
+
+        // Publishes the result, or a failure if the attach panics, when dropped
+        let mut leader = AttachLeader {
+            attaching: &self.attaching,
+            key,
+            result: Err(RustyCanError::AttachFailed {
+                epuid: epuid.clone(),
+                source: None,
+            }),
+        };
+        let started = Instant::now();
+        leader.result = self.attach_endpoint(nuid.clone(), epuid.clone(), sbox, options);
+
+        // Docker has already been told this Join failed, so don't leave it half done
+        if let (Ok(_), Some(limit)) = (&leader.result, self.config.attach_timeout) {
+            if started.elapsed() > limit {
+                warn!(
+                    network_id = nuid.as_str(), endpoint_id = epuid.as_str();
+                    "Join of endpoint {} finished after its {}ms timeout, rolling it back",
+                    epuid, limit.as_millis()
+                );
+                if let Some(network) = self.network(&nuid) {
+                    if let Err(e) = network.lock().endpoint_detach(epuid.clone()) {
+                        error!("Failed to roll back late join of {}: {}", epuid, e);
+                    }
+                }
+                leader.result = Err(RustyCanError::AttachTimeout {
+                    epuid: epuid.clone(),
+                    after: limit,
+                });
+            }
+        }
+        if leader.result.is_err() {
+            METRICS.endpoint_attach_errors.inc_with(&[("nuid", &nuid), ("epuid", &epuid)]);
+        }
+        leader.result.clone()
+    }
+
+    fn attach_endpoint(
+        &self,
+        nuid: String,
+        epuid: String,
+        _sbox: String,
+        options: String,
+    ) -> Result<JoinResponse, RustyCanError> {
+        let _op = self.begin_change()?;
+        self.check_options_size(&options)?;
+
+        // REBOOT RESILIENCE: Check if network exists in memory
+        // If network_load() failed during startup (Docker socket not ready),
+        // the network won't be in memory. We need to load it on-demand.
+        let network_exists = {
+            let map = self.network_list.read();
+            map.contains_key(&nuid)
+        };
+
+        if !network_exists {
+            info!("Network not found in memory (post-reboot recovery), loading from persisted state...");
+            
+            // CRITICAL SECTION: Use mutex to prevent concurrent network loads
             let _load_guard = self.load_mutex.lock();
             
             // Double-check: another thread may have loaded the network while we waited
             {
                 let map = self.network_list.read();
                 if map.contains_key(&nuid) {
-                    println!(" -> Network was loaded by another thread, continuing");
+                    info!("Network was loaded by another thread, continuing");
                     drop(map);
                     drop(_load_guard);
                 } else {
                     drop(map);
                     
                     // Load from persisted configuration file
-                    match fs::read_to_string(NETWORK_STATE_FILE) {
-                        Ok(contents) => {
-                            match serde_json::from_str::<HashMap<String, NetworkConfig>>(&contents) {
-                                Ok(configs) => {
-                                    if let Some(config) = configs.get(&nuid) {
-                                        println!(" -> Found network in persisted state: device={}, peer={}, id={}", 
-                                            config.device, config.peer, config.canid);
-                                        
-                                        // Create the network object
-                                        let nw = Network::new(
-                                            config.device.clone(),
-                                            config.peer.clone(),
-                                            config.canid.clone()
-                                        );
-                                        
-                                        let mut map = self.network_list.write();
-                                        map.insert(nuid.clone(), nw);
-                                        drop(map);
-                                        
-                                        println!(" -> Successfully recovered network from persisted state");
-                                    } else {
+                    match self.state.load() {
+                        Ok(Some(configs)) => {
+                            if let Some(config) = configs.get(&nuid).filter(|c| !c.ephemeral) {
+                                info!("Found network in persisted state: device={}, peer={}, id={}", 
+                                    config.device, config.peer, config.canid);
+                                
+                                // Create the network object
+                                let now = self.clock.now();
+                                let nw = match Network::new(config, Origin::Recovery, now) {
+                                    Ok(nw) => nw,
+                                    Err(e) => {
                                         drop(_load_guard);
-                                        eprintln!(" !! Network not found in persisted state - network may not exist");
-                                        return Err(Error);
+                                        error!("Failed to recreate network from persisted state: {}", e);
+                                        return Err(e);
                                     }
-                                }
-                                Err(e) => {
-                                    drop(_load_guard);
-                                    eprintln!(" !! Failed to parse network state file: {}", e);
-                                    return Err(Error);
-                                }
+                                };
+                                
+                                // Any network this replaces is dropped once the lock is released
+                                let mut map = self.map_write();
+                                let replaced = map.insert(nuid.clone(), Arc::new(Mutex::new(nw)));
+                                drop(map);
+                                drop(replaced);
+                                
+                                info!("Successfully recovered network from persisted state");
+                                self.events.record(LifecycleEvent::NetworkRecovered { nuid: nuid.clone() });
+                            } else {
+                                drop(_load_guard);
+                                warn!("Network not found in persisted state - network may not exist");
+                                return Err(RustyCanError::NetworkNotFound(nuid));
                             }
                         }
+                        Ok(None) => {
+                            drop(_load_guard);
+                            warn!("No persisted network state to recover from");
+                            return Err(RustyCanError::NetworkNotFound(nuid));
+                        }
                         Err(e) => {
                             drop(_load_guard);
-                            eprintln!(" !! Failed to read network state file: {}", e);
-                            return Err(Error);
+                            error!("Failed to load network state file: {}", e);
+                            return Err(e);
                         }
                     }
                     
@@ -330,130 +1263,1562 @@ impl NetworkManager {
             }
         }
 
-        // Lock the network list for reading first (lower contention)
-        let map = self.network_list.read();
-        let network_ref = match map.get(&nuid) {
+        // From here on only this network is locked, never the whole list
+        let network = match self.network(&nuid) {
             Some(n) => n,
             None => {
-                drop(map);
-                eprintln!(" !! Network not found during endpoint attach (should not happen)");
-                return Err(Error);
+                warn!("Network not found during endpoint attach (should not happen)");
+                return Err(RustyCanError::NetworkNotFound(nuid));
             }
         };
 
+        // Pick up an endpoint rebuilt from the kernel under its full id
+        {
+            let mut n = network.lock();
+            if n.resolve_reconstructed(&epuid, self.config.endpoint_short_name_len) {
+                self.save_endpoints(&nuid, &n);
+            }
+        }
+
         // REBOOT RESILIENCE: Check if endpoint exists in memory
         // After reboot, Docker's metadata persists but our in-memory endpoint list doesn't.
         // If the endpoint is missing, recreate it transparently.
-        let endpoint_exists = {
-            let ep_map = network_ref.endpoint_list.read();
-            ep_map.contains_key(&epuid)
+        let (endpoint_exists, saved) = {
+            let options =
+                network.lock().endpoint_list.read().get(&epuid).map(|ep| ep.options.clone());
+            match options {
+                Some(options) if !options.is_null() => (true, None),
+                Some(_) => (true, self.saved_endpoint(&nuid, &epuid)),
+                None => (false, self.saved_endpoint(&nuid, &epuid)),
+            }
         };
-
-        // Release read lock before potentially acquiring write lock
-        drop(map);
+        let saved_options = saved.as_ref().map(|ep| ep.options.clone()).unwrap_or_default();
+        // One rebuilt from the kernel keeps its direction and netem
+        if endpoint_exists {
+            network.lock().restore_endpoint_options(&epuid, saved_options.clone())?;
+        }
 
         // If endpoint doesn't exist, we need to create it
-        // Upgrade to write lock only if necessary
         if !endpoint_exists {
-            println!(" -> Endpoint not found in memory (likely post-reboot), recreating...");
-            
-            // Acquire write lock on network list to add endpoint
-            let mut map_write = self.network_list.write();
-            let n = match map_write.get_mut(&nuid) {
-                Some(network) => network,
-                None => {
-                    drop(map_write);
-                    eprintln!(" !! Network disappeared during endpoint creation");
-                    return Err(Error);
-                }
-            };
-            
-            // Double-check: another thread may have created the endpoint while we waited for write lock
+            info!("Endpoint not found in memory (likely post-reboot), recreating...");
+
+            // Names held by other networks are gathered before this network is locked
+            let mut claimed = claimed_interface_names(&self.network_list.read(), Some(&nuid));
+            let mut n = network.lock();
+            claimed.extend(n.interface_names());
+
+            // Double-check: another thread may have created the endpoint while we waited
             let still_missing = {
                 let ep_map = n.endpoint_list.read();
                 !ep_map.contains_key(&epuid)
             };
             
             if still_missing {
-                // Recreate the endpoint
-                let ep = Endpoint::new(epuid.clone());
+                // Recreate the endpoint; a direct network pairs it with its partner
+                let ep = match n.new_endpoint(
+                    &nuid,
+                    epuid.clone(),
+                    saved_options,
+                    self.config.endpoint_short_name_len,
+                    &claimed,
+                    saved.and_then(|ep| ep.names()),
+                ) {
+                    Ok(ep) => ep,
+                    Err(e) => {
+                        drop(n);
+                        error!("Failed to recreate endpoint after reboot: {}", e);
+                        return Err(e);
+                    }
+                };
                 n.endpoint_add(ep);
-                println!(" -> Successfully recreated endpoint after reboot");
+                self.save_endpoints(&nuid, &n);
+                info!("Successfully recreated endpoint after reboot");
+                self.events.record(LifecycleEvent::EndpointRecovered {
+                    nuid: nuid.clone(),
+                    epuid: epuid.clone(),
+                });
             } else {
-                println!(" -> Endpoint was created by another thread, continuing");
+                info!("Endpoint was created by another thread, continuing");
             }
             
-            // Release write lock before continuing
-            drop(map_write);
+            // Release the network lock before continuing
+            drop(n);
         }
 
         // Now perform the actual endpoint attach operation
-        // Acquire write lock one final time for the attach operation
-        let mut map = self.network_list.write();
-        match map.get_mut(&nuid) {
-            Some(n) => {
-                let peer = match serde_json::from_str::<serde_json::Value>(&options) {
-                    Ok(v) => match v["vxcan.peer"].as_str() {
-                        Some(u) => u.to_string(),
-                        None => String::new(),
-                    },
-                    Err(_) => String::new(),
-                };
+        // Lock the network one final time for the attach operation
+        let mut n = network.lock();
+        let peer = match serde_json::from_str::<serde_json::Value>(&options) {
+            Ok(v) => match v["vxcan.peer"].as_str() {
+                Some(u) => u.to_string(),
+                None => String::new(),
+            },
+            Err(_) => String::new(),
+        };
+
+        let namespace = String::new();
 
-                let namespace = String::new();
+        // Add the endpoint to the network (or reattach after reboot)
+        let rsp = n
+            .endpoint_attach(epuid.clone(), namespace, peer, self.config.minimal_repair)
+            .map_err(|e| RustyCanError::AttachFailed {
+                epuid: epuid.clone(),
+                source: Some(Box::new(e)),
+            })?;
+        n.refresh_ttl(self.clock.now());
+        warn_reserved_prefix(&epuid, rsp.dst_prefix(), &self.config.reserved_prefixes);
+        self.events.record(LifecycleEvent::EndpointAttached { nuid, epuid });
+        Ok(rsp)
+    }
 
-                // Add the endpoint to the network (or reattach after reboot)
-                let rsp = n.endpoint_attach(epuid, namespace, peer)?;
-                Ok(rsp)
+    pub fn endpoint_detach(&self, nuid: String, epuid: String) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+
+        let Some(network) = self.network(&nuid) else {
+            if self.config.missing_network_detach == MissingNetworkPolicy::Error {
+                return Err(RustyCanError::NetworkNotFound(nuid));
             }
-            None => {
-                eprintln!(" !! Network not found during endpoint attach (should not happen)");
-                Err(Error)
+            warn!(
+                network_id = nuid.as_str(), endpoint_id = epuid.as_str();
+                "Network {} not found while detaching endpoint {}, cleaning up by name",
+                nuid, epuid
+            );
+            self.cleanup_orphan_endpoint(&epuid);
+            self.events.record(LifecycleEvent::EndpointDetached { nuid, epuid });
+            return Ok(());
+        };
+
+        // Detach the endpoint from the network, locking only that network
+        let mut n = network.lock();
+        if n.resolve_reconstructed(&epuid, self.config.endpoint_short_name_len) {
+            self.save_endpoints(&nuid, &n);
+        }
+        n.endpoint_detach(epuid.clone())?;
+        self.events.record(LifecycleEvent::EndpointDetached { nuid, epuid });
+        Ok(())
+    }
+
+    /// Best-effort removal of the vxcan pair of an endpoint whose network is gone
+    /// The pair is found by the endpoint naming scheme and left alone if any managed
+    /// network still accounts for the name; its cangw jobs go with the interface
+    /// The network's `vxcan.prefix` is gone with it, so every prefix still in use is tried
+    fn cleanup_orphan_endpoint(&self, epuid: &str) {
+        let claimed = claimed_interface_names(&self.network_list.read(), None);
+        let short_len = self.config.endpoint_short_name_len;
+        for prefix in endpoint_prefixes(&self.networks()) {
+            for (device, _) in Endpoint::candidate_interface_names(&prefix, epuid, short_len) {
+                if claimed.contains(&device) || !LinkStatus::query(&device).exists {
+                    continue;
+                }
+                match netlink::delete_link(&device) {
+                    Ok(()) => info!("Removed orphaned endpoint interface {}", device),
+                    Err(e) => error!("Unable to remove orphaned interface {}: {}", device, e),
+                }
+                return;
+            }
+        }
+        info!("No interface left behind by endpoint {}", epuid);
+    }
+
+    /// Detach every endpoint on a network in one locked operation, leaving the network intact
+    /// Returns the number of endpoints detached
+    pub fn detach_all(&self, nuid: &str, remove_interfaces: bool) -> Result<usize, RustyCanError> {
+        let _op = self.begin_change()?;
+        let network = self
+            .network(nuid)
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))?;
+
+        let mut n = network.lock();
+        let detached = n.detach_all(remove_interfaces);
+        // Removed endpoints must not be recreated from the state file after a restart
+        self.save_endpoints(nuid, &n);
+        drop(n);
+        info!(
+            network_id = nuid;
+            "Detached {} endpoints from network {}", detached.len(), nuid
+        );
+        for epuid in detached.iter() {
+            self.events.record(LifecycleEvent::EndpointDetached {
+                nuid: nuid.to_string(),
+                epuid: epuid.clone(),
+            });
+            if remove_interfaces {
+                self.events.record(LifecycleEvent::EndpointDeleted {
+                    nuid: nuid.to_string(),
+                    epuid: epuid.clone(),
+                });
+            }
+        }
+        Ok(detached.len())
+    }
+
+    /// Delete every network whose TTL has run out, along with its rules and endpoints
+    /// Returns the ids of the networks deleted
+    pub fn expire_networks(&self) -> Vec<String> {
+        let now = self.clock.now();
+        let expired: Vec<String> = self
+            .networks()
+            .into_iter()
+            .filter(|(_, n)| n.lock().expired(now))
+            .map(|(nuid, _)| nuid)
+            .collect();
+
+        let mut deleted = Vec::new();
+        for nuid in expired {
+            info!(network_id = nuid.as_str(); "Network {} reached its TTL, deleting it", nuid);
+            let result = self
+                .detach_all(&nuid, true)
+                .and_then(|_| self.network_delete(nuid.clone()));
+            match result {
+                Ok(()) => {
+                    self.events.record(LifecycleEvent::NetworkExpired { nuid: nuid.clone() });
+                    deleted.push(nuid);
+                }
+                Err(e) => error!("Unable to delete expired network {}: {}", nuid, e),
             }
         }
+        deleted
     }
 
-    pub fn endpoint_detach(&self, nuid: String, epuid: String) {
-        // Lock the network list
-        let mut map = self.network_list.write();
-        match map.get_mut(&nuid) {
-            Some(n) => {
-                // Detach the endpoint from the network
-                n.endpoint_detach(epuid)
+    /// Confirm that Docker moved an attached endpoint's peer out of the host namespace
+    /// A peer still on the host after Join means the namespace move failed
+    pub fn verify_peer_placement(&self, nuid: &str, epuid: &str) {
+        let peer = {
+            let network = self.network(nuid);
+            let peer = network.as_ref().and_then(|n| {
+                n.lock().endpoint_list.read().get(epuid).map(|ep| ep.peer.clone())
+            });
+            match peer {
+                Some(p) => p,
+                // Detached or deleted in the meantime; nothing to check
+                None => return,
             }
-            None => (),
         };
+
+        match link::interface_exists(&peer) {
+            Ok(false) => info!("Peer {} was moved into the container namespace", peer),
+            Ok(true) => {
+                METRICS
+                    .peer_placement_failures
+                    .inc_with(&[("epuid", epuid), ("peer", &peer)]);
+                warn!(
+                    "Peer {} of endpoint {} is still in the host namespace after Join",
+                    peer, epuid
+                );
+            }
+            Err(e) => error!("Unable to verify placement of peer {}: {}", peer, e),
+        }
     }
 
-    fn options_parse(&self, options: String) -> Result<(String, String, String), Error> {
-        match serde_json::from_str::<serde_json::Value>(&options) {
-            Ok(v) => {
-                let device = match v["vxcan.dev"].as_str() {
-                    Some(u) => u.to_string(),
-                    None => {
-                        println!(" !! Error parsing vxcan.dev option: {}", v["vxcan.dev"]);
-                        String::from("vcan")
+    /// Snapshot of every network and its endpoints, ordered by network id
+    pub fn list_networks(&self) -> Vec<NetworkSummary> {
+        let mut list: Vec<NetworkSummary> = self
+            .networks()
+            .iter()
+            .map(|(nuid, n)| n.lock().summary(nuid))
+            .collect();
+        list.sort_by(|a, b| a.nuid.cmp(&b.nuid));
+        list
+    }
+
+    /// Totals of what the plugin holds across every network and endpoint
+    pub fn resource_report(&self) -> ResourceReport {
+        let mut report = ResourceReport {
+            state_bytes: self.state.size(),
+            interfaces_created: self.pool.as_ref().map_or(0, |p| p.free_count()),
+            ..Default::default()
+        };
+        for (_, n) in self.networks() {
+            let r = n.lock().resources();
+            report.networks += 1;
+            report.endpoints += r.endpoints;
+            report.interfaces_created += r.interfaces_created;
+            report.rules += r.rules;
+            report.cangw_jobs += r.cangw_jobs;
+            report.memory_bytes += r.memory_bytes;
+        }
+        report
+    }
+
+    /// Number of networks the plugin manages
+    pub fn network_count(&self) -> usize {
+        self.network_list.read().len()
+    }
+
+    /// Number of endpoints across every network
+    pub fn endpoint_count(&self) -> usize {
+        self.networks()
+            .iter()
+            .map(|(_, n)| n.lock().resources().endpoints)
+            .sum()
+    }
+
+    /// Number of cangw jobs the plugin holds across every network
+    pub fn cangw_rule_count(&self) -> usize {
+        self.networks()
+            .iter()
+            .map(|(_, n)| n.lock().resources().cangw_jobs)
+            .sum()
+    }
+
+    /// Detailed view of a single network, including its kernel state and tracked rules
+    pub fn describe_network(&self, nuid: &str) -> Option<NetworkDescription> {
+        let network = self.network(nuid)?;
+        let n = network.lock();
+        Some(n.describe(nuid, self.clock.now()))
+    }
+
+    /// Interface details Docker shows for an endpoint in `docker network inspect`
+    /// (device, peer, kernel ifindexes, MTU); None if the network or endpoint is unknown
+    pub fn endpoint_oper_info(&self, nuid: &str, epuid: &str) -> Option<EndpointSummary> {
+        let network = self.network(nuid)?;
+        let n = network.lock();
+        n.describe_endpoint(epuid)
+    }
+
+    /// Put a network's bus into a simulated bus-off state for fault-injection testing
+    pub fn trigger_busoff(&self, nuid: &str) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        let network = self
+            .network(nuid)
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))?;
+        network.lock().trigger_busoff()?;
+        self.events.record(LifecycleEvent::NetworkBusOff { nuid: nuid.to_string() });
+        Ok(())
+    }
+
+    /// Bring a network's bus back from a simulated bus-off state
+    pub fn recover_busoff(&self, nuid: &str) -> Result<(), RustyCanError> {
+        let _op = self.begin_change()?;
+        let network = self
+            .network(nuid)
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))?;
+        network.lock().recover_busoff()?;
+        self.events.record(LifecycleEvent::NetworkBusRecovered { nuid: nuid.to_string() });
+        Ok(())
+    }
+
+    /// Report which network or endpoint owns an interface, along with its kernel state
+    pub fn inspect_interface(&self, ifname: &str) -> Option<InterfaceInfo> {
+        let networks = self.networks();
+        networks
+            .iter()
+            .find_map(|(nuid, n)| n.lock().inspect_interface(nuid, ifname))
+    }
+
+    /// Render plugin metrics, including per-network and per-endpoint interface state
+    pub fn render_metrics(&self, format: Format) -> String {
+        let max_series = self.config.metrics_max_series;
+        let mut networks = StateGauge::new(
+            "rustycan_network_interface_up",
+            "Whether a network's vcan interface exists and is up",
+            &["nuid"],
+            max_series,
+        );
+        let mut endpoints = StateGauge::new(
+            "rustycan_endpoint_interface_up",
+            "Whether an endpoint's vxcan interface exists and is up",
+            &["nuid", "epuid"],
+            max_series,
+        );
+
+        for (nuid, n) in self.networks() {
+            n.lock().record_interface_state(&nuid, &mut networks, &mut endpoints);
+        }
+
+        METRICS.networks.set(self.network_count() as u64);
+        METRICS.endpoints.set(self.endpoint_count() as u64);
+        METRICS.cangw_rules.set(self.cangw_rule_count() as u64);
+
+        let mut out = METRICS.render(format);
+        networks.render(&mut out);
+        endpoints.render(&mut out);
+        if format == Format::OpenMetrics {
+            out.push_str("# EOF\n");
+        }
+        out
+    }
+
+    /// Best-effort rebuild of endpoints and their network membership from the kernel
+    /// An endpoint-named vxcan device with a cangw rule to a known network's bus is added
+    /// to that network, along with the kernel rules between its interfaces. This is the
+    /// last resort when neither the state file nor Docker describes the endpoints
+    pub fn reconstruct_from_kernel(&self) -> Result<ReconstructReport, RustyCanError> {
+        let kernel_rules = cangw::list().map_err(RustyCanError::RuleQuery)?;
+        let kernel_ifcs: HashSet<String> = link::interface_names()?.into_iter().collect();
+
+        let networks = self.networks();
+        let prefixes = endpoint_prefixes(&networks);
+        let is_endpoint = |name: &str| {
+            Endpoint::classify_interface_name(name, prefixes.iter().map(String::as_str))
+                == Some(InterfaceRole::EndpointDevice)
+        };
+
+        // Bus interface -> endpoint devices with a rule to or from it
+        let mut membership: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for rule in kernel_rules.iter() {
+            let (bus, device) = match (is_endpoint(&rule.src), is_endpoint(&rule.dst)) {
+                (true, false) => (&rule.dst, &rule.src),
+                (false, true) => (&rule.src, &rule.dst),
+                _ => continue,
+            };
+            if kernel_ifcs.contains(device) {
+                membership
+                    .entry(bus.clone())
+                    .or_default()
+                    .insert(device.clone());
+            }
+        }
+
+        let mut report = ReconstructReport::default();
+        for (bus, devices) in membership {
+            let (nuid, network) = match networks.iter().find(|(_, n)| n.lock().interface() == bus) {
+                Some(found) => found,
+                None => {
+                    report.unmatched_buses.push(bus);
+                    continue;
+                }
+            };
+            let mut n = network.lock();
+
+            let known: HashSet<String> = n.managed_interfaces().into_iter().collect();
+            let reconstructed = report.endpoints.len();
+            for device in devices.iter().filter(|d| !known.contains(*d)) {
+                if let Some(ep) = Endpoint::from_kernel(device, n.prefix()) {
+                    info!(
+                        "Reconstructed endpoint {} on network {} from kernel state",
+                        device, nuid
+                    );
+                    self.events.record(LifecycleEvent::EndpointRecovered {
+                        nuid: nuid.clone(),
+                        epuid: ep.uid.clone(),
+                    });
+                    n.endpoint_add(ep);
+                    report.endpoints.push((nuid.clone(), device.clone()));
+                }
+            }
+            if report.endpoints.len() > reconstructed {
+                self.save_endpoints(nuid, &n);
+            }
+
+            let managed: HashSet<String> = n.managed_interfaces().into_iter().collect();
+            for rule in kernel_rules.iter().filter(|r| !r.fd) {
+                if managed.contains(&rule.src)
+                    && managed.contains(&rule.dst)
+                    && n.adopt_rule(&rule.src, &rule.dst)
+                {
+                    report.rules += 1;
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Interfaces named like plugin interfaces that no network, endpoint or the pool owns
+    /// Read-only: nothing is removed. Network interfaces are never reported, since their
+    /// names are user-chosen and can't be told apart from interfaces the plugin should adopt
+    pub fn list_orphans(&self) -> Result<Vec<OrphanInterface>, RustyCanError> {
+        let kernel = link::interface_names()?;
+
+        let owned = claimed_interface_names(&self.network_list.read(), None);
+        let prefixes = endpoint_prefixes(&self.networks());
+
+        let mut orphans: Vec<OrphanInterface> = kernel
+            .into_iter()
+            .filter(|name| !owned.contains(name))
+            .filter_map(|name| {
+                let kind =
+                    Endpoint::classify_interface_name(&name, prefixes.iter().map(String::as_str));
+                let reason = match kind {
+                    Some(InterfaceRole::EndpointPeer) => OrphanReason::UntrackedEndpointPeer,
+                    Some(_) => OrphanReason::UntrackedEndpointDevice,
+                    None if InterfacePool::is_pool_name(&name)
+                        && !self.pool.as_ref().is_some_and(|p| p.holds(&name)) =>
+                    {
+                        OrphanReason::UntrackedPoolInterface
                     }
+                    None => return None,
                 };
-                let peer = match v["vxcan.peer"].as_str() {
-                    Some(u) => u.to_string(),
+                Some(OrphanInterface {
+                    kernel: LinkStatus::query(&name),
+                    name,
+                    reason,
+                })
+            })
+            .collect();
+        orphans.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(orphans)
+    }
+
+    /// Gateway jobs every network expects in the kernel, and the interfaces they manage
+    pub fn intended_rules(&self) -> (BTreeSet<KernelRule>, HashSet<String>) {
+        let mut rules = BTreeSet::new();
+        let mut managed = HashSet::new();
+        for (_, n) in self.networks() {
+            let n = n.lock();
+            rules.extend(n.expected_rules());
+            managed.extend(n.managed_interfaces());
+        }
+        (rules, managed)
+    }
+
+    /// Ids of all networks currently managed
+    pub fn network_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self.network_list.read().keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    /// Probe that frames sent on a network's bus reach each attached endpoint
+    /// Probes run without holding the network lock, as each can take a while
+    pub fn verify_forwarding(&self, nuid: &str) -> Result<bool, RustyCanError> {
+        let paths = self
+            .network(nuid)
+            .map(|n| n.lock().forwarding_paths())
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))?;
+
+        for (src, dst) in paths {
+            if !probe::forwarding(&src, &dst).map_err(RustyCanError::Probe)? {
+                METRICS.forwarding_failures.inc_with(&[("nuid", nuid), ("dst", &dst)]);
+                warn!("Probe frame on {} was not forwarded to {}", src, dst);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Per-rule handled/dropped/deleted frame counters for a network's gateway jobs
+    pub fn rule_stats(&self, nuid: &str) -> Result<Vec<RuleStats>, RustyCanError> {
+        let kernel = cangw::list_stats().map_err(RustyCanError::RuleQuery)?;
+        self.network(nuid)
+            .map(|n| n.lock().rule_stats(&kernel))
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))
+    }
+
+    /// Which of a network's expected rules the kernel has, lacks, or has untracked
+    pub fn diagnose_rules(&self, nuid: &str) -> Result<RuleDiagnosis, RustyCanError> {
+        let kernel = cangw::list().map_err(RustyCanError::RuleQuery)?;
+        self.network(nuid)
+            .map(|n| n.lock().diagnose_rules(&kernel))
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))
+    }
+
+    /// Re-install any of a network's expected rules missing from the kernel
+    /// Returns the number of gateway jobs re-installed
+    pub fn reconcile_rules(&self, nuid: &str) -> Result<usize, RustyCanError> {
+        let kernel = cangw::list().map_err(RustyCanError::RuleQuery)?;
+
+        let network = self
+            .network(nuid)
+            .ok_or_else(|| RustyCanError::NetworkNotFound(nuid.to_string()))?;
+        let n = network.lock();
+        let intended: BTreeSet<KernelRule> = n.expected_rules().into_iter().collect();
+        let managed: HashSet<String> = n.managed_interfaces().into_iter().collect();
+
+        let diff = diff_rules(&intended, &managed, &kernel);
+        let mut reinstalled = 0;
+        for rule in diff.missing.iter() {
+            if n.reinstall_rule(rule) {
+                info!(
+                    "Re-installed cangw rule {} -> {} (fd={})",
+                    rule.src, rule.dst, rule.fd
+                );
+                reinstalled += 1;
+            } else {
+                error!(
+                    "Failed to re-install cangw rule {} -> {} (fd={})",
+                    rule.src, rule.dst, rule.fd
+                );
+            }
+        }
+        Ok(reinstalled)
+    }
+
+    /// Bring the kernel back in line with every known network: recreate missing bus
+    /// interfaces, then re-install missing cangw rules
+    /// A network that fails is reported and the rest are still reconciled
+    pub fn reconcile(&self) -> Result<BTreeMap<String, NetworkReconcile>, RustyCanError> {
+        let _op = self.begin_change()?;
+        let saved = self.state.load()?.unwrap_or_default();
+        let mut report = BTreeMap::new();
+        for (nuid, network) in self.networks() {
+            let mut entry = NetworkReconcile::default();
+            if let Some(config) = saved.get(&nuid) {
+                match self.restore_network_endpoints(&nuid, &network, &config.endpoints) {
+                    Ok(restored) => entry.endpoints_restored = restored,
+                    Err(e) => entry.error = Some(e.to_string()),
+                }
+            }
+            let recreated = {
+                let mut n = network.lock();
+                if n.is_direct() {
+                    Ok(false)
+                } else {
+                    n.ensure_network_interface_exists()
+                }
+            };
+            match recreated.map(|r| entry.interface_recreated = r) {
+                Ok(()) => match self.reconcile_rules(&nuid) {
+                    Ok(reinstalled) => entry.rules_reinstalled = reinstalled,
+                    Err(e) => entry.error = Some(e.to_string()),
+                },
+                Err(e) => entry.error = Some(e.to_string()),
+            }
+            let changed = entry.interface_recreated
+                || entry.rules_reinstalled > 0
+                || entry.endpoints_restored > 0;
+            if changed {
+                info!(
+                    network_id = nuid.as_str();
+                    "Reconciled network {} (interface recreated: {}, {} rules re-installed, \
+                     {} endpoints restored)",
+                    nuid, entry.interface_recreated, entry.rules_reinstalled,
+                    entry.endpoints_restored
+                );
+            }
+            report.insert(nuid, entry);
+        }
+        Ok(report)
+    }
+
+    /// Re-install a tracked gateway job on whichever network owns it
+    pub fn reinstall_rule(&self, rule: &KernelRule) -> bool {
+        self.networks().iter().any(|(_, n)| n.lock().reinstall_rule(rule))
+    }
+
+    /// Reject Docker-supplied options blobs larger than the configured limit before parsing them
+    fn check_options_size(&self, options: &str) -> Result<(), RustyCanError> {
+        let limit = self.config.max_options_bytes;
+        if options.len() > limit {
+            warn!("Options string of {} bytes exceeds the {} byte limit", options.len(), limit);
+            return Err(RustyCanError::OptionsTooLarge {
+                len: options.len(),
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    fn options_parse(&self, options: String) -> Result<NetworkConfig, RustyCanError> {
+        self.check_options_size(&options)?;
+        parse_network_options(&options, &self.config, &self.gw_params)
+    }
+}
+
+/// Network configuration from CreateNetwork options, checked against the plugin's
+/// settings and the can-gw module's limits
+fn parse_network_options(
+    options: &str,
+    plugin: &PluginConfig,
+    gw_params: &ModuleParams,
+) -> Result<NetworkConfig, RustyCanError> {
+    match serde_json::from_str::<serde_json::Value>(options) {
+        Ok(v) => {
+            // vxcan.link bridges to an existing interface: vxcan.dev with vxcan.realname
+            let link = match &v["vxcan.link"] {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(s) if !s.trim().is_empty() => {
+                    Some(s.trim().to_string())
+                }
+                other => {
+                    return Err(RustyCanError::InvalidOptions(format!(
+                        "vxcan.link must name an existing CAN interface, got {other}"
+                    )))
+                }
+            };
+            if link.is_some() && !v["vxcan.dev"].is_null() {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "vxcan.link and vxcan.dev can't both be set",
+                )));
+            }
+            let device = match (&link, v["vxcan.dev"].as_str()) {
+                (Some(link), _) => link.clone(),
+                (None, Some(u)) => u.to_string(),
+                (None, None) => {
+                    error!("Error parsing vxcan.dev option: {}", v["vxcan.dev"]);
+                    String::from("vcan")
+                }
+            };
+            let peer = match v["vxcan.peer"].as_str() {
+                Some(u) => u.to_string(),
+                None => {
+                    error!("Error parsing vxcan.peer option: {}", v["vxcan.peer"]);
+                    String::from("vcanp")
+                }
+            };
+            // Canonical decimal, so `7`, `007` and `0x7` name the same interface
+            let extended = option_bool(&v, "vxcan.extended")?.unwrap_or(false);
+            let canid = option_canid(&v, extended)?.unwrap_or(0).to_string();
+
+            let persist = option_bool(&v, "vxcan.persist")?.unwrap_or(false);
+            let ephemeral = option_bool(&v, "vxcan.ephemeral")?.unwrap_or(false);
+            let realname = match option_bool(&v, "vxcan.realname")? {
+                Some(false) if link.is_some() => {
+                    return Err(RustyCanError::InvalidOptions(String::from(
+                        "vxcan.link always adopts the named interface; \
+                         vxcan.realname=false can't be used with it",
+                    )))
+                }
+                _ if link.is_some() => true,
+                Some(realname) => realname,
+                None => Network::is_real_can_device(&device),
+            };
+
+            // cangw's -l is a hop limit; the kernel gateway has no rate limit to offer
+            if !v["vxcan.ratelimit"].is_null() {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "vxcan.ratelimit is not supported: cangw cannot rate-limit frames \
+                     (its -l flag limits hops, see vxcan.hoplimit)",
+                )));
+            }
+            let hoplimit = option_u8(&v, "vxcan.hoplimit")?;
+            if let Some(hops) = hoplimit {
+                gw_params
+                    .validate_hoplimit(hops)
+                    .map_err(RustyCanError::InvalidOptions)?;
+            }
+
+            let mode = match &v["vxcan.mode"] {
+                serde_json::Value::Null => NetworkMode::default(),
+                m => m.as_str().and_then(NetworkMode::parse).ok_or_else(|| {
+                    RustyCanError::InvalidOptions(format!(
+                        "vxcan.mode must be \"bus\" or \"direct\", got {m}"
+                    ))
+                })?,
+            };
+            if mode == NetworkMode::Direct && realname {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "a real CAN interface can't be used by a direct network",
+                )));
+            }
+
+            let filter = match &v["vxcan.filter"] {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(spec) => {
+                    Some(cangw::parse_filters(spec).map_err(RustyCanError::InvalidOptions)?)
+                }
+                f => {
+                    return Err(RustyCanError::InvalidOptions(format!(
+                        "vxcan.filter must be a string, got {f}"
+                    )))
+                }
+            };
+            if mode == NetworkMode::Direct && filter.is_some() {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "direct networks have no gateway rules to filter",
+                )));
+            }
+
+            let modify = match &v["vxcan.modify"] {
+                serde_json::Value::Null => None,
+                serde_json::Value::String(spec) => Some(
+                    cangw::parse_modifications(spec).map_err(RustyCanError::InvalidOptions)?,
+                ),
+                m => {
+                    return Err(RustyCanError::InvalidOptions(format!(
+                        "vxcan.modify must be a string, got {m}"
+                    )))
+                }
+            };
+            if mode == NetworkMode::Direct && modify.is_some() {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "direct networks have no gateway rules to modify frames",
+                )));
+            }
+
+            let ttl = option_u64(&v, "vxcan.ttl")?;
+            if ttl == Some(0) {
+                return Err(RustyCanError::InvalidOptions(String::from(
+                    "vxcan.ttl must be at least 1 second",
+                )));
+            }
+            let ttl_refresh = option_bool(&v, "vxcan.ttl.refresh")?.unwrap_or(false);
+
+            let bitrate = option_u64(&v, "vxcan.bitrate")?;
+            let bitrate = match bitrate {
+                None => None,
+                Some(_) if !realname => {
+                    return Err(RustyCanError::InvalidOptions(String::from(
+                        "vxcan.bitrate only applies to a real CAN interface (vxcan.realname)",
+                    )))
+                }
+                Some(b) => match link::STANDARD_BITRATES.iter().find(|s| u64::from(**s) == b) {
+                    Some(s) => Some(*s),
                     None => {
-                        println!(" !! Error parsing vxcan.peer option: {}", v["vxcan.peer"]);
-                        String::from("vcanp")
+                        return Err(RustyCanError::InvalidOptions(format!(
+                            "vxcan.bitrate {b} is not a standard CAN bitrate ({})",
+                            link::STANDARD_BITRATES
+                                .iter()
+                                .map(|s| s.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )))
+                    }
+                },
+            };
+
+            let fd = option_bool(&v, "vxcan.fd")?.unwrap_or(false);
+            let dbitrate = match option_u64(&v, "vxcan.dbitrate")? {
+                None => None,
+                Some(_) if !fd || bitrate.is_none() => {
+                    return Err(RustyCanError::InvalidOptions(String::from(
+                        "vxcan.dbitrate needs vxcan.fd=true and a vxcan.bitrate",
+                    )))
+                }
+                Some(d) => match link::STANDARD_DBITRATES.iter().find(|s| u64::from(**s) == d) {
+                    Some(s) if Some(*s) >= bitrate => Some(*s),
+                    Some(s) => {
+                        return Err(RustyCanError::InvalidOptions(format!(
+                            "vxcan.dbitrate {s} is below the nominal bitrate"
+                        )))
                     }
-                };
-                let canid = match v["vxcan.id"].as_str() {
-                    Some(u) => u.to_string(),
                     None => {
-                        println!(" !! Error parsing vxcan.dev option: {}", v["vxcan.dev"]);
-                        String::from("0")
+                        return Err(RustyCanError::InvalidOptions(format!(
+                            "vxcan.dbitrate {d} is not a standard CAN FD data bitrate ({})",
+                            link::STANDARD_DBITRATES
+                                .iter()
+                                .map(|s| s.to_string())
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )))
                     }
+                },
+            };
+
+            // vcan hands every frame to local sockets regardless; only a controller has a mode
+            let loopback = match option_bool(&v, "vxcan.loopback")? {
+                Some(_) if !realname => {
+                    return Err(RustyCanError::InvalidOptions(String::from(
+                        "vxcan.loopback only applies to a real CAN interface (vxcan.realname)",
+                    )))
+                }
+                loopback => loopback,
+            };
+
+            let prefix = match &v["vxcan.prefix"] {
+                serde_json::Value::Null => String::from(DEFAULT_PREFIX),
+                serde_json::Value::String(p) => p.clone(),
+                p => {
+                    return Err(RustyCanError::InvalidOptions(format!(
+                        "vxcan.prefix must be a string, got {p}"
+                    )))
+                }
+            };
+            Endpoint::validate_prefix(&prefix, plugin.endpoint_short_name_len)?;
+
+            let config = NetworkConfig {
+                device,
+                peer,
+                canid,
+                persist,
+                ephemeral,
+                realname,
+                link: link.is_some(),
+                hoplimit,
+                mode,
+                filter,
+                modify,
+                ttl,
+                ttl_refresh,
+                bitrate,
+                fd,
+                extended,
+                dbitrate,
+                loopback,
+                prefix,
+                endpoints: Vec::new(),
+            };
+
+            // The bus name is vxcan.dev with vxcan.id appended, unless it's a real device
+            let ifname = Network::interface_name(&config);
+            if !ifname.is_empty() {
+                let option = match (&link, realname) {
+                    (Some(_), _) => "vxcan.link",
+                    (None, true) => "vxcan.dev",
+                    (None, false) => "vxcan.dev and vxcan.id",
                 };
+                link::validate_interface_name(&ifname, option)?;
+            }
+            Ok(config)
+        }
+        Err(e) => Err(RustyCanError::InvalidOptions(e.to_string())),
+    }
+}
+
+/// Every interface name held by a network bus, endpoint device or endpoint peer
+/// `skip` leaves out a network the caller already holds locked
+/// Why a new network `uid` on interface `ifname` can't be added to the map, if it can't
+/// A second network on the same device and id would silently reuse the first's
+/// interface, so its endpoints would cross-talk; it is refused instead
+fn interface_conflict(map: &NetworkMap, uid: &str, ifname: &str) -> Option<RustyCanError> {
+    if ifname.is_empty() {
+        return None;
+    }
+    if let Some((other, _)) = map.iter().find(|(_, n)| n.lock().interface() == ifname) {
+        warn!(
+            network_id = uid;
+            "Network {} would use interface {}, which network {} already uses",
+            uid, ifname, other
+        );
+        return Some(RustyCanError::InterfaceInUse {
+            name: ifname.to_string(),
+            nuid: other.clone(),
+        });
+    }
+    if claimed_interface_names(map, None).contains(ifname) {
+        return Some(RustyCanError::InterfaceNameTaken(ifname.to_string()));
+    }
+    None
+}
+
+fn claimed_interface_names(map: &NetworkMap, skip: Option<&str>) -> HashSet<String> {
+    map.iter()
+        .filter(|(nuid, _)| Some(nuid.as_str()) != skip)
+        .flat_map(|(_, n)| n.lock().interface_names())
+        .collect()
+}
+
+/// Network options, in the form `options_parse` accepts, that reproduce a configuration
+fn network_options(config: &NetworkConfig) -> serde_json::Value {
+    let mut options = serde_json::json!({
+        "vxcan.dev": config.device,
+        "vxcan.peer": config.peer,
+        "vxcan.id": config.canid,
+        "vxcan.persist": config.persist,
+        "vxcan.ephemeral": config.ephemeral,
+        "vxcan.realname": config.realname,
+    });
+    // vxcan.link stands in for vxcan.dev and vxcan.realname, and refuses them
+    if let (true, Some(map)) = (config.link, options.as_object_mut()) {
+        map.remove("vxcan.dev");
+        map.remove("vxcan.realname");
+        map.insert(String::from("vxcan.link"), serde_json::json!(config.device));
+    }
+    if let Some(hops) = config.hoplimit {
+        options["vxcan.hoplimit"] = serde_json::json!(hops);
+    }
+    if let Some(filter) = &config.filter {
+        options["vxcan.filter"] = serde_json::json!(filter);
+    }
+    if let Some(modify) = &config.modify {
+        options["vxcan.modify"] = serde_json::json!(modify);
+    }
+    if let Some(ttl) = config.ttl {
+        options["vxcan.ttl"] = serde_json::json!(ttl);
+        options["vxcan.ttl.refresh"] = serde_json::json!(config.ttl_refresh);
+    }
+    if config.mode != NetworkMode::Bus {
+        options["vxcan.mode"] = serde_json::json!(config.mode);
+    }
+    if let Some(bitrate) = config.bitrate {
+        options["vxcan.bitrate"] = serde_json::json!(bitrate);
+    }
+    if config.fd {
+        options["vxcan.fd"] = serde_json::json!(true);
+    }
+    if config.extended {
+        options["vxcan.extended"] = serde_json::json!(true);
+    }
+    if let Some(dbitrate) = config.dbitrate {
+        options["vxcan.dbitrate"] = serde_json::json!(dbitrate);
+    }
+    if let Some(loopback) = config.loopback {
+        options["vxcan.loopback"] = serde_json::json!(loopback);
+    }
+    if config.prefix != DEFAULT_PREFIX {
+        options["vxcan.prefix"] = serde_json::json!(config.prefix);
+    }
+    options
+}
+
+/// Endpoint interface name prefixes in use: the default and every network's own
+fn endpoint_prefixes(networks: &[(String, Arc<Mutex<Network>>)]) -> BTreeSet<String> {
+    let mut prefixes: BTreeSet<String> =
+        networks.iter().map(|(_, n)| n.lock().prefix().to_string()).collect();
+    prefixes.insert(String::from(DEFAULT_PREFIX));
+    prefixes
+}
+
+/// Read a boolean option that Docker may pass either as a JSON bool or a string
+fn option_bool(v: &serde_json::Value, key: &str) -> Result<Option<bool>, RustyCanError> {
+    match &v[key] {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::Bool(b) => Ok(Some(*b)),
+        serde_json::Value::String(s) => match s.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Ok(Some(true)),
+            "false" | "0" | "no" | "off" => Ok(Some(false)),
+            _ => Err(RustyCanError::InvalidOptions(format!(
+                "{key} must be true or false, got '{s}'"
+            ))),
+        },
+        other => Err(RustyCanError::InvalidOptions(format!(
+            "{key} must be true or false, got {other}"
+        ))),
+    }
+}
+
+/// Whether a DstPrefix could name the same in-container interface as something
+/// else in the container: libnetwork appends an index, so `eth` becomes `eth0`
+/// The plugin can't see inside the container, so this only flags likely clashes
+fn is_reserved_prefix(prefix: &str, reserved: &[String]) -> bool {
+    prefix.is_empty()
+        || reserved.iter().any(|r| {
+            prefix
+                .strip_prefix(r.as_str())
+                .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit()))
+        })
+}
+
+/// Log a warning when an endpoint's DstPrefix is one is_reserved_prefix flags
+fn warn_reserved_prefix(epuid: &str, prefix: &str, reserved: &[String]) {
+    if is_reserved_prefix(prefix, reserved) {
+        warn!(
+            "Endpoint {} uses interface prefix '{}', which may clash with a reserved interface in the container",
+            epuid, prefix
+        );
+    }
+}
+
+/// Read a small integer option that Docker may pass either as a JSON number or a string
+fn option_u64(v: &serde_json::Value, key: &str) -> Result<Option<u64>, RustyCanError> {
+    let parsed = match &v[key] {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => s.trim().parse::<u64>().ok(),
+        _ => None,
+    };
+    match parsed {
+        Some(n) => Ok(Some(n)),
+        None => Err(RustyCanError::InvalidOptions(format!(
+            "{key} must be a non-negative number, got {}",
+            v[key]
+        ))),
+    }
+}
+
+/// Largest 11-bit standard and 29-bit extended CAN ids
+const CAN_SFF_MAX: u64 = 0x7FF;
+const CAN_EFF_MAX: u64 = 0x1FFF_FFFF;
+
+/// Read vxcan.id as a decimal or 0x-prefixed hex number within the CAN id range
+fn option_canid(v: &serde_json::Value, extended: bool) -> Result<Option<u64>, RustyCanError> {
+    let id = match &v["vxcan.id"] {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => parse_canid(s),
+        _ => None,
+    };
+    let Some(id) = id else {
+        // The id names the network's interface, so there is no interface for a range
+        let range = v["vxcan.id"].as_str().and_then(|s| s.split_once('-'));
+        if range.is_some_and(|(lo, hi)| parse_canid(lo).is_some() && parse_canid(hi).is_some()) {
+            return Err(RustyCanError::InvalidOptions(format!(
+                "vxcan.id {} is a range, but a network takes a single id; \
+                 use vxcan.filter to limit the ids it forwards",
+                v["vxcan.id"]
+            )));
+        }
+        return Err(RustyCanError::InvalidOptions(format!(
+            "vxcan.id must be a decimal or 0x-prefixed hex number, got {}",
+            v["vxcan.id"]
+        )));
+    };
+    let (max, range) = if extended {
+        (CAN_EFF_MAX, "29-bit extended")
+    } else {
+        (CAN_SFF_MAX, "11-bit standard (set vxcan.extended=true for 29-bit ids)")
+    };
+    if id > max {
+        return Err(RustyCanError::InvalidOptions(format!(
+            "vxcan.id {id:#X} is outside the {range} range, at most {max:#X}"
+        )));
+    }
+    Ok(Some(id))
+}
+
+fn parse_canid(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse::<u64>().ok(),
+    }
+}
+
+fn option_u8(v: &serde_json::Value, key: &str) -> Result<Option<u8>, RustyCanError> {
+    let parsed = match &v[key] {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Number(n) => n.as_u64().and_then(|n| u8::try_from(n).ok()),
+        serde_json::Value::String(s) => s.trim().parse::<u8>().ok(),
+        _ => None,
+    };
+    match parsed {
+        Some(n) => Ok(Some(n)),
+        None => Err(RustyCanError::InvalidOptions(format!(
+            "{key} must be a number between 0 and 255, got {}",
+            v[key]
+        ))),
+    }
+}
 
-                // Return the tuple of options
-                Ok((device, peer, canid))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::MockRunner;
+
+    // Manager with a state file of its own; interfaces are only dry-run with a
+    // MockRunner installed
+    fn manager(name: &str) -> NetworkManager {
+        let dir = state_dir(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        NetworkManager::with_config(PluginConfig {
+            state_files: vec![dir.join("networks.json").to_string_lossy().into_owned()],
+            ..PluginConfig::default()
+        })
+    }
+
+    fn state_dir(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "rustycan4docker-manager-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn bus_options(canid: &str) -> String {
+        serde_json::json!({ "vxcan.dev": "vcan", "vxcan.id": canid }).to_string()
+    }
+
+    // Devices of a network's endpoints, sorted
+    fn endpoint_devices(mgr: &NetworkManager, nuid: &str) -> Vec<String> {
+        let network = mgr.network(nuid).unwrap();
+        let n = network.lock();
+        let mut devices: Vec<String> =
+            n.endpoint_list.read().values().map(|ep| ep.device.clone()).collect();
+        devices.sort();
+        devices
+    }
+
+    // Rules a bus network should track once all its endpoints are joined: each endpoint
+    // to and from the bus, and to every other endpoint
+    fn joined_rules(ifc: &str, devices: &[String]) -> BTreeSet<(String, String)> {
+        let mut rules = BTreeSet::new();
+        for dev in devices {
+            rules.insert((ifc.to_string(), dev.clone()));
+            rules.insert((dev.clone(), ifc.to_string()));
+            for other in devices.iter().filter(|other| *other != dev) {
+                rules.insert((dev.clone(), other.clone()));
             }
-            Err(_) => Err(Error),
         }
+        rules
+    }
+
+    // Ids of the endpoints saved for a network in the state file
+    fn saved_endpoints(mgr: &NetworkManager, nuid: &str) -> Vec<String> {
+        let saved = mgr.state.load().unwrap().unwrap_or_default();
+        saved[nuid].endpoints.iter().map(|ep| ep.uid.clone()).collect()
+    }
+
+    fn join(mgr: &NetworkManager, nuid: &str, epuid: &str) {
+        mgr.endpoint_attach(nuid.into(), epuid.into(), String::new(), String::from("{}"))
+            .unwrap();
+    }
+
+    // Every cangw job added was removed again with the same arguments
+    fn assert_jobs_removed(mock: &MockRunner) {
+        let calls = mock.calls_to("cangw");
+        let mut added: Vec<&str> = calls.iter().filter_map(|c| c.strip_prefix("-A ")).collect();
+        let mut removed: Vec<&str> = calls.iter().filter_map(|c| c.strip_prefix("-D ")).collect();
+        added.sort();
+        removed.sort();
+        assert!(!added.is_empty());
+        assert_eq!(added, removed);
+    }
+
+    #[test]
+    fn detach_all_removes_every_rule_and_saves_the_endpoints() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("detach-all");
+        mgr.network_create(String::from("n724"), bus_options("724")).unwrap();
+        for epuid in ["ep1", "ep2", "ep3"] {
+            mgr.endpoint_create("n724".into(), epuid.into(), serde_json::json!({})).unwrap();
+            join(&mgr, "n724", epuid);
+        }
+        assert_eq!(saved_endpoints(&mgr, "n724").len(), 3);
+
+        assert_eq!(mgr.detach_all("n724", true).unwrap(), 3);
+        assert!(mgr.describe_network("n724").unwrap().rules.is_empty());
+        assert_eq!(mgr.cangw_rule_count(), 0);
+        assert_eq!(mgr.endpoint_count(), 0);
+        assert_jobs_removed(&mock);
+        assert!(saved_endpoints(&mgr, "n724").is_empty());
+    }
+
+    #[test]
+    fn an_endpoint_recreated_on_join_is_saved() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("recreated-endpoint");
+        // CreateEndpoint went to an earlier plugin instance that didn't save it
+        mgr.network_create(String::from("n788"), bus_options("788")).unwrap();
+        assert!(saved_endpoints(&mgr, "n788").is_empty());
+
+        join(&mgr, "n788", "ep1");
+        assert_eq!(saved_endpoints(&mgr, "n788"), ["ep1"]);
+    }
+
+    #[test]
+    fn shutdown_keeps_the_endpoints_it_tears_down_saved() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("shutdown");
+        mgr.network_create(String::from("n789"), bus_options("789")).unwrap();
+        join(&mgr, "n789", "ep1");
+        join(&mgr, "n789", "ep2");
+
+        mgr.shutdown();
+        assert_eq!(mgr.network_count(), 0);
+        assert_jobs_removed(&mock);
+        let mut saved = saved_endpoints(&mgr, "n789");
+        saved.sort();
+        assert_eq!(saved, ["ep1", "ep2"]);
+    }
+
+    #[test]
+    fn a_second_identical_network_is_refused() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("identical-networks");
+        mgr.network_create(String::from("n765"), bus_options("765")).unwrap();
+
+        let again = mgr.network_create(String::from("n765"), bus_options("765"));
+        assert_eq!(again, Err(RustyCanError::NetworkExists(String::from("n765"))));
+        let other = mgr.network_create(String::from("other"), bus_options("765"));
+        assert!(matches!(other, Err(RustyCanError::InterfaceInUse { nuid, .. }) if nuid == "n765"));
+        assert_eq!(mgr.network_ids(), ["n765"]);
+    }
+
+    #[test]
+    fn only_one_of_several_identical_concurrent_networks_is_created() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("identical-concurrent-networks");
+
+        let start = std::sync::Barrier::new(8);
+        let created = std::thread::scope(|s| {
+            let creates: Vec<_> = (0..8)
+                .map(|i| {
+                    let (mgr, start) = (&mgr, &start);
+                    s.spawn(move || {
+                        start.wait();
+                        mgr.network_create(format!("n766-{i}"), bus_options("766"))
+                    })
+                })
+                .collect();
+            creates.into_iter().filter_map(|c| c.join().unwrap().ok()).count()
+        });
+        assert_eq!(created, 1);
+        assert_eq!(mgr.network_count(), 1);
+
+        // The losers leave the interface to the winner
+        let ip = mock.calls_to("ip");
+        assert_eq!(ip.iter().filter(|c| c.starts_with("link add dev vcan766 ")).count(), 1);
+        assert!(!ip.iter().any(|c| c.starts_with("link del")));
+        assert!(link::interface_exists("vcan766").unwrap());
+    }
+
+    #[test]
+    fn a_failed_batch_create_rolls_back_and_saves_nothing() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("batch-rollback");
+        let request = |nuid: &str, canid: &str| CreateNetworkRequest {
+            nuid: nuid.to_string(),
+            options: serde_json::json!({ "vxcan.dev": "vcan", "vxcan.id": canid }),
+        };
+
+        // The third network would share the first one's interface
+        let result = mgr.create_networks(vec![
+            request("n709a", "701"),
+            request("n709b", "702"),
+            request("n709c", "701"),
+        ]);
+        let nuid = match result {
+            Err(RustyCanError::InterfaceInUse { nuid, .. }) => nuid,
+            other => panic!("expected the interface to be in use, got {other:?}"),
+        };
+        assert_eq!(nuid, "n709a");
+        assert_eq!(mgr.network_count(), 0);
+        assert!(mgr.state.load().unwrap().unwrap_or_default().is_empty());
+        let deleted: Vec<String> = mock
+            .calls_to("ip")
+            .into_iter()
+            .filter(|c| c.starts_with("link del"))
+            .collect();
+        assert_eq!(deleted, ["link del dev vcan702", "link del dev vcan701"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "torn down while the network map is locked")]
+    fn a_network_torn_down_under_the_map_lock_is_caught() {
+        let mgr = manager("lock-order");
+        let config = parse(serde_json::json!({ "vxcan.mode": "direct" })).unwrap();
+        let network = Network::new(&config, Origin::Request, Instant::now()).unwrap();
+        mgr.map_write().insert(String::from("n797"), Arc::new(Mutex::new(network)));
+
+        mgr.map_write().clear();
+    }
+
+    #[test]
+    fn a_create_that_cannot_be_saved_is_torn_down() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("unsaved-create");
+        // Nothing can be written where the state file should be
+        let path = state_dir("unsaved-create").join("networks.json");
+        std::fs::create_dir_all(path.join("blocked")).unwrap();
+
+        assert!(mgr.network_create(String::from("n798"), bus_options("798")).is_err());
+        assert_eq!(mgr.network_count(), 0);
+        assert!(mock.calls_to("ip").contains(&String::from("link del dev vcan798")));
+    }
+
+    #[test]
+    fn a_failed_join_carries_its_cause() {
+        let mock = MockRunner::responding(|call| match call.program.as_str() {
+            "cangw" => Err(String::from("No such file or directory")),
+            _ => Ok(String::new()),
+        });
+        let _mock = mock.install();
+        let mgr = manager("failed-join");
+        mgr.network_create(String::from("n735"), bus_options("735")).unwrap();
+
+        let err = mgr
+            .endpoint_attach("n735".into(), "ep1".into(), String::new(), String::from("{}"))
+            .unwrap_err();
+        let cause = RustyCanError::RuleCommand(String::from("No such file or directory"));
+        assert_eq!(
+            err,
+            RustyCanError::AttachFailed {
+                epuid: String::from("ep1"),
+                source: Some(Box::new(cause.clone())),
+            }
+        );
+        assert_eq!(err.to_string(), format!("unable to attach endpoint ep1: {cause}"));
+    }
+
+    #[test]
+    fn concurrent_joins_create_each_interface_and_rule_once() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let mgr = manager("concurrent-joins");
+
+        let networks = [("n796", "vcan796"), ("n797", "vcan797")];
+        let epuids: Vec<String> = (0..4).map(|i| format!("{i:02}{}", "e".repeat(62))).collect();
+        for (nuid, ifc) in networks {
+            mgr.network_create(nuid.to_string(), bus_options(&ifc[4..])).unwrap();
+            for epuid in &epuids {
+                mgr.endpoint_create(nuid.to_string(), epuid.clone(), serde_json::json!({}))
+                    .unwrap();
+            }
+        }
+
+        // Every endpoint is joined by three threads at once, on both networks together
+        std::thread::scope(|s| {
+            let joins: Vec<_> = networks
+                .iter()
+                .flat_map(|(nuid, _)| epuids.iter().map(move |epuid| (nuid, epuid)))
+                .flat_map(|join| [join; 3])
+                .map(|(nuid, epuid)| {
+                    let mgr = &mgr;
+                    s.spawn(move || {
+                        mgr.endpoint_attach(
+                            nuid.to_string(),
+                            epuid.clone(),
+                            String::new(),
+                            String::from("{}"),
+                        )
+                    })
+                })
+                .collect();
+            for join in joins {
+                assert!(join.join().expect("join panicked").is_ok());
+            }
+        });
+
+        // Only CreateNetwork and CreateEndpoint made interfaces; no Join recreated one
+        let creates = mock.calls_to("ip").iter().filter(|c| c.starts_with("link add")).count();
+        assert_eq!(creates, networks.len() * (1 + epuids.len()));
+
+        let mut jobs = 0;
+        for (nuid, ifc) in networks {
+            let devices = endpoint_devices(&mgr, nuid);
+            assert_eq!(devices.len(), epuids.len());
+            let rules = mgr.describe_network(nuid).unwrap().rules;
+            let tracked: BTreeSet<(String, String)> = rules.iter().cloned().collect();
+            assert_eq!(tracked.len(), rules.len(), "a rule is tracked twice");
+            assert_eq!(tracked, joined_rules(ifc, &devices));
+            jobs += rules.len() * 2;
+        }
+        // Each rule is a classic and an FD job, each installed exactly once
+        assert_eq!(mgr.cangw_rule_count(), jobs);
+        let added = mock.calls_to("cangw").iter().filter(|c| c.starts_with("-A")).count();
+        assert_eq!(added, jobs);
+    }
+
+    fn parse(options: serde_json::Value) -> Result<NetworkConfig, RustyCanError> {
+        let gw_params = ModuleParams {
+            loaded: true,
+            max_hops: Some(6),
+            params: BTreeMap::new(),
+        };
+        parse_network_options(&options.to_string(), &PluginConfig::default(), &gw_params)
+    }
+
+    fn invalid(options: serde_json::Value) -> String {
+        match parse(options) {
+            Err(RustyCanError::InvalidOptions(reason)) => reason,
+            other => panic!("expected invalid options, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn options_default_to_a_vcan_bus() {
+        let config = parse(serde_json::json!({})).unwrap();
+        assert_eq!(
+            (config.device.as_str(), config.peer.as_str(), config.canid.as_str()),
+            ("vcan", "vcanp", "0")
+        );
+        assert_eq!(config.mode, NetworkMode::Bus);
+        assert_eq!(config.prefix, DEFAULT_PREFIX);
+        assert!(!config.realname && !config.link && !config.persist);
+    }
+
+    #[test]
+    fn options_accept_strings_as_docker_passes_them() {
+        let config = parse(serde_json::json!({
+            "vxcan.dev": "vcan",
+            "vxcan.id": "0x7",
+            "vxcan.persist": "true",
+            "vxcan.fd": "yes",
+            "vxcan.hoplimit": "2",
+            "vxcan.ttl": "30",
+        }))
+        .unwrap();
+        assert_eq!(config.canid, "7");
+        assert!(config.persist && config.fd);
+        assert_eq!((config.hoplimit, config.ttl), (Some(2), Some(30)));
+    }
+
+    #[test]
+    fn options_canonicalize_the_can_id() {
+        for (id, canonical) in [("7", "7"), ("007", "7"), ("0x10", "16"), ("0X7df", "2015")] {
+            let config = parse(serde_json::json!({ "vxcan.id": id })).unwrap();
+            assert_eq!(config.canid, canonical, "vxcan.id {id}");
+        }
+        assert_eq!(parse(serde_json::json!({ "vxcan.id": 7 })).unwrap().canid, "7");
+        invalid(serde_json::json!({ "vxcan.id": "" }));
+        invalid(serde_json::json!({ "vxcan.id": "0x" }));
+    }
+
+    #[test]
+    fn options_check_the_can_id_range() {
+        assert!(invalid(serde_json::json!({ "vxcan.id": "0x800" })).contains("11-bit"));
+        let config =
+            parse(serde_json::json!({ "vxcan.id": "0x800", "vxcan.extended": true })).unwrap();
+        assert_eq!(config.canid, "2048");
+        assert!(invalid(serde_json::json!({ "vxcan.id": "seven" })).contains("vxcan.id"));
+        assert!(invalid(serde_json::json!({ "vxcan.id": "0x100-0x1FF" })).contains("single id"));
+    }
+
+    #[test]
+    fn options_link_an_existing_interface() {
+        let config = parse(serde_json::json!({ "vxcan.link": "vcan9" })).unwrap();
+        assert_eq!(config.device, "vcan9");
+        assert!(config.link && config.realname);
+
+        invalid(serde_json::json!({ "vxcan.link": "vcan9", "vxcan.dev": "vcan" }));
+        invalid(serde_json::json!({ "vxcan.link": "vcan9", "vxcan.realname": false }));
+        invalid(serde_json::json!({ "vxcan.link": " " }));
+    }
+
+    #[test]
+    fn options_respect_the_module_hop_limit() {
+        assert!(invalid(serde_json::json!({ "vxcan.hoplimit": 7 })).contains("max_hops"));
+        invalid(serde_json::json!({ "vxcan.hoplimit": 0 }));
+        invalid(serde_json::json!({ "vxcan.ratelimit": 10 }));
+    }
+
+    #[test]
+    fn options_normalize_filters_and_modifications() {
+        let config = parse(serde_json::json!({
+            "vxcan.filter": "100:7f0,200~7ff",
+            "vxcan.modify": "set:i:123.0.0000000000000000",
+        }))
+        .unwrap();
+        assert_eq!(config.filter.as_deref(), Some("100:7F0,200~7FF"));
+        assert_eq!(config.modify.as_deref(), Some("SET:I:123.0.0000000000000000"));
+        invalid(serde_json::json!({ "vxcan.filter": 100 }));
+        invalid(serde_json::json!({ "vxcan.modify": "SET:I" }));
+    }
+
+    #[test]
+    fn options_refuse_settings_a_direct_network_cannot_use() {
+        let config = parse(serde_json::json!({ "vxcan.mode": "direct" })).unwrap();
+        assert_eq!(config.mode, NetworkMode::Direct);
+        invalid(serde_json::json!({ "vxcan.mode": "star" }));
+        invalid(serde_json::json!({ "vxcan.mode": "direct", "vxcan.filter": "100:7FF" }));
+        invalid(serde_json::json!({
+            "vxcan.mode": "direct",
+            "vxcan.modify": "SET:I:123.0.0000000000000000",
+        }));
+        invalid(serde_json::json!({ "vxcan.mode": "direct", "vxcan.link": "vcan9" }));
+    }
+
+    #[test]
+    fn options_refuse_out_of_range_values() {
+        invalid(serde_json::json!({ "vxcan.ttl": 0 }));
+        invalid(serde_json::json!({ "vxcan.persist": "maybe" }));
+        invalid(serde_json::json!({ "vxcan.bitrate": 500000 }));
+        assert!(matches!(
+            parse(serde_json::json!({ "vxcan.dev": "a-name-far-too-long-for-a-link" })),
+            Err(RustyCanError::InvalidInterfaceName { .. })
+        ));
+    }
+
+    #[test]
+    fn options_must_be_json() {
+        let gw_params = ModuleParams {
+            loaded: false,
+            max_hops: None,
+            params: BTreeMap::new(),
+        };
+        assert!(matches!(
+            parse_network_options("{", &PluginConfig::default(), &gw_params),
+            Err(RustyCanError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn network_options_parse_back_to_the_same_config() {
+        let config = parse(serde_json::json!({
+            "vxcan.dev": "vcan",
+            "vxcan.peer": "can{index}",
+            "vxcan.id": 12,
+            "vxcan.hoplimit": 3,
+            "vxcan.filter": "100:7F0",
+            "vxcan.modify": "XOR:I:100.0.0000000000000000",
+            "vxcan.ttl": 60,
+            "vxcan.ttl.refresh": true,
+            "vxcan.fd": true,
+            "vxcan.prefix": "ecu",
+        }))
+        .unwrap();
+        assert_eq!(parse(network_options(&config)).unwrap(), config);
+
+        let linked = parse(serde_json::json!({ "vxcan.link": "vcan9" })).unwrap();
+        assert_eq!(parse(network_options(&linked)).unwrap(), linked);
     }
 }
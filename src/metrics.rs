@@ -0,0 +1,481 @@
+/*
+ * Filename: metrics.rs
+ * Created Date: Friday, October 16th 2026, 11:20:05 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use log::debug;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// OpenMetrics caps the combined length of an exemplar's label names and values
+const EXEMPLAR_MAX_CHARS: usize = 128;
+
+/// Exposition format requested by the scraper
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    // Prometheus text format 0.0.4; exemplars are left out
+    Prometheus,
+    // OpenMetrics 1.0 text format, with exemplars on counters
+    OpenMetrics,
+}
+
+impl Format {
+    /// Pick the format from an HTTP Accept header
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        match accept {
+            Some(a) if a.contains("application/openmetrics-text") => Format::OpenMetrics,
+            _ => Format::Prometheus,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+            Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+        }
+    }
+}
+
+/// Labels identifying the increment that produced a counter's current value
+struct Exemplar {
+    labels: String,
+    timestamp: f64,
+}
+
+/// Monotonic counter exported in Prometheus text format
+/// Only the most recent exemplar is kept, so tracing data never adds series
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+    exemplar: Mutex<Option<Exemplar>>,
+}
+
+impl Counter {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Counter {
+            name,
+            help,
+            value: AtomicU64::new(0),
+            exemplar: Mutex::new(None),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Increment, recording the networks/endpoints/rules involved as the exemplar
+    /// A label set over the OpenMetrics length limit is logged instead
+    pub fn inc_with(&self, labels: &[(&str, &str)]) {
+        self.inc();
+
+        let chars: usize = labels
+            .iter()
+            .map(|(k, v)| k.chars().count() + v.chars().count())
+            .sum();
+        let rendered: Vec<String> = labels
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+            .collect();
+        let rendered = rendered.join(",");
+        if chars > EXEMPLAR_MAX_CHARS {
+            debug!("{} incremented for {{{}}}", self.name, rendered);
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        *self.exemplar.lock().unwrap_or_else(|e| e.into_inner()) = Some(Exemplar {
+            labels: rendered,
+            timestamp,
+        });
+    }
+
+    fn render(&self, out: &mut String, format: Format) {
+        let value = self.value.load(Ordering::Relaxed);
+        if format == Format::Prometheus {
+            let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+            let _ = writeln!(out, "# TYPE {} counter", self.name);
+            let _ = writeln!(out, "{} {}", self.name, value);
+            return;
+        }
+
+        // OpenMetrics names the family without the _total suffix
+        let family = self.name.strip_suffix("_total").unwrap_or(self.name);
+        let _ = writeln!(out, "# HELP {} {}", family, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", family);
+        match &*self.exemplar.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some(ex) => {
+                let _ = writeln!(
+                    out,
+                    "{} {} # {{{}}} 1 {:.3}",
+                    self.name, value, ex.labels, ex.timestamp
+                );
+            }
+            None => {
+                let _ = writeln!(out, "{} {}", self.name, value);
+            }
+        }
+    }
+}
+
+/// Point-in-time value exported in Prometheus text format
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Gauge {
+    const fn new(name: &'static str, help: &'static str) -> Self {
+        Gauge {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set(&self, v: u64) {
+        self.value.store(v, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+        let _ = writeln!(out, "{} {}", self.name, self.value.load(Ordering::Relaxed));
+    }
+}
+
+/// Counter with one series per value of a single label, all fixed up front
+pub struct LabeledCounter<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    label: &'static str,
+    values: [&'static str; N],
+    counts: [AtomicU64; N],
+}
+
+impl<const N: usize> LabeledCounter<N> {
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        label: &'static str,
+        values: [&'static str; N],
+    ) -> Self {
+        LabeledCounter {
+            name,
+            help,
+            label,
+            values,
+            counts: [const { AtomicU64::new(0) }; N],
+        }
+    }
+
+    /// Increment the series for `value`; values outside the fixed set are ignored
+    pub fn inc(&self, value: &str) {
+        if let Some(i) = self.values.iter().position(|v| *v == value) {
+            self.counts[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn render(&self, out: &mut String, format: Format) {
+        let family = match format {
+            Format::Prometheus => self.name,
+            Format::OpenMetrics => self.name.strip_suffix("_total").unwrap_or(self.name),
+        };
+        let _ = writeln!(out, "# HELP {} {}", family, self.help);
+        let _ = writeln!(out, "# TYPE {} counter", family);
+        for (value, count) in self.values.iter().zip(&self.counts) {
+            let _ = writeln!(
+                out,
+                "{}{{{}=\"{}\"}} {}",
+                self.name,
+                self.label,
+                value,
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+// Upper bounds, in seconds, of the buckets every histogram is exported with
+const DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Cumulative bucket counts, sum and count of one histogram series
+struct HistogramSeries {
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+/// Duration histogram with one series per label set, all fixed up front
+pub struct LabeledHistogram<const N: usize> {
+    name: &'static str,
+    help: &'static str,
+    labels: [&'static str; 2],
+    values: [[&'static str; 2]; N],
+    series: [HistogramSeries; N],
+}
+
+impl<const N: usize> LabeledHistogram<N> {
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: [&'static str; 2],
+        values: [[&'static str; 2]; N],
+    ) -> Self {
+        LabeledHistogram {
+            name,
+            help,
+            labels,
+            values,
+            series: [const {
+                HistogramSeries {
+                    buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len()],
+                    sum_micros: AtomicU64::new(0),
+                    count: AtomicU64::new(0),
+                }
+            }; N],
+        }
+    }
+
+    /// Record one observation for the series labelled `values`; unknown label sets are ignored
+    pub fn observe(&self, values: [&str; 2], elapsed: Duration) {
+        let Some(i) = self.values.iter().position(|v| *v == values) else {
+            return;
+        };
+        let series = &self.series[i];
+        let secs = elapsed.as_secs_f64();
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&series.buckets) {
+            if secs <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        series
+            .sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        series.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        for (values, series) in self.values.iter().zip(&self.series) {
+            let labels = format!(
+                "{}=\"{}\",{}=\"{}\"",
+                self.labels[0], values[0], self.labels[1], values[1]
+            );
+            for (bound, bucket) in DURATION_BUCKETS.iter().zip(&series.buckets) {
+                let _ = writeln!(
+                    out,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    self.name,
+                    labels,
+                    bound,
+                    bucket.load(Ordering::Relaxed)
+                );
+            }
+            let count = series.count.load(Ordering::Relaxed);
+            let sum = series.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                self.name, labels, count
+            );
+            let _ = writeln!(out, "{}_sum{{{}}} {}", self.name, labels, sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", self.name, labels, count);
+        }
+    }
+}
+
+// Label value used for the series that aggregates label sets beyond the cap
+const OVERFLOW_LABEL: &str = "_overflow";
+
+/// Up/down gauge with one series per label set, built fresh for each scrape
+/// At most `max_series` label sets are exported; the rest collapse into a single
+/// overflow series that is up only when every aggregated entity is up
+pub struct StateGauge {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    max_series: usize,
+    series: Vec<(Vec<String>, bool)>,
+}
+
+impl StateGauge {
+    pub fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        max_series: usize,
+    ) -> Self {
+        StateGauge {
+            name,
+            help,
+            labels,
+            max_series,
+            series: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, values: &[&str], up: bool) {
+        self.series
+            .push((values.iter().map(|v| v.to_string()).collect(), up));
+    }
+
+    pub fn render(&mut self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} gauge", self.name);
+
+        self.series.sort();
+        let overflow = self
+            .series
+            .split_off(self.max_series.min(self.series.len()));
+        for (values, up) in &self.series {
+            self.render_series(out, values, *up);
+        }
+        if !overflow.is_empty() {
+            let values = vec![OVERFLOW_LABEL.to_string(); self.labels.len()];
+            self.render_series(out, &values, overflow.iter().all(|(_, up)| *up));
+        }
+    }
+
+    fn render_series(&self, out: &mut String, values: &[String], up: bool) {
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .zip(values)
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_label(v)))
+            .collect();
+        let _ = writeln!(out, "{}{{{}}} {}", self.name, labels.join(","), up as u8);
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Process-wide plugin metrics
+pub struct Metrics {
+    pub cangw_external_changes: Counter,
+    pub self_heal_repairs: Counter,
+    pub peer_placement_failures: Counter,
+    pub cangw_rules_installed: Counter,
+    pub forwarding_failures: Counter,
+    pub endpoint_attach_errors: Counter,
+    pub networks: Gauge,
+    pub endpoints: Gauge,
+    pub cangw_rules: Gauge,
+    pub interface_creates_in_use: Gauge,
+    pub interfaces_created: LabeledCounter<4>,
+    pub cangw_op_seconds: LabeledHistogram<4>,
+}
+
+pub static METRICS: Metrics = Metrics {
+    cangw_external_changes: Counter::new(
+        "rustycan_cangw_external_changes_total",
+        "cangw rules added or removed outside the plugin",
+    ),
+    self_heal_repairs: Counter::new(
+        "rustycan_self_heal_repairs_total",
+        "Networks whose forwarding was restored by self-heal",
+    ),
+    peer_placement_failures: Counter::new(
+        "rustycan_peer_placement_failures_total",
+        "Endpoint peers still in the host namespace after Join",
+    ),
+    cangw_rules_installed: Counter::new(
+        "rustycan_cangw_rules_installed_total",
+        "cangw rules installed or re-installed by the plugin",
+    ),
+    forwarding_failures: Counter::new(
+        "rustycan_forwarding_failures_total",
+        "Probe frames that were not forwarded from a network bus to an endpoint",
+    ),
+    endpoint_attach_errors: Counter::new(
+        "rustycan_endpoint_attach_errors_total",
+        "Endpoint Joins that failed",
+    ),
+    networks: Gauge::new("rustycan_networks_total", "Networks managed by the plugin"),
+    endpoints: Gauge::new("rustycan_endpoints_total", "Endpoints across every network"),
+    cangw_rules: Gauge::new(
+        "rustycan_cangw_rules_total",
+        "cangw jobs the plugin holds across every network",
+    ),
+    interface_creates_in_use: Gauge::new(
+        "rustycan_interface_creates_in_use",
+        "Interface creations currently running under the concurrency limit",
+    ),
+    interfaces_created: LabeledCounter::new(
+        "rustycan_interfaces_created_total",
+        "Interfaces created by the plugin, by what caused the creation",
+        "origin",
+        ["request", "recovery", "rebuild", "pool"],
+    ),
+    cangw_op_seconds: LabeledHistogram::new(
+        "rustycan_cangw_op_seconds",
+        "Time taken by cangw to add or remove a gateway job",
+        ["op", "rule"],
+        [
+            ["add", "base"],
+            ["add", "extended"],
+            ["remove", "base"],
+            ["remove", "extended"],
+        ],
+    ),
+};
+
+impl Metrics {
+    /// Render all metrics in the Prometheus text exposition format
+    pub fn render(&self, format: Format) -> String {
+        let mut out = String::new();
+        self.cangw_external_changes.render(&mut out, format);
+        self.self_heal_repairs.render(&mut out, format);
+        self.peer_placement_failures.render(&mut out, format);
+        self.cangw_rules_installed.render(&mut out, format);
+        self.forwarding_failures.render(&mut out, format);
+        self.endpoint_attach_errors.render(&mut out, format);
+        self.networks.render(&mut out);
+        self.endpoints.render(&mut out);
+        self.cangw_rules.render(&mut out);
+        self.interface_creates_in_use.render(&mut out);
+        self.interfaces_created.render(&mut out, format);
+        self.cangw_op_seconds.render(&mut out);
+        out
+    }
+}
@@ -0,0 +1,148 @@
+/*
+ * Filename: netem.rs
+ * Created Date: Saturday, October 17th 2026, 10:55:37 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::error::RustyCanError;
+use serde::{Deserialize, Serialize};
+
+// Largest delay accepted, to catch unit mistakes (values are milliseconds)
+const MAX_DELAY_MS: f64 = 60_000.0;
+
+/// netem impairment applied to a single endpoint's host-side vxcan device
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Netem {
+    pub delay_ms: Option<f64>,
+    pub loss_pct: Option<f64>,
+    pub reorder_pct: Option<f64>,
+}
+
+impl Netem {
+    /// Read `vxcan.netem.delay` (ms), `vxcan.netem.loss` (%) and `vxcan.netem.reorder` (%)
+    /// from endpoint options. Returns None when none of them are set
+    pub fn from_options(options: &serde_json::Value) -> Result<Option<Self>, RustyCanError> {
+        let netem = Netem {
+            delay_ms: number(options, "vxcan.netem.delay", "ms")?,
+            loss_pct: number(options, "vxcan.netem.loss", "%")?,
+            reorder_pct: number(options, "vxcan.netem.reorder", "%")?,
+        };
+        if netem == Netem::default() {
+            return Ok(None);
+        }
+        netem.validate()?;
+        Ok(Some(netem))
+    }
+
+    fn validate(&self) -> Result<(), RustyCanError> {
+        let invalid = |msg: String| Err(RustyCanError::InvalidOptions(msg));
+        if let Some(d) = self.delay_ms {
+            if !(0.0..=MAX_DELAY_MS).contains(&d) {
+                return invalid(format!(
+                    "vxcan.netem.delay must be between 0 and {MAX_DELAY_MS} ms, got {d}"
+                ));
+            }
+        }
+        for (key, pct) in [
+            ("vxcan.netem.loss", self.loss_pct),
+            ("vxcan.netem.reorder", self.reorder_pct),
+        ] {
+            if let Some(p) = pct {
+                if !(0.0..=100.0).contains(&p) {
+                    return invalid(format!("{key} must be between 0 and 100 %, got {p}"));
+                }
+            }
+        }
+        // netem only reorders frames that would otherwise be delayed
+        if self.reorder_pct.is_some() && self.delay_ms.unwrap_or(0.0) <= 0.0 {
+            return invalid(String::from(
+                "vxcan.netem.reorder requires vxcan.netem.delay",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Arguments for `tc` that install this impairment as the root qdisc of `dev`
+    pub fn tc_args(&self, dev: &str) -> Vec<String> {
+        let mut args: Vec<String> = ["qdisc", "replace", "dev", dev, "root", "netem"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        if let Some(d) = self.delay_ms {
+            args.extend([String::from("delay"), format!("{d}ms")]);
+        }
+        if let Some(l) = self.loss_pct {
+            args.extend([String::from("loss"), format!("{l}%")]);
+        }
+        if let Some(r) = self.reorder_pct {
+            args.extend([String::from("reorder"), format!("{r}%")]);
+        }
+        args
+    }
+
+    /// Install the impairment on `dev`
+    pub fn apply(&self, dev: &str) -> Result<(), RustyCanError> {
+        let args = self.tc_args(dev);
+        let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        run_tc(&args)
+    }
+
+    /// Remove any impairment from `dev`, restoring the default qdisc
+    pub fn clear(dev: &str) -> Result<(), RustyCanError> {
+        run_tc(&["qdisc", "del", "dev", dev, "root"])
+    }
+}
+
+fn run_tc(args: &[&str]) -> Result<(), RustyCanError> {
+    match command::output("tc", args) {
+        Ok(out) if out.status.success() => Ok(()),
+        Ok(out) => Err(RustyCanError::LinkCommand(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        )),
+        Err(e) => Err(RustyCanError::LinkCommand(format!(
+            "failed to execute tc: {e}"
+        ))),
+    }
+}
+
+/// Read a number that may be a JSON number or a string with an optional unit suffix
+fn number(
+    options: &serde_json::Value,
+    key: &str,
+    unit: &str,
+) -> Result<Option<f64>, RustyCanError> {
+    let parsed = match &options[key] {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => s.trim().trim_end_matches(unit).trim().parse::<f64>().ok(),
+        _ => None,
+    };
+    match parsed {
+        Some(n) if n.is_finite() => Ok(Some(n)),
+        _ => Err(RustyCanError::InvalidOptions(format!(
+            "{key} must be a number, got {}",
+            options[key]
+        ))),
+    }
+}
@@ -0,0 +1,231 @@
+/*
+ * Filename: netlink.rs
+ * Created Date: Saturday, October 17th 2026, 10:41:08 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::error::RustyCanError;
+use crate::link;
+use futures::TryStreamExt;
+use netlink_packet_route::nlas::link::{Info, InfoData, InfoKind, Nla, VethInfo};
+use netlink_packet_route::LinkMessage;
+use rtnetlink::Handle;
+use std::future::Future;
+use log::info;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set from RUSTYCAN_DRY_RUN at startup
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+// Interfaces a dry run has pretended to create, each with its vxcan peer if it has one
+static DRY_RUN_LINKS: Mutex<BTreeMap<String, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// Log interface changes as their `ip` equivalent instead of making them; each is
+/// treated as having succeeded
+/// Turning it off forgets the interfaces the dry run pretended to create
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+    if !enabled {
+        DRY_RUN_LINKS.lock().clear();
+    }
+}
+
+/// Interfaces a dry run has pretended to create and not yet deleted, so they are
+/// found again like real ones; None outside a dry run
+pub fn dry_run_links() -> Option<Vec<String>> {
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        return None;
+    }
+    let links = DRY_RUN_LINKS.lock();
+    Some(links.keys().cloned().collect())
+}
+
+// In dry-run mode, log and trace a change as the `ip` command it stands for and skip it
+fn dry_run(args: &[&str]) -> bool {
+    if !DRY_RUN.load(Ordering::Relaxed) {
+        return false;
+    }
+    command::log_invocation("ip", args);
+    info!("Dry run: ip {}", args.join(" "));
+    true
+}
+
+/// Create a vcan interface with the given MTU, left down
+/// Equivalent to `ip link add dev <name> mtu <mtu> type vcan`
+pub fn create_vcan(name: &str, mtu: u32) -> Result<(), RustyCanError> {
+    let mtu_arg = mtu.to_string();
+    if dry_run(&["link", "add", "dev", name, "mtu", &mtu_arg, "type", "vcan"]) {
+        let mut links = DRY_RUN_LINKS.lock();
+        if links.contains_key(name) {
+            return Err(RustyCanError::LinkExists(name.to_string()));
+        }
+        links.insert(name.to_string(), None);
+        return Ok(());
+    }
+    run(name, |handle| {
+        let mut request = handle.link().add();
+        let msg = request.message_mut();
+        msg.nlas.push(Nla::IfName(name.to_string()));
+        msg.nlas.push(Nla::Mtu(mtu));
+        msg.nlas.push(Nla::Info(vec![Info::Kind(InfoKind::Other(
+            "vcan".to_string(),
+        ))]));
+        request.execute()
+    })
+}
+
+/// Create a vxcan pair with the given MTU on both ends, both left down
+/// Equivalent to `ip link add dev <dev> mtu <mtu> type vxcan peer name <peer> mtu <mtu>`
+pub fn create_vxcan_pair(dev: &str, peer: &str, mtu: u32) -> Result<(), RustyCanError> {
+    let mtu_arg = mtu.to_string();
+    let args = ["link", "add", "dev", dev, "mtu", &mtu_arg, "type", "vxcan"];
+    if dry_run(&[&args[..], &["peer", "name", peer, "mtu", &mtu_arg]].concat()) {
+        let mut links = DRY_RUN_LINKS.lock();
+        if let Some(name) = [dev, peer].into_iter().find(|name| links.contains_key(*name)) {
+            return Err(RustyCanError::LinkExists(name.to_string()));
+        }
+        links.insert(dev.to_string(), Some(peer.to_string()));
+        links.insert(peer.to_string(), Some(dev.to_string()));
+        return Ok(());
+    }
+    run(dev, |handle| {
+        // vxcan takes its peer in the same nested layout as veth (VXCAN_INFO_PEER)
+        let mut peer_msg = LinkMessage::default();
+        peer_msg.nlas.push(Nla::IfName(peer.to_string()));
+        peer_msg.nlas.push(Nla::Mtu(mtu));
+
+        let mut request = handle.link().add();
+        let msg = request.message_mut();
+        msg.nlas.push(Nla::IfName(dev.to_string()));
+        msg.nlas.push(Nla::Mtu(mtu));
+        msg.nlas.push(Nla::Info(vec![
+            Info::Kind(InfoKind::Other("vxcan".to_string())),
+            Info::Data(InfoData::Veth(VethInfo::Peer(peer_msg))),
+        ]));
+        request.execute()
+    })
+}
+
+/// Bring an interface administratively up
+pub fn set_link_up(name: &str) -> Result<(), RustyCanError> {
+    if dry_run(&["link", "set", "dev", name, "up"]) {
+        return Ok(());
+    }
+    let index = link::ifindex(name).ok_or(RustyCanError::InterfaceNotFound(name.to_string()))?;
+    run(name, |handle| handle.link().set(index).up().execute())
+}
+
+/// Bring an interface administratively down
+pub fn set_link_down(name: &str) -> Result<(), RustyCanError> {
+    if dry_run(&["link", "set", "dev", name, "down"]) {
+        return Ok(());
+    }
+    let index = link::ifindex(name).ok_or(RustyCanError::InterfaceNotFound(name.to_string()))?;
+    run(name, |handle| handle.link().set(index).down().execute())
+}
+
+/// Change the MTU of an interface
+/// Equivalent to `ip link set dev <name> mtu <mtu>`
+pub fn set_mtu(name: &str, mtu: u32) -> Result<(), RustyCanError> {
+    if dry_run(&["link", "set", "dev", name, "mtu", &mtu.to_string()]) {
+        return Ok(());
+    }
+    let index = link::ifindex(name).ok_or(RustyCanError::InterfaceNotFound(name.to_string()))?;
+    run(name, |handle| handle.link().set(index).mtu(mtu).execute())
+}
+
+/// Link type of an interface (`vcan`, `vxcan`, `can`, ...), None when the kernel
+/// reports none
+/// Equivalent to the type shown by `ip -d link show <name>`
+pub fn link_kind(name: &str) -> Result<Option<String>, RustyCanError> {
+    if DRY_RUN.load(Ordering::Relaxed) {
+        if let Some(peer) = DRY_RUN_LINKS.lock().get(name) {
+            return Ok(Some(String::from(if peer.is_some() { "vxcan" } else { "vcan" })));
+        }
+    }
+    run(name, |handle| async move {
+        let links = handle.link().get().match_name(name.to_string()).execute();
+        let mut links = std::pin::pin!(links);
+        let Some(msg) = links.try_next().await? else {
+            return Ok(None);
+        };
+        Ok(msg.nlas.into_iter().find_map(|nla| match nla {
+            Nla::Info(infos) => infos.into_iter().find_map(|info| match info {
+                Info::Kind(InfoKind::Other(kind)) => Some(kind),
+                Info::Kind(kind) => Some(format!("{kind:?}").to_ascii_lowercase()),
+                _ => None,
+            }),
+            _ => None,
+        }))
+    })
+}
+
+/// Delete an interface (for a vxcan pair, both ends)
+pub fn delete_link(name: &str) -> Result<(), RustyCanError> {
+    if dry_run(&["link", "del", "dev", name]) {
+        let mut links = DRY_RUN_LINKS.lock();
+        if let Some(Some(peer)) = links.remove(name) {
+            links.remove(&peer);
+        }
+        return Ok(());
+    }
+    let index = link::ifindex(name).ok_or(RustyCanError::InterfaceNotFound(name.to_string()))?;
+    run(name, |handle| handle.link().del(index).execute())
+}
+
+// Send one request on its own connection
+// Callers may be on a runtime worker or a plain thread, so the request runs on a
+// scoped thread with a single-threaded runtime instead of blocking the caller's
+fn run<F, Fut, T>(name: &str, request: F) -> Result<T, RustyCanError>
+where
+    F: FnOnce(Handle) -> Fut + Send,
+    Fut: Future<Output = Result<T, rtnetlink::Error>>,
+    T: Send,
+{
+    let result = std::thread::scope(|s| {
+        s.spawn(|| -> Result<T, io::Error> {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .build()?;
+            runtime.block_on(async {
+                let (connection, handle, _) = rtnetlink::new_connection()?;
+                tokio::spawn(connection);
+                request(handle).await.map_err(|e| match e {
+                    rtnetlink::Error::NetlinkError(msg) => msg.to_io(),
+                    e => io::Error::other(e.to_string()),
+                })
+            })
+        })
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::other("netlink thread panicked")))
+    });
+
+    result.map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => RustyCanError::LinkExists(name.to_string()),
+        _ => RustyCanError::Netlink(format!("{name}: {e}")),
+    })
+}
@@ -24,12 +24,24 @@
  * SOFTWARE.
  */
 
-use crate::endpoint::Endpoint;
-use parking_lot::RwLock;
+use crate::cangw::{self, CangwJob, CangwRule, JobOptions, KernelRule, RuleStats};
+use crate::command;
+use crate::endpoint::{Direction, Endpoint};
+use crate::error::RustyCanError;
+use crate::link::{self, LinkStatus, Origin};
+use crate::metrics::{StateGauge, METRICS};
+use crate::netem::Netem;
+use crate::netlink;
+use crate::pool::InterfacePool;
+use crate::probe;
+use crate::rulewatch::diff_rules;
+use crate::state::{NetworkConfig, NetworkMode, SavedEndpoint};
+use log::{error, info, warn};
+use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fmt::Error;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -38,136 +50,392 @@ pub struct JoinResponse {
     DstPrefix: String,
 }
 
+impl JoinResponse {
+    /// Base libnetwork uses for the interface name inside the container
+    pub fn dst_prefix(&self) -> &str {
+        &self.DstPrefix
+    }
+}
+
+/// Role an interface plays within a network
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum InterfaceRole {
+    Network,
+    EndpointDevice,
+    EndpointPeer,
+}
+
+/// Combined logical and kernel view of an interface owned by a network
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub role: InterfaceRole,
+    pub network_id: String,
+    pub endpoint_id: Option<String>,
+    pub device: String,
+    pub peer: String,
+    pub canid: String,
+    pub created: bool,
+    pub kernel: LinkStatus,
+}
+
+/// Whether deleting a network removes its interface from the kernel
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Ownership {
+    // Created by the plugin and removed when the network is deleted
+    Created,
+    // Pre-existing interface the plugin uses but never removes
+    Adopted,
+    // Created by the plugin but kept on delete because of vxcan.persist
+    Persistent,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct EndpointSummary {
+    pub uid: String,
+    pub device: String,
+    pub peer: String,
+    pub created: bool,
+    // None when the interface list could not be read
+    pub interface_exists: Option<bool>,
+    // Kernel ifindex in the host namespace; None once moved into a container or missing
+    pub device_ifindex: Option<u32>,
+    pub peer_ifindex: Option<u32>,
+    // MTU both ends of the pair were created with
+    pub mtu: u32,
+    pub direction: Direction,
+}
+
+/// How a network's tracked gateway jobs compare with what `cangw -L` reports
+#[derive(Debug, Serialize, Clone)]
+pub struct RuleDiagnosis {
+    // Tracked and installed in the kernel
+    pub present: Vec<KernelRule>,
+    // Tracked but absent from the kernel
+    pub missing: Vec<KernelRule>,
+    // In the kernel on one of the network's interfaces but not tracked
+    pub untracked: Vec<KernelRule>,
+}
+
+/// What a single network holds, for the plugin-wide resource report
+#[derive(Debug, Clone, Default)]
+pub struct NetworkResources {
+    pub endpoints: usize,
+    // Kernel interfaces the plugin created and still owns (a vxcan pair counts as two)
+    pub interfaces_created: usize,
+    // Tracked gateway rules; each is installed as a classic and an FD cangw job
+    pub rules: usize,
+    // Kernel cangw jobs behind those rules, one pair per ingress filter on endpoint rules
+    pub cangw_jobs: usize,
+    // Rough size of the in-memory records, including their strings
+    pub memory_bytes: usize,
+}
+
+/// Point-in-time view of a network and its endpoints
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NetworkSummary {
+    pub nuid: String,
+    pub device: String,
+    pub peer: String,
+    pub canid: String,
+    pub ifc: String,
+    // Kernel ifindex of `ifc`; None when the interface is missing
+    pub ifindex: Option<u32>,
+    pub created: bool,
+    pub ownership: Ownership,
+    // A direct network has no bus interface, so `ifc` is never expected to exist
+    pub mode: NetworkMode,
+    pub endpoints: Vec<EndpointSummary>,
+}
+
+/// Detailed view of a single network, including kernel state and tracked rules
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NetworkDescription {
+    #[serde(flatten)]
+    pub summary: NetworkSummary,
+    pub persist: bool,
+    pub ephemeral: bool,
+    pub hoplimit: Option<u8>,
+    pub filter: Option<String>,
+    pub modify: Option<String>,
+    pub bitrate: Option<u32>,
+    pub fd: bool,
+    pub extended: bool,
+    pub dbitrate: Option<u32>,
+    pub loopback: Option<bool>,
+    pub prefix: String,
+    // Seconds left before the network is deleted automatically
+    pub ttl_remaining_secs: Option<u64>,
+    pub kernel: LinkStatus,
+    pub rules: Vec<(String, String)>,
+    pub busoff: bool,
+}
+
 pub struct Network {
     device: String,
     peer: String,
     canid: String,
     ifc: String,
     created: bool,
+    persist: bool,
+    ephemeral: bool,
+    hoplimit: Option<u8>,
+    realname: bool,
+    // The interface was named through vxcan.link
+    link: bool,
+    mode: NetworkMode,
+    // Ingress filter (cangw -f) on every rule from an endpoint
+    filter: Option<String>,
+    // Frame modifications (cangw -m) on every rule to an endpoint
+    modify: Option<String>,
+    ttl: Option<Duration>,
+    ttl_refresh: bool,
+    // When the sweeper deletes the network; None without a TTL
+    expires_at: Option<Instant>,
+    // Bitrate configured on an adopted real CAN interface
+    bitrate: Option<u32>,
+    // Carries CAN FD frames; sets the MTU of the interfaces the plugin creates
+    fd: bool,
+    // vxcan.id may use the 29-bit extended CAN id range
+    extended: bool,
+    dbitrate: Option<u32>,
+    // Loopback mode set on an adopted real CAN interface
+    loopback: Option<bool>,
+    // Prefix of endpoint interface names
+    prefix: String,
+    // Only touched with the network's own lock held; when both are needed,
+    // endpoint_list is taken before rules_list (see NetworkMap for the full order)
     pub endpoint_list: Arc<RwLock<HashMap<String, Endpoint>>>,
-    rules_list: Arc<RwLock<Vec<(String, String)>>>,
+    rules_list: Arc<RwLock<Vec<CangwRule>>>,
+    // Set when the interface was claimed from the pool; it is returned there on drop
+    pool: Option<Arc<InterfacePool>>,
+    // Simulated bus-off in effect (interface held down until recovery)
+    busoff: bool,
+    // Index each joined endpoint substitutes for `{index}` in its DstPrefix
+    peer_indices: Mutex<HashMap<String, usize>>,
 }
 
+// Automatic restart delay set on real CAN controllers when leaving simulated bus-off
+const BUSOFF_RESTART_MS: &str = "100";
+
+// Threads running an attach's cangw jobs
+const CANGW_WORKERS: usize = 8;
+
+// Placeholder in vxcan.peer replaced by a per-endpoint index, e.g. `can{index}`
+const PEER_INDEX: &str = "{index}";
+
 impl Network {
-    pub fn new(device: String, peer: String, canid: String) -> Self {
-        let ifcs = interfaces::Interface::get_all().unwrap();
+    /// Kernel interface name used for a network's shared bus
+    /// Real CAN interfaces are used verbatim; otherwise the canid is appended to the device
+    /// Direct networks have no bus, so the name is empty
+    pub fn interface_name(config: &NetworkConfig) -> String {
+        if config.mode == NetworkMode::Direct {
+            String::new()
+        } else if config.realname {
+            config.device.clone()
+        } else {
+            format!("{}{}", config.device, config.canid)
+        }
+    }
+
+    /// Whether a device name is a real CAN controller present on the host (e.g. `can0`)
+    pub fn is_real_can_device(device: &str) -> bool {
+        device
+            .strip_prefix("can")
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            && link::is_hardware(device)
+    }
 
-        let mut exists: bool = false;
-        let newifc = format!("{device}{canid}");
+    /// `origin` tags the interface creation (if one is needed) in logs and metrics
+    /// `now` is the manager's clock reading, from which a TTL is counted
+    pub fn new(
+        config: &NetworkConfig,
+        origin: Origin,
+        now: Instant,
+    ) -> Result<Self, RustyCanError> {
+        let device = config.device.clone();
+        let peer = config.peer.clone();
+        let canid = config.canid.clone();
+        let newifc = Network::interface_name(config);
 
-        for i in ifcs.into_iter() {
-            if i.name.eq(&newifc) {
-                exists = true;
+        if config.mode == NetworkMode::Direct {
+            info!(
+                "Creating direct network with settings: device='{}', peer='{}', id='{}'",
+                device, peer, canid
+            );
+            return Ok(Network {
+                device,
+                peer,
+                canid,
+                ifc: newifc,
+                created: false,
+                persist: config.persist,
+                ephemeral: config.ephemeral,
+                hoplimit: config.hoplimit,
+                realname: false,
+                link: false,
+                mode: NetworkMode::Direct,
+                filter: None,
+                modify: None,
+                ttl: config.ttl.map(Duration::from_secs),
+                ttl_refresh: config.ttl_refresh,
+                expires_at: Network::expiry(config, now),
+                bitrate: None,
+                fd: config.fd,
+                extended: config.extended,
+                dbitrate: None,
+                loopback: None,
+                prefix: config.prefix.clone(),
+                endpoint_list: Arc::new(RwLock::new(HashMap::new())),
+                rules_list: Arc::new(RwLock::new(Vec::new())),
+                pool: None,
+                busoff: false,
+                peer_indices: Mutex::new(HashMap::new()),
+            });
+        }
+
+        // Refuse to guess when enumeration fails, or an existing interface could be clobbered
+        let exists = link::interface_exists(&newifc)?;
+
+        // A real CAN interface is only ever adopted, never replaced with a vcan
+        if config.realname && !exists {
+            if config.link {
+                return Err(RustyCanError::LinkMissing(newifc));
             }
+            return Err(RustyCanError::InterfaceNotFound(newifc));
         }
 
-        if !exists {
-            println!(" -> Creating interface {newifc}...");
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("add")
-                .arg("dev")
-                .arg(&newifc)
-                .arg("type")
-                .arg("vcan")
-                .output()
-                .expect(" !! Failed to add VCAN device");
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("set")
-                .arg("up")
-                .arg(&newifc)
-                .output()
-                .expect(" !! Failed to start VCAN device");
-        }
-        println!(
-            " -> Creating network with settings: device='{}', peer='{}', id='{}' -- new device? {}",
+        if exists {
+            // An adopted interface may be a real controller or a vcan set up by hand
+            let kinds: &[&str] = if config.realname { &["can", "vcan"] } else { &["vcan"] };
+            link::check_kind(&newifc, kinds)?;
+        } else {
+            info!("Creating interface {newifc}...");
+            link::create_vcan(&newifc, link::can_mtu(config.fd), origin).map_err(|e| {
+                RustyCanError::InterfaceCreate {
+                    name: newifc.clone(),
+                    reason: e.to_string(),
+                }
+            })?;
+            link::bring_up_new(&newifc)?;
+        }
+        if let (true, Some(bitrate)) = (config.realname, config.bitrate) {
+            info!("Setting {newifc} bitrate to {bitrate}");
+            link::set_can_bitrate(&newifc, bitrate, config.dbitrate)?;
+        }
+        if let (true, Some(loopback)) = (config.realname, config.loopback) {
+            info!("Setting {newifc} loopback {}", if loopback { "on" } else { "off" });
+            link::set_can_loopback(&newifc, loopback)?;
+        }
+        info!(
+            "Creating network with settings: device='{}', peer='{}', id='{}' -- new device? {}",
             device, peer, canid, !exists
         );
-        Network {
-            device: device,
-            peer: peer,
-            canid: canid,
+        Ok(Network {
+            device,
+            peer,
+            canid,
             ifc: newifc,
             created: !exists,
+            persist: config.persist,
+            ephemeral: config.ephemeral,
+            hoplimit: config.hoplimit,
+            realname: config.realname,
+            link: config.link,
+            mode: NetworkMode::Bus,
+            filter: config.filter.clone(),
+            modify: config.modify.clone(),
+            ttl: config.ttl.map(Duration::from_secs),
+            ttl_refresh: config.ttl_refresh,
+            expires_at: Network::expiry(config, now),
+            bitrate: config.bitrate,
+            fd: config.fd,
+            extended: config.extended,
+            dbitrate: config.dbitrate,
+            loopback: config.loopback,
+            prefix: config.prefix.clone(),
             endpoint_list: Arc::new(RwLock::new(HashMap::new())),
             rules_list: Arc::new(RwLock::new(Vec::new())),
+            pool: None,
+            busoff: false,
+            peer_indices: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Take over the interface `other` created, when both use the same one, so that
+    /// dropping `other` leaves it alone; it is removed (or handed back to the pool)
+    /// along with this network instead
+    pub fn inherit_interface(&mut self, other: &mut Network) {
+        if other.ifc != self.ifc || !other.created {
+            return;
+        }
+        if !self.created {
+            self.created = true;
+            self.pool = other.pool.take();
         }
+        other.created = false;
+    }
+
+    /// Mark the interface as claimed from the pool, so it is owned by this
+    /// network and handed back to the pool rather than deleted
+    pub fn claim_from_pool(&mut self, pool: Arc<InterfacePool>) {
+        self.created = true;
+        self.pool = Some(pool);
     }
 
     /// Check if the network's VCAN interface exists in the kernel
-    fn network_interface_exists(&self) -> bool {
-        match interfaces::Interface::get_all() {
-            Ok(ifcs) => {
-                for i in ifcs.into_iter() {
-                    if i.name == self.ifc {
-                        return true;
-                    }
-                }
-                false
-            }
-            Err(_) => {
-                eprintln!(" !! Failed to query network interfaces");
-                false
-            }
-        }
+    fn network_interface_exists(&self) -> Result<bool, RustyCanError> {
+        link::interface_exists(&self.ifc)
     }
 
     /// Recreate the network's VCAN interface if it's missing
-    /// This is called during post-reboot recovery
-    fn ensure_network_interface_exists(&mut self) -> Result<(), String> {
-        if self.network_interface_exists() {
-            return Ok(());
+    /// This is called during post-reboot recovery; a missing real CAN interface is an error
+    /// Returns true if the interface was recreated, false if it already existed
+    pub fn ensure_network_interface_exists(&mut self) -> Result<bool, RustyCanError> {
+        // Never recreate on an enumeration failure; the interface may well exist
+        if self.network_interface_exists()? {
+            return Ok(false);
         }
 
-        println!(" -> Network interface {} missing after reboot, recreating...", self.ifc);
+        // An adopted controller can't be recreated; a vcan under its name would
+        // swallow the containers' traffic without any error
+        if self.link {
+            return Err(RustyCanError::LinkMissing(self.ifc.clone()));
+        }
+        if self.realname {
+            return Err(RustyCanError::InterfaceNotFound(self.ifc.clone()));
+        }
+
+        info!("Network interface {} missing after reboot, recreating...", self.ifc);
         
         // Create the VCAN interface
-        let output = std::process::Command::new("ip")
-            .arg("link")
-            .arg("add")
-            .arg("dev")
-            .arg(&self.ifc)
-            .arg("type")
-            .arg("vcan")
-            .output();
-
-        match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let stderr = String::from_utf8_lossy(&result.stderr);
-                    if stderr.contains("File exists") {
-                        println!(" -> Interface {} was created concurrently, continuing", self.ifc);
-                        return Ok(());
-                    }
-                    return Err(format!(" !! Failed to recreate VCAN device {}: {}", self.ifc, stderr));
-                }
+        match link::create_vcan(&self.ifc, self.can_mtu(), Origin::Rebuild) {
+            Ok(()) => {}
+            Err(RustyCanError::LinkExists(_)) => {
+                info!("Interface {} was created concurrently, continuing", self.ifc);
+                return Ok(false);
+            }
+            Err(e) => {
+                return Err(RustyCanError::InterfaceCreate {
+                    name: self.ifc.clone(),
+                    reason: e.to_string(),
+                })
             }
-            Err(e) => return Err(format!(" !! Failed to execute ip command: {}", e)),
         }
 
         // Bring up the interface
-        let output = std::process::Command::new("ip")
-            .arg("link")
-            .arg("set")
-            .arg("up")
-            .arg(&self.ifc)
-            .output();
-
-        match output {
-            Ok(result) => {
-                if !result.status.success() {
-                    let stderr = String::from_utf8_lossy(&result.stderr);
-                    return Err(format!(" !! Failed to bring up VCAN device {}: {}", self.ifc, stderr));
-                }
-            }
-            Err(e) => return Err(format!(" !! Failed to execute ip command: {}", e)),
+        if let Err(e) = netlink::set_link_up(&self.ifc) {
+            return Err(RustyCanError::InterfaceUp {
+                name: self.ifc.clone(),
+                reason: e.to_string(),
+            });
         }
 
-        println!(" -> Successfully recreated network interface: {}", self.ifc);
+        info!("Successfully recreated network interface: {}", self.ifc);
         self.created = true;
         
-        Ok(())
+        Ok(true)
     }
 
     /// Validate that all network interfaces and rules are properly configured
@@ -176,36 +444,421 @@ impl Network {
         let mut healthy = true;
 
         // Check network interface
-        if !self.network_interface_exists() {
-            eprintln!(" !! Health check FAILED: Network interface {} does not exist", self.ifc);
-            healthy = false;
-        } else {
-            println!(" -> Health check OK: Network interface {} exists", self.ifc);
+        match self.network_interface_exists() {
+            Ok(true) => info!("Health check OK: Network interface {} exists", self.ifc),
+            Ok(false) => {
+                warn!("Health check FAILED: Network interface {} does not exist", self.ifc);
+                healthy = false;
+            }
+            Err(e) => {
+                warn!("Health check FAILED: Network interface {}: {}", self.ifc, e);
+                healthy = false;
+            }
         }
 
         // Check all endpoints
         let map = self.endpoint_list.read();
         for (_uid, ep) in map.iter() {
-            if !ep.interface_exists() {
-                eprintln!(" !! Health check FAILED: Endpoint interface {} does not exist", ep.device);
-                healthy = false;
-            } else {
-                println!(" -> Health check OK: Endpoint interface {} exists", ep.device);
+            match ep.interface_exists() {
+                Ok(true) => info!("Health check OK: Endpoint interface {} exists", ep.device),
+                Ok(false) => {
+                    warn!("Health check FAILED: Endpoint interface {} does not exist", ep.device);
+                    healthy = false;
+                }
+                Err(e) => {
+                    warn!("Health check FAILED: Endpoint interface {}: {}", ep.device, e);
+                    healthy = false;
+                }
             }
         }
 
         healthy
     }
 
+    pub fn ownership(&self) -> Ownership {
+        match (self.created, self.persist) {
+            (false, _) => Ownership::Adopted,
+            (true, true) => Ownership::Persistent,
+            (true, false) => Ownership::Created,
+        }
+    }
+
+    pub fn summary(&self, nuid: &str) -> NetworkSummary {
+        let mut endpoints: Vec<EndpointSummary> = self
+            .endpoint_list
+            .read()
+            .values()
+            .map(Network::endpoint_summary)
+            .collect();
+        endpoints.sort_by(|a, b| a.uid.cmp(&b.uid));
+
+        NetworkSummary {
+            nuid: nuid.to_string(),
+            device: self.device.clone(),
+            peer: self.peer.clone(),
+            canid: self.canid.clone(),
+            ifc: self.ifc.clone(),
+            ifindex: link::ifindex(&self.ifc),
+            created: self.created,
+            ownership: self.ownership(),
+            mode: self.mode,
+            endpoints,
+        }
+    }
+
+    fn endpoint_summary(ep: &Endpoint) -> EndpointSummary {
+        EndpointSummary {
+            uid: ep.uid.clone(),
+            device: ep.device.clone(),
+            peer: ep.peer.clone(),
+            created: ep.created,
+            interface_exists: ep.interface_exists().ok(),
+            device_ifindex: link::ifindex(&ep.device),
+            peer_ifindex: link::ifindex(&ep.peer),
+            mtu: ep.mtu,
+            direction: ep.direction,
+        }
+    }
+
+    /// Counts and approximate memory held by this network and its endpoints
+    pub fn resources(&self) -> NetworkResources {
+        let endpoints = self.endpoint_list.read();
+        let rules = self.rules_list.read();
+
+        let mut memory_bytes = std::mem::size_of::<Network>()
+            + self.device.capacity()
+            + self.peer.capacity()
+            + self.canid.capacity()
+            + self.ifc.capacity()
+            + self.filter.as_ref().map_or(0, |f| f.capacity())
+            + self.modify.as_ref().map_or(0, |m| m.capacity());
+        for ep in endpoints.values() {
+            memory_bytes += std::mem::size_of::<Endpoint>()
+                + ep.uid.capacity()
+                + ep.device.capacity()
+                + ep.peer.capacity()
+                + ep.options.to_string().len();
+        }
+        for rule in rules.iter() {
+            memory_bytes += std::mem::size_of::<CangwRule>()
+                + rule.src.capacity()
+                + rule.dst.capacity();
+            for job in &rule.jobs {
+                memory_bytes += std::mem::size_of::<CangwJob>()
+                    + job.args.iter().map(|a| a.capacity()).sum::<usize>();
+            }
+        }
+
+        NetworkResources {
+            endpoints: endpoints.len(),
+            interfaces_created: self.created as usize
+                + 2 * endpoints.values().filter(|ep| ep.created).count(),
+            rules: rules.len(),
+            cangw_jobs: rules.iter().map(|r| r.jobs.len()).sum(),
+            memory_bytes,
+        }
+    }
+
+    /// Operational view of one endpoint, for Docker's EndpointOperInfo
+    pub fn describe_endpoint(&self, epuid: &str) -> Option<EndpointSummary> {
+        self.endpoint_list
+            .read()
+            .get(epuid)
+            .map(Network::endpoint_summary)
+    }
+
+    /// Configuration this network was created with
+    pub fn config(&self) -> NetworkConfig {
+        NetworkConfig {
+            device: self.device.clone(),
+            peer: self.peer.clone(),
+            canid: self.canid.clone(),
+            persist: self.persist,
+            ephemeral: self.ephemeral,
+            realname: self.realname,
+            link: self.link,
+            hoplimit: self.hoplimit,
+            mode: self.mode,
+            filter: self.filter.clone(),
+            modify: self.modify.clone(),
+            ttl: self.ttl.map(|t| t.as_secs()),
+            ttl_refresh: self.ttl_refresh,
+            bitrate: self.bitrate,
+            fd: self.fd,
+            extended: self.extended,
+            dbitrate: self.dbitrate,
+            loopback: self.loopback,
+            prefix: self.prefix.clone(),
+            endpoints: Vec::new(),
+        }
+    }
+
+    /// Configuration saved to the state file: the creation settings and current endpoints
+    pub fn saved_config(&self) -> NetworkConfig {
+        let mut endpoints: Vec<SavedEndpoint> = self
+            .endpoint_list
+            .read()
+            .values()
+            .map(|ep| SavedEndpoint {
+                uid: ep.uid.clone(),
+                options: ep.options.clone(),
+                device: Some(ep.device.clone()),
+                peer: Some(ep.peer.clone()),
+            })
+            .collect();
+        endpoints.sort_by(|a, b| a.uid.cmp(&b.uid));
+        NetworkConfig {
+            endpoints,
+            ..self.config()
+        }
+    }
+
+    /// MTU of the virtual interfaces on this network: 72 with CAN FD, otherwise 16
+    pub fn can_mtu(&self) -> u32 {
+        link::can_mtu(self.fd)
+    }
+
+    fn expiry(config: &NetworkConfig, now: Instant) -> Option<Instant> {
+        config.ttl.map(|secs| now + Duration::from_secs(secs))
+    }
+
+    /// Whether the network's TTL has run out
+    pub fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|t| now >= t)
+    }
+
+    /// Restart the TTL after an attach, for networks that refresh it
+    pub fn refresh_ttl(&mut self, now: Instant) {
+        if let (true, Some(ttl)) = (self.ttl_refresh, self.ttl) {
+            self.expires_at = Some(now + ttl);
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        self.mode == NetworkMode::Direct
+    }
+
+    /// Build a new endpoint for this network
+    /// A direct network creates a vxcan pair for its first endpoint and hands the
+    /// other end to the second; a third endpoint is refused
+    pub fn new_endpoint(
+        &self,
+        nuid: &str,
+        uid: String,
+        options: serde_json::Value,
+        short_len: usize,
+        claimed: &HashSet<String>,
+        saved: Option<(String, String)>,
+    ) -> Result<Endpoint, RustyCanError> {
+        let mtu = self.can_mtu();
+        if !self.is_direct() {
+            return Endpoint::new(uid, options, &self.prefix, short_len, claimed, saved, mtu);
+        }
+
+        if Netem::from_options(&options)?.is_some() {
+            return Err(RustyCanError::InvalidOptions(String::from(
+                "vxcan.netem options are not supported on direct networks",
+            )));
+        }
+        if Direction::from_options(&options)? != Direction::Both {
+            return Err(RustyCanError::InvalidOptions(String::from(
+                "vxcan.direction is not supported on direct networks",
+            )));
+        }
+
+        let map = self.endpoint_list.read();
+        let mut others = map.values().filter(|ep| ep.uid != uid);
+        match (others.next(), others.next()) {
+            (None, _) => Endpoint::new(uid, options, &self.prefix, short_len, claimed, saved, mtu),
+            (Some(first), None) => {
+                // Both partners share one vxcan pair, and with it one MTU
+                if Endpoint::mtu_option(&options, mtu)? != first.mtu {
+                    return Err(RustyCanError::InvalidOptions(format!(
+                        "vxcan.mtu must match the other endpoint of a direct network ({})",
+                        first.mtu
+                    )));
+                }
+                Ok(Endpoint::direct_partner(uid, options, first))
+            }
+            _ => Err(RustyCanError::DirectNetworkFull(nuid.to_string())),
+        }
+    }
+
+    /// Kernel interface carrying this network's bus
+    pub fn interface(&self) -> &str {
+        &self.ifc
+    }
+
+    /// Prefix of this network's endpoint interface names
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn describe(&self, nuid: &str, now: Instant) -> NetworkDescription {
+        NetworkDescription {
+            summary: self.summary(nuid),
+            persist: self.persist,
+            ephemeral: self.ephemeral,
+            hoplimit: self.hoplimit,
+            filter: self.filter.clone(),
+            modify: self.modify.clone(),
+            bitrate: self.bitrate,
+            fd: self.fd,
+            extended: self.extended,
+            dbitrate: self.dbitrate,
+            loopback: self.loopback,
+            prefix: self.prefix.clone(),
+            ttl_remaining_secs: self
+                .expires_at
+                .map(|t| t.saturating_duration_since(now).as_secs()),
+            kernel: LinkStatus::query(&self.ifc),
+            rules: self
+                .rules_list
+                .read()
+                .iter()
+                .map(|r| (r.src.clone(), r.dst.clone()))
+                .collect(),
+            busoff: self.busoff,
+        }
+    }
+
+    /// Simulate a bus-off condition by taking the network interface down
+    /// Frames forwarded onto the bus are dropped until `recover_busoff` is called
+    pub fn trigger_busoff(&mut self) -> Result<(), RustyCanError> {
+        if self.is_direct() {
+            return Err(RustyCanError::InvalidOptions(String::from(
+                "direct networks have no bus to take down",
+            )));
+        }
+        if self.busoff {
+            return Ok(());
+        }
+
+        info!(
+            "Simulating bus-off on {} ({})",
+            self.ifc,
+            if link::is_hardware(&self.ifc) { "CAN controller" } else { "vcan" }
+        );
+        netlink::set_link_down(&self.ifc)?;
+        self.busoff = true;
+        Ok(())
+    }
+
+    /// Leave a simulated bus-off condition and bring the interface back up
+    /// Real CAN controllers get an automatic restart delay so a genuine bus-off recovers too
+    pub fn recover_busoff(&mut self) -> Result<(), RustyCanError> {
+        if !self.busoff {
+            return Ok(());
+        }
+
+        info!("Recovering {} from simulated bus-off", self.ifc);
+        if link::is_hardware(&self.ifc) {
+            // The CAN link attributes aren't set over netlink, so this one goes through ip
+            link::ip(&[
+                "link", "set", "dev", &self.ifc, "type", "can", "restart-ms", BUSOFF_RESTART_MS,
+            ])
+            .map_err(RustyCanError::LinkCommand)?;
+        }
+        netlink::set_link_up(&self.ifc)?;
+        self.busoff = false;
+        Ok(())
+    }
+
+    /// Look up an interface by name among the network device and its endpoints
+    pub fn inspect_interface(&self, nuid: &str, ifname: &str) -> Option<InterfaceInfo> {
+        let (role, endpoint_id, created) = if self.ifc == ifname {
+            (InterfaceRole::Network, None, self.created)
+        } else {
+            let map = self.endpoint_list.read();
+            let (ep, role) = map.values().find_map(|ep| {
+                if ep.device == ifname {
+                    Some((ep, InterfaceRole::EndpointDevice))
+                } else if ep.peer == ifname {
+                    Some((ep, InterfaceRole::EndpointPeer))
+                } else {
+                    None
+                }
+            })?;
+            (role, Some(ep.uid.clone()), ep.created)
+        };
+
+        Some(InterfaceInfo {
+            name: ifname.to_string(),
+            role,
+            network_id: nuid.to_string(),
+            endpoint_id,
+            device: self.device.clone(),
+            peer: self.peer.clone(),
+            canid: self.canid.clone(),
+            created,
+            kernel: LinkStatus::query(ifname),
+        })
+    }
+
     pub fn endpoint_add(&mut self, ep: Endpoint) {
         // Add the endpoint to the list
         self.endpoint_list.write().insert(ep.uid.clone(), ep);
     }
 
+    /// Move an endpoint rebuilt from the kernel under its full id once Docker refers to it
+    /// Reconstructed endpoints are keyed by the hex digits in their interface name, which
+    /// match one of the names the id hashes to, or for interfaces named before ids were
+    /// hashed, the start of the id
+    /// Returns true if an endpoint was moved
+    pub fn resolve_reconstructed(&mut self, epuid: &str, short_len: usize) -> bool {
+        let mut map = self.endpoint_list.write();
+        if map.contains_key(epuid) {
+            return false;
+        }
+        let names: HashSet<String> =
+            Endpoint::candidate_interface_names(&self.prefix, epuid, short_len)
+                .into_iter()
+                .map(|(device, _)| device)
+                .collect();
+        let short = map
+            .iter()
+            .filter(|(_, ep)| ep.reconstructed)
+            .find(|(uid, ep)| names.contains(&ep.device) || epuid.starts_with(uid.as_str()))
+            .map(|(uid, _)| uid.clone());
+        if let Some(mut ep) = short.and_then(|uid| map.remove(&uid)) {
+            info!("Matched reconstructed endpoint {} to {}", ep.uid, epuid);
+            ep.uid = epuid.to_string();
+            ep.reconstructed = false;
+            map.insert(epuid.to_string(), ep);
+            return true;
+        }
+        false
+    }
+
+    /// Apply saved options to an endpoint that was rebuilt without them
+    pub fn restore_endpoint_options(
+        &self,
+        epuid: &str,
+        options: serde_json::Value,
+    ) -> Result<(), RustyCanError> {
+        match self.endpoint_list.write().get_mut(epuid) {
+            Some(ep) => ep.restore_options(options),
+            None => Err(RustyCanError::EndpointNotFound(epuid.to_string())),
+        }
+    }
+
+    /// Track a gateway rule found in the kernel between this network's interfaces
+    /// Returns false if it was already tracked
+    /// The kernel doesn't report a job's options, so they're assumed to be the network's
+    pub fn adopt_rule(&self, src: &str, dst: &str) -> bool {
+        let mut rules = self.rules_list.write();
+        if rules.iter().any(|r| r.is(src, dst)) {
+            return false;
+        }
+        rules.push(self.rule(src, dst));
+        true
+    }
+
     pub fn endpoint_remove(&mut self, uid: String) {
+        self.peer_indices.lock().remove(&uid);
         let mut map = self.endpoint_list.write();
         if map.contains_key(&uid) {
-            println!(" -> Endpoint exists...removing!");
+            info!("Endpoint exists...removing!");
             map.remove(&uid);
         }
     }
@@ -215,13 +868,18 @@ impl Network {
         epuid: String,
         _namespace: String,
         peer: String,
-    ) -> Result<JoinResponse, Error> {
+        minimal_repair: bool,
+    ) -> Result<JoinResponse, RustyCanError> {
+        if self.is_direct() {
+            return self.endpoint_attach_direct(epuid, peer);
+        }
+
         // REBOOT RESILIENCE: Ensure network interface exists before proceeding
         // After system reboot, Docker metadata persists but kernel interfaces don't.
         // This check recreates missing interfaces transparently during container restart.
         if let Err(e) = self.ensure_network_interface_exists() {
-            eprintln!(" !! Failed to ensure network interface exists: {}", e);
-            return Err(Error);
+            error!("Failed to ensure network interface exists: {}", e);
+            return Err(e);
         }
 
         // Drop read lock before acquiring write lock to avoid deadlock
@@ -231,8 +889,8 @@ impl Network {
         };
 
         if !endpoint_exists {
-            eprintln!(" !! Endpoint not found in network");
-            return Err(Error);
+            warn!("Endpoint not found in network");
+            return Err(RustyCanError::EndpointNotFound(epuid));
         }
 
         // REBOOT RESILIENCE: Check and recreate endpoint's vxcan interface if missing
@@ -243,12 +901,12 @@ impl Network {
                 match ep.ensure_interface_exists() {
                     Ok(recreated) => {
                         if recreated {
-                            println!(" -> Endpoint interfaces were recreated after reboot");
+                            info!("Endpoint interfaces were recreated after reboot");
                         }
                     }
                     Err(e) => {
-                        eprintln!(" !! Failed to ensure endpoint interface exists: {}", e);
-                        return Err(Error);
+                        error!("Failed to ensure endpoint interface exists: {}", e);
+                        return Err(e);
                     }
                 }
             }
@@ -259,146 +917,714 @@ impl Network {
         match map.get(&epuid) {
             Some(ep) => {
                 // Add cangw rules: self->endpoint, endpoint->self
-                self.add_cangw_rule(&self.ifc, &ep.device);
-                self.add_cangw_rule(&ep.device, &self.ifc);
+                let mut pairs = self.bus_pairs(ep);
 
                 for (uid, endpt) in map.iter() {
                     if uid.ne(&epuid) {
                         // REBOOT RESILIENCE: Check other endpoints too
                         // In case multiple containers are restarting simultaneously
-                        if !endpt.interface_exists() {
-                            println!(" -> Warning: A peer endpoint interface is missing, skipping cross-rules for now");
+                        if let Ok(false) = endpt.interface_exists() {
+                            info!("Warning: A peer endpoint interface is missing, skipping cross-rules for now");
                             continue;
                         }
                         
                         // Add cangw rules: other->endpoint, endpoint->other
-                        self.add_cangw_rule(&endpt.device, &ep.device);
-                        self.add_cangw_rule(&ep.device, &endpt.device);
+                        pairs.extend(Network::peer_pairs(ep, endpt));
                     }
                 }
 
-                let mut peerifc = &peer;
-                if peer.is_empty() {
-                    peerifc = &self.peer;
+                if minimal_repair {
+                    match self.repair_cangw_rules(&pairs) {
+                        Ok(installed) => info!(
+                            "Installed {} missing cangw jobs for endpoint {}",
+                            installed, epuid
+                        ),
+                        Err(e) => {
+                            error!("Failed to repair cangw rules for {}: {}", epuid, e);
+                            return Err(e);
+                        }
+                    }
+                } else {
+                    // A repeated Join must not install a second copy of a tracked rule;
+                    // the kernel would forward each frame twice
+                    {
+                        let tracked = self.rules_list.read();
+                        pairs.retain(|(src, dst)| !tracked.iter().any(|r| r.is(src, dst)));
+                    }
+                    if let Err(e) = self.add_cangw_rules(&pairs) {
+                        error!("Failed to add cangw rules for {}: {}", epuid, e);
+                        return Err(e);
+                    }
+                }
+
+                if let Some(netem) = &ep.netem {
+                    if let Err(e) = netem.apply(&ep.device) {
+                        error!("Failed to apply netem to {}: {}", ep.device, e);
+                        // The container won't start, so don't leave its rules behind
+                        if !minimal_repair {
+                            for (src, dst) in pairs.iter().rev() {
+                                let _ = self.remove_cangw_rule(src, dst);
+                            }
+                        }
+                        return Err(e);
+                    }
+                    info!("Applied netem {:?} to {}", netem, ep.device);
                 }
 
                 let rsp = JoinResponse {
                     SrcName: ep.peer.clone(),
-                    DstPrefix: (*peerifc).clone(),
+                    DstPrefix: self.dst_prefix(&epuid, &peer),
                 };
                 Ok(rsp)
             }
-            None => Err(Error),
+            None => Err(RustyCanError::EndpointNotFound(epuid)),
+        }
+    }
+
+    /// Gateway rules between the bus and `ep`, as its vxcan.direction allows
+    fn bus_pairs(&self, ep: &Endpoint) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        if ep.direction.receives() {
+            pairs.push((self.ifc.clone(), ep.device.clone()));
+        }
+        if ep.direction.sends() {
+            pairs.push((ep.device.clone(), self.ifc.clone()));
         }
+        pairs
     }
 
-    pub fn endpoint_detach(&mut self, epuid: String) {
+    /// Gateway rules between two endpoints: a frame is only routed from one that
+    /// sends to one that receives
+    fn peer_pairs(ep: &Endpoint, other: &Endpoint) -> Vec<(String, String)> {
+        let mut pairs = Vec::new();
+        if other.direction.sends() && ep.direction.receives() {
+            pairs.push((other.device.clone(), ep.device.clone()));
+        }
+        if ep.direction.sends() && other.direction.receives() {
+            pairs.push((ep.device.clone(), other.device.clone()));
+        }
+        pairs
+    }
+
+    /// Join an endpoint of a direct network: its end of the shared vxcan pair is
+    /// handed to the container and no gateway rules are installed
+    fn endpoint_attach_direct(
+        &self,
+        epuid: String,
+        peer: String,
+    ) -> Result<JoinResponse, RustyCanError> {
         let map = self.endpoint_list.read();
-        match map.get(&epuid) {
-            Some(ep) => {
-                for (uid, endpt) in map.iter() {
-                    if uid.ne(&epuid) {
-                        // Remove cangw rules: other->endpoint, endpoint->other
-                        self.remove_cangw_rule(&endpt.device, &ep.device);
-                        self.remove_cangw_rule(&ep.device, &endpt.device);
-                    }
+        let ep = match map.get(&epuid) {
+            Some(ep) => ep,
+            None => {
+                warn!("Endpoint not found in network");
+                return Err(RustyCanError::EndpointNotFound(epuid));
+            }
+        };
+
+        match link::interface_exists(&ep.peer) {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Direct endpoint interface {} is missing", ep.peer);
+                return Err(RustyCanError::InterfaceNotFound(ep.peer.clone()));
+            }
+            Err(e) => {
+                warn!("{}", e);
+                return Err(e);
+            }
+        }
+
+        Ok(JoinResponse {
+            SrcName: ep.peer.clone(),
+            DstPrefix: self.dst_prefix(&epuid, &peer),
+        })
+    }
+
+    /// In-container interface prefix for an endpoint: the Join's vxcan.peer, or the
+    /// network's. A `{index}` in it becomes the lowest index no other joined endpoint
+    /// holds; the endpoint keeps its index until it leaves, so a re-join gets the same one
+    fn dst_prefix(&self, epuid: &str, peer: &str) -> String {
+        let prefix = if peer.is_empty() { &self.peer } else { peer };
+        if !prefix.contains(PEER_INDEX) {
+            return prefix.to_string();
+        }
+        let mut indices = self.peer_indices.lock();
+        let index = match indices.get(epuid) {
+            Some(index) => *index,
+            None => {
+                let taken: HashSet<usize> = indices.values().copied().collect();
+                let index = (0..).find(|i| !taken.contains(i)).unwrap_or_default();
+                indices.insert(epuid.to_string(), index);
+                index
+            }
+        };
+        prefix.replace(PEER_INDEX, &index.to_string())
+    }
+
+    /// Remove every gateway rule connecting an endpoint to the bus and its peers
+    /// All rules are attempted even if one fails; the first failure is returned
+    pub fn endpoint_detach(&mut self, epuid: String) -> Result<(), RustyCanError> {
+        self.peer_indices.lock().remove(&epuid);
+        let map = self.endpoint_list.read();
+        let ep = match map.get(&epuid) {
+            Some(ep) => ep,
+            None => return Err(RustyCanError::EndpointNotFound(epuid)),
+        };
+
+        // The same directions endpoint_attach installed for each endpoint's vxcan.direction
+        let mut pairs = Vec::new();
+        for (uid, endpt) in map.iter() {
+            if uid.ne(&epuid) {
+                // Remove cangw rules: other->endpoint, endpoint->other
+                pairs.extend(Network::peer_pairs(ep, endpt));
+            }
+        }
+
+        // Remove cangw rules: self->endpoint, endpoint->self
+        pairs.extend(self.bus_pairs(ep).into_iter().rev());
+
+        let mut result = Ok(());
+        for (src, dst) in &pairs {
+            if let Err(e) = self.remove_cangw_rule(src, dst) {
+                error!("Failed to remove cangw rule {} -> {}: {}", src, dst, e);
+                if result.is_ok() {
+                    result = Err(e);
                 }
+            }
+        }
 
-                // Remove cangw rules: self->endpoint, endpoint->self
-                self.remove_cangw_rule(&ep.device, &self.ifc);
-                self.remove_cangw_rule(&self.ifc, &ep.device);
+        if ep.netem.is_some() {
+            if let Err(e) = Netem::clear(&ep.device) {
+                error!("Failed to remove netem from {}: {}", ep.device, e);
             }
-            None => (),
+        }
+        result
+    }
+
+    /// Record whether this network's interface and each endpoint interface are up
+    pub fn record_interface_state(
+        &self,
+        nuid: &str,
+        networks: &mut StateGauge,
+        endpoints: &mut StateGauge,
+    ) {
+        let up = |name: &str| {
+            let status = LinkStatus::query(name);
+            status.exists && status.up
         };
+
+        if !self.is_direct() {
+            networks.set(&[nuid], up(&self.ifc));
+        }
+        for ep in self.endpoint_list.read().values() {
+            endpoints.set(&[nuid, &ep.uid], up(&ep.device));
+        }
+    }
+
+    /// Source and destination of every tracked rule from the network bus to an endpoint
+    pub fn forwarding_paths(&self) -> Vec<(String, String)> {
+        let map = self.endpoint_list.read();
+        let rules = self.rules_list.read();
+        map.values()
+            .map(|ep| (self.ifc.clone(), ep.device.clone()))
+            .filter(|(src, dst)| rules.iter().any(|r| r.is(src, dst)))
+            .collect()
+    }
+
+    /// Every interface name this network accounts for, including endpoint peers
+    pub fn interface_names(&self) -> Vec<String> {
+        let mut names = vec![self.ifc.clone()];
+        names.retain(|n| !n.is_empty());
+        for ep in self.endpoint_list.read().values() {
+            names.push(ep.device.clone());
+            names.push(ep.peer.clone());
+        }
+        names
+    }
+
+    /// Detach every endpoint at once by removing all tracked gateway rules
+    /// With `remove_interfaces` the endpoints are dropped too, deleting their vxcan pairs
+    /// Returns the ids of the endpoints that were detached
+    pub fn detach_all(&mut self, remove_interfaces: bool) -> Vec<String> {
+        let rules = self.rules_list.read().clone();
+        for rule in rules.iter() {
+            if let Err(e) = self.remove_cangw_rule(&rule.src, &rule.dst) {
+                error!("Failed to remove cangw rule {} -> {}: {}", rule.src, rule.dst, e);
+            }
+        }
+
+        for ep in self.endpoint_list.read().values().filter(|ep| ep.netem.is_some()) {
+            if let Err(e) = Netem::clear(&ep.device) {
+                error!("Failed to remove netem from {}: {}", ep.device, e);
+            }
+        }
+
+        // As endpoint_detach does for one endpoint, free the `{index}` each one held
+        self.peer_indices.lock().clear();
+
+        let mut uids: Vec<String> = self.endpoint_list.read().keys().cloned().collect();
+        uids.sort();
+        if remove_interfaces {
+            self.endpoint_list.write().clear();
+        }
+        uids
+    }
+
+    /// Kernel frame counters for this network's tracked gateway jobs
+    /// Tracked rules missing from the kernel are left out
+    pub fn rule_stats(&self, kernel: &[RuleStats]) -> Vec<RuleStats> {
+        let rules = self.rules_list.read();
+        kernel
+            .iter()
+            .filter(|s| rules.iter().any(|r| r.is(&s.rule.src, &s.rule.dst)))
+            .cloned()
+            .collect()
+    }
+
+    /// Interfaces whose gateway rules this network manages
+    pub fn managed_interfaces(&self) -> Vec<String> {
+        let mut ifcs = vec![self.ifc.clone()];
+        ifcs.extend(self.endpoint_list.read().values().map(|ep| ep.device.clone()));
+        ifcs
+    }
+
+    /// Compare the rules the joined endpoints expect with the kernel's gateway jobs
+    pub fn diagnose_rules(&self, kernel: &[KernelRule]) -> RuleDiagnosis {
+        let intended: BTreeSet<KernelRule> = self.expected_rules().into_iter().collect();
+        let managed: HashSet<String> = self.managed_interfaces().into_iter().collect();
+        let diff = diff_rules(&intended, &managed, kernel);
+        RuleDiagnosis {
+            present: intended.difference(&diff.missing).cloned().collect(),
+            missing: diff.missing.into_iter().collect(),
+            untracked: diff.external.into_iter().collect(),
+        }
+    }
+
+    /// Rules endpoint_attach installs for every joined endpoint, as each one's
+    /// vxcan.direction allows
+    /// Derived from the endpoints rather than the tracked rules, which are empty after
+    /// a restart. An endpoint whose peer is still in the host namespace hasn't joined
+    /// a container, or has left it, and expects no rules
+    fn expected_pairs(&self) -> Vec<(String, String)> {
+        if self.is_direct() {
+            return Vec::new();
+        }
+        let map = self.endpoint_list.read();
+        let mut joined: Vec<&Endpoint> = map
+            .values()
+            .filter(|ep| !matches!(link::interface_exists(&ep.peer), Ok(true)))
+            .collect();
+        joined.sort_by(|a, b| a.uid.cmp(&b.uid));
+
+        let mut pairs = Vec::new();
+        for (i, ep) in joined.iter().enumerate() {
+            pairs.extend(self.bus_pairs(ep));
+            for other in &joined[i + 1..] {
+                pairs.extend(Network::peer_pairs(ep, other));
+            }
+        }
+        pairs
     }
 
-    fn add_cangw_rule(&self, src: &String, dst: &String) {
-        println!(" -> Adding cangw rule for {src} to {dst}");
+    /// Kernel gateway jobs expected to exist for the joined endpoints
+    pub fn expected_rules(&self) -> Vec<KernelRule> {
+        self.expected_pairs()
+            .iter()
+            .flat_map(|(src, dst)| {
+                [KernelRule::new(src, dst, false), KernelRule::new(src, dst, true)]
+            })
+            .collect()
+    }
 
-        std::process::Command::new("cangw")
-            .arg("-A")
-            .arg("-s")
-            .arg(&src)
-            .arg("-d")
-            .arg(&dst)
-            .arg("-e")
-            .output()
-            .expect(" !! Failed to add cangw rule");
+    /// Re-install a single expected gateway job that went missing from the kernel,
+    /// and track its rule
+    /// With several ingress filters, a job is installed for each of them
+    pub fn reinstall_rule(&self, rule: &KernelRule) -> bool {
+        let jobs: Vec<CangwJob> = self
+            .rule(&rule.src, &rule.dst)
+            .jobs
+            .into_iter()
+            .filter(|j| j.fd == rule.fd)
+            .collect();
+        for job in &jobs {
+            match cangw::add(job) {
+                Ok(result) if result.status.success() => {}
+                _ => return false,
+            }
+        }
+        self.adopt_rule(&rule.src, &rule.dst);
+        METRICS
+            .cangw_rules_installed
+            .inc_with(&[("src", &rule.src), ("dst", &rule.dst)]);
+        true
+    }
 
-        std::process::Command::new("cangw")
-            .arg("-A")
-            .arg("-s")
-            .arg(&src)
-            .arg("-d")
-            .arg(&dst)
-            .arg("-eX")
-            .output()
-            .expect(" !! Failed to add cangw extended rule");
+    /// Install only those gateway jobs for `pairs` that are missing from the kernel,
+    /// leaving healthy ones untouched, and track every pair
+    /// On failure the jobs installed and the pairs newly tracked by this call are
+    /// taken back out, latest first, and jobs that were already there are left alone
+    /// Returns the number of jobs installed
+    fn repair_cangw_rules(&self, pairs: &[(String, String)]) -> Result<usize, RustyCanError> {
+        let kernel: BTreeSet<KernelRule> = cangw::list()
+            .map_err(RustyCanError::RuleQuery)?
+            .into_iter()
+            .collect();
 
-        self.rules_list.write().push((src.clone(), dst.clone()));
+        let mut added: Vec<CangwJob> = Vec::new();
+        let mut adopted: Vec<(&str, &str)> = Vec::new();
+        let mut installed = 0;
+        for (src, dst) in pairs {
+            let rule = self.rule(src, dst);
+            for fd in [false, true] {
+                if kernel.contains(&KernelRule::new(src, dst, fd)) {
+                    continue;
+                }
+                info!("Re-installing missing cangw rule for {src} to {dst} (fd={fd})");
+                for job in rule.jobs.iter().filter(|j| j.fd == fd) {
+                    if let Err(e) = cangw_added(cangw::add(job)) {
+                        self.unwind_cangw_jobs(&added, &adopted);
+                        return Err(e);
+                    }
+                    added.push(job.clone());
+                }
+                METRICS
+                    .cangw_rules_installed
+                    .inc_with(&[("src", src), ("dst", dst)]);
+                installed += 1;
+            }
+            if self.adopt_rule(src, dst) {
+                adopted.push((src, dst));
+            }
+        }
+        Ok(installed)
+    }
+
+    // Take back the jobs and tracked pairs of a failed attach, latest first
+    fn unwind_cangw_jobs(&self, jobs: &[CangwJob], pairs: &[(&str, &str)]) {
+        for job in jobs.iter().rev() {
+            if let Err(e) = cangw_removed(cangw::remove(job)) {
+                error!("Unable to roll back cangw job {}: {}", job.args.join(" "), e);
+            }
+        }
+        self.rules_list
+            .write()
+            .retain(|r| !pairs.iter().any(|(src, dst)| r.is(src, dst)));
+    }
+
+    /// Settings of each gateway job installed for a rule from `src` to `dst`
+    /// The ingress filters apply to frames entering the network from an endpoint,
+    /// with one job per filter; every other rule is a single unfiltered job
+    /// The modifications apply to every job delivering frames to an endpoint; an
+    /// unfiltered rule with modifications gets a second job passing probe frames as sent
+    fn job_options(&self, src: &str, dst: &str) -> Vec<JobOptions> {
+        let modify = self.modify.clone().filter(|_| dst != self.ifc);
+        match self.filter.as_ref().filter(|_| src != self.ifc) {
+            Some(filters) => filters
+                .split(',')
+                .map(|f| JobOptions {
+                    hoplimit: self.hoplimit,
+                    filter: Some(f.to_string()),
+                    modify: modify.clone(),
+                })
+                .collect(),
+            // Probe frames skip the modifications, or they'd never be recognised
+            None if modify.is_some() => {
+                let [modified, probe] = probe::modify_exemption();
+                vec![
+                    JobOptions {
+                        hoplimit: self.hoplimit,
+                        filter: Some(modified),
+                        modify,
+                    },
+                    JobOptions {
+                        hoplimit: self.hoplimit,
+                        filter: Some(probe),
+                        modify: None,
+                    },
+                ]
+            }
+            None => vec![JobOptions {
+                hoplimit: self.hoplimit,
+                filter: None,
+                modify,
+            }],
+        }
+    }
+
+    /// The rule from `src` to `dst` with the classic and FD jobs for each of its job settings
+    fn rule(&self, src: &str, dst: &str) -> CangwRule {
+        let jobs = self
+            .job_options(src, dst)
+            .iter()
+            .flat_map(|opts| [false, true].map(|fd| CangwJob::new(src, dst, fd, opts)))
+            .collect();
+        CangwRule {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            jobs,
+        }
     }
 
-    fn remove_cangw_rule(&self, src: &String, dst: &String) {
+    /// Install the rules for every (src, dst) pair
+    /// cangw takes one job per process, so the jobs are spread over a few threads
+    /// rather than run one after another. A job that still fails after cangw's
+    /// retries, or a worker that panics, is an error, and every job the batch installed
+    /// is taken back out so nothing is left untracked
+    fn add_cangw_rules(&self, pairs: &[(String, String)]) -> Result<(), RustyCanError> {
+        let rules: Vec<CangwRule> = pairs.iter().map(|(src, dst)| self.rule(src, dst)).collect();
+        let jobs: Vec<&CangwJob> = rules.iter().flat_map(|r| &r.jobs).collect();
+        info!("Adding {} cangw rules ({} jobs)", rules.len(), jobs.len());
+
+        // A recorded trace is compared command by command, so keep its order stable
+        let workers = if command::trace_status().is_some() { 1 } else { CANGW_WORKERS };
+        let chunk = jobs.len().div_ceil(workers).max(1);
+        // Each job is recorded as soon as it's in, so a rollback sees every one of them
+        let added: Mutex<Vec<CangwJob>> = Mutex::new(Vec::new());
+        let failed: Mutex<Option<RustyCanError>> = Mutex::new(None);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = jobs
+                .chunks(chunk)
+                .map(|part| {
+                    let (added, failed) = (&added, &failed);
+                    s.spawn(move || {
+                        for job in part {
+                            // The batch is rolled back once a job fails, so stop adding
+                            if failed.lock().is_some() {
+                                return;
+                            }
+                            match cangw_added(cangw::add(job)) {
+                                Ok(()) => added.lock().push((*job).clone()),
+                                Err(e) => {
+                                    failed.lock().get_or_insert(e);
+                                    return;
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                if h.join().is_err() {
+                    failed.lock().get_or_insert(RustyCanError::RuleCommand(String::from(
+                        "cangw worker panicked",
+                    )));
+                }
+            }
+        });
+
+        if let Some(e) = failed.into_inner() {
+            self.unwind_cangw_jobs(&added.into_inner(), &[]);
+            return Err(e);
+        }
+
+        for rule in &rules {
+            METRICS
+                .cangw_rules_installed
+                .inc_with(&[("src", &rule.src), ("dst", &rule.dst)]);
+        }
+        self.rules_list.write().extend(rules);
+        Ok(())
+    }
+
+    fn remove_cangw_rule(&self, src: &String, dst: &String) -> Result<(), RustyCanError> {
         let mut rules = self.rules_list.write();
-        if rules.contains(&(src.clone(), dst.clone())) {
-            println!(" -> Removing cangw rule for {src} to {dst}");
-
-            std::process::Command::new("cangw")
-                .arg("-D")
-                .arg("-s")
-                .arg(&src)
-                .arg("-d")
-                .arg(&dst)
-                .arg("-e")
-                .output()
-                .expect(" !! Failed to remove cangw rule");
-
-            std::process::Command::new("cangw")
-                .arg("-D")
-                .arg("-s")
-                .arg(&src)
-                .arg("-d")
-                .arg(&dst)
-                .arg("-eX")
-                .output()
-                .expect(" !! Failed to remove cangw extended rule");
-
-            let index = rules
-                .iter()
-                .position(|x| *x == (src.clone(), dst.clone()))
-                .unwrap(); // safe unwrap since we already verified key exists
+        if let Some(index) = rules.iter().position(|r| r.is(src, dst)) {
+            info!("Removing cangw rule for {src} to {dst}");
+
+            // Replay the jobs exactly as installed; keep tracking the rule if the
+            // kernel still has any of them, so it isn't forgotten
+            for job in &rules[index].jobs {
+                cangw_removed(cangw::remove(job))?;
+            }
             rules.remove(index);
         }
+        Ok(())
+    }
+}
+
+fn cangw_added(result: std::io::Result<std::process::Output>) -> Result<(), RustyCanError> {
+    match result {
+        Ok(out) if out.status.success() => Ok(()),
+        Ok(out) => Err(RustyCanError::RuleCommand(
+            String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        )),
+        Err(e) => Err(RustyCanError::RuleCommand(format!("failed to execute cangw: {e}"))),
+    }
+}
+
+/// Treat a gateway job that's already gone from the kernel as removed
+fn cangw_removed(result: std::io::Result<std::process::Output>) -> Result<(), RustyCanError> {
+    match result {
+        Ok(out) if out.status.success() => Ok(()),
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            if stderr.contains("No such file or directory") {
+                Ok(())
+            } else {
+                Err(RustyCanError::RuleCommand(stderr.trim().to_string()))
+            }
+        }
+        Err(e) => Err(RustyCanError::RuleCommand(format!("failed to execute cangw: {e}"))),
     }
 }
 
 impl Drop for Network {
     fn drop(&mut self) {
-        if self.created {
-            let ifc = format!("{}{}", self.device, self.canid);
-
-            // Actually delete the network interface
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("set")
-                .arg("down")
-                .arg(&ifc)
-                .output()
-                .expect(" !! Failed to stop VCAN device");
-            std::process::Command::new("ip")
-                .arg("link")
-                .arg("del")
-                .arg("dev")
-                .arg(&ifc)
-                .arg("type")
-                .arg("vcan")
-                .output()
-                .expect(" !! Failed to remove VCAN device");
-
-            println!(
-                " -> Dropping network object: device={}, peer={}, id={}",
+        #[cfg(debug_assertions)]
+        debug_assert!(
+            !crate::manager::map_write_held(),
+            "network on {} torn down while the network map is locked",
+            self.ifc
+        );
+        if self.created && self.persist {
+            info!(
+                "Keeping interface {} for persistent network: device={}, peer={}, id={}",
+                self.ifc, self.device, self.peer, self.canid
+            );
+        } else if self.created {
+            let ifc = self.ifc.clone();
+
+            if let Some(pool) = &self.pool {
+                if pool.give_back(&ifc) {
+                    return;
+                }
+            }
+
+            // Actually delete the network interface, unless something already did
+            match netlink::delete_link(&ifc) {
+                Ok(()) | Err(RustyCanError::InterfaceNotFound(_)) => {}
+                Err(e) => error!("Failed to remove VCAN device {}: {}", ifc, e),
+            }
+
+            info!(
+                "Dropping network object: device={}, peer={}, id={}",
                 self.device, self.peer, self.canid
             );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::command::MockRunner;
+
+    // A direct network has no bus interface, so it is built without touching the kernel
+    fn direct_network(ttl: u64, ttl_refresh: bool, now: Instant) -> Network {
+        let config: NetworkConfig = serde_json::from_value(serde_json::json!({
+            "device": "vxcan",
+            "peer": "vcan",
+            "canid": "0",
+            "mode": "direct",
+            "ttl": ttl,
+            "ttl_refresh": ttl_refresh,
+        }))
+        .unwrap();
+        Network::new(&config, Origin::Request, now).unwrap()
+    }
+
+    // Creates the bus interface through netlink, so only with a MockRunner installed
+    fn bus_network(canid: &str) -> Network {
+        let config: NetworkConfig = serde_json::from_value(serde_json::json!({
+            "device": "vcan",
+            "peer": "vxcan",
+            "canid": canid,
+        }))
+        .unwrap();
+        Network::new(&config, Origin::Request, Instant::now()).unwrap()
+    }
+
+    fn pair(src: &str, dst: &str) -> (String, String) {
+        (src.to_string(), dst.to_string())
+    }
+
+    #[test]
+    fn adding_a_rule_installs_a_classic_and_an_fd_job() {
+        let mock = MockRunner::new();
+        let _mock = mock.install();
+        let network = bus_network("770");
+
+        network.add_cangw_rules(&[pair("vcan770", "vxcan1")]).unwrap();
+
+        // The jobs are spread over worker threads, so their order isn't fixed
+        let mut calls = mock.calls_to("cangw");
+        calls.sort();
+        assert_eq!(calls, ["-A -s vcan770 -d vxcan1 -e", "-A -s vcan770 -d vxcan1 -eX"]);
+        assert_eq!(network.rules_list.read().len(), 1);
+        assert!(network.rules_list.read()[0].is("vcan770", "vxcan1"));
+    }
+
+    // Every job that went in was taken back out with the same arguments
+    fn assert_rolled_back(mock: &MockRunner, failing: &str) {
+        let calls = mock.calls_to("cangw");
+        let mut added: Vec<&str> = calls
+            .iter()
+            .filter_map(|c| c.strip_prefix("-A "))
+            .filter(|c| !c.contains(failing))
+            .collect();
+        let mut removed: Vec<&str> = calls.iter().filter_map(|c| c.strip_prefix("-D ")).collect();
+        added.sort();
+        removed.sort();
+        assert_eq!(added, removed);
+    }
+
+    #[test]
+    fn a_failed_peer_rule_rolls_back_the_rules_before_it() {
+        // cangw's answer for an interface that's gone
+        let mock = MockRunner::responding(|call| {
+            if call.args.contains(&String::from("vxcan3")) {
+                Err(String::from("No such file or directory"))
+            } else {
+                Ok(String::new())
+            }
+        });
+        let _mock = mock.install();
+        let network = bus_network("786");
+        let pairs = ["vxcan1", "vxcan2", "vxcan3"].map(|dst| pair("vcan786", dst));
+
+        assert!(matches!(network.add_cangw_rules(&pairs), Err(RustyCanError::RuleCommand(_))));
+        assert_rolled_back(&mock, "vxcan3");
+        assert!(network.rules_list.read().is_empty());
+    }
+
+    #[test]
+    fn a_panicking_cangw_worker_is_an_error() {
+        let mock = MockRunner::responding(|call| {
+            assert!(!call.args.contains(&String::from("vxcan3")), "cangw blew up");
+            Ok(String::new())
+        });
+        let _mock = mock.install();
+        let network = bus_network("787");
+        let pairs = ["vxcan1", "vxcan2", "vxcan3"].map(|dst| pair("vcan787", dst));
+
+        let err = network.add_cangw_rules(&pairs).unwrap_err();
+        assert_eq!(err, RustyCanError::RuleCommand(String::from("cangw worker panicked")));
+        assert_rolled_back(&mock, "vxcan3");
+        assert!(network.rules_list.read().is_empty());
+    }
+
+    #[test]
+    fn network_expires_once_its_ttl_has_passed() {
+        let clock = ManualClock::new();
+        let network = direct_network(60, false, clock.now());
+
+        clock.advance(Duration::from_secs(59));
+        assert!(!network.expired(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(network.expired(clock.now()));
+    }
+
+    #[test]
+    fn attach_restarts_a_refreshing_ttl() {
+        let clock = ManualClock::new();
+        let mut network = direct_network(60, true, clock.now());
+
+        clock.advance(Duration::from_secs(50));
+        network.refresh_ttl(clock.now());
+        clock.advance(Duration::from_secs(50));
+        assert!(!network.expired(clock.now()));
+        clock.advance(Duration::from_secs(10));
+        assert!(network.expired(clock.now()));
+    }
+}
@@ -0,0 +1,163 @@
+/*
+ * Filename: pool.rs
+ * Created Date: Friday, October 16th 2026, 2:30:12 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use crate::link::{self, LinkStatus, Origin};
+use log::{error, info};
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+// Name prefix for idle pooled interfaces
+const POOL_PREFIX: &str = "rcpool";
+
+/// Pre-created vcan interfaces handed out to new networks by renaming them,
+/// so the CreateNetwork path doesn't pay for `ip link add`
+pub struct InterfacePool {
+    target: usize,
+    free: Mutex<Vec<String>>,
+    // Held while refilling so only one refill runs at a time
+    filling: Mutex<()>,
+}
+
+impl InterfacePool {
+    pub fn new(target: usize) -> Self {
+        InterfacePool {
+            target,
+            free: Mutex::new(Vec::new()),
+            filling: Mutex::new(()),
+        }
+    }
+
+    /// Whether a name follows the pooled interface naming scheme
+    pub fn is_pool_name(name: &str) -> bool {
+        name.strip_prefix(POOL_PREFIX)
+            .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+    }
+
+    /// Number of idle interfaces currently in the pool
+    pub fn free_count(&self) -> usize {
+        self.free.lock().len()
+    }
+
+    /// Whether the pool currently holds the named idle interface
+    pub fn holds(&self, name: &str) -> bool {
+        self.free.lock().iter().any(|p| p == name)
+    }
+
+    /// Create (or adopt leftover) pooled interfaces until the pool is full
+    pub fn fill(&self) {
+        let _filling = self.filling.lock();
+
+        let mut slot = 0;
+        while self.free.lock().len() < self.target && slot < self.target * 4 {
+            let name = format!("{POOL_PREFIX}{slot}");
+            slot += 1;
+
+            if self.free.lock().contains(&name) {
+                continue;
+            }
+
+            // Interfaces left over from a previous run are reused as-is
+            if !LinkStatus::query(&name).exists
+                && link::create_vcan(&name, link::CAN_MTU, Origin::Pool).is_err()
+            {
+                error!("Failed to pre-create pooled interface {}", name);
+                continue;
+            }
+            self.free.lock().push(name);
+        }
+        info!(
+            "Interface pool holds {} of {} vcan interfaces",
+            self.free.lock().len(),
+            self.target
+        );
+    }
+
+    /// Refill the pool on a background thread
+    pub fn refill(self: &Arc<Self>) {
+        let pool = self.clone();
+        std::thread::spawn(move || pool.fill());
+    }
+
+    /// Rename a pooled interface to `name` and bring it up
+    /// Returns false when the pool is empty or the rename failed
+    pub fn take(self: &Arc<Self>, name: &str) -> bool {
+        let pooled = match self.free.lock().pop() {
+            Some(p) => p,
+            None => return false,
+        };
+
+        let ok =
+            ip(&["link", "set", "dev", &pooled, "name", name]) && ip(&["link", "set", "up", name]);
+        if ok {
+            info!("Using pooled interface {} as {}", pooled, name);
+        } else {
+            error!(
+                "Failed to claim pooled interface {} as {}",
+                pooled, name
+            );
+            if LinkStatus::query(&pooled).exists {
+                self.free.lock().push(pooled);
+            }
+        }
+
+        self.refill();
+        ok
+    }
+
+    /// Return an interface to the pool instead of deleting it
+    /// Returns false if the pool is already full, in which case the caller removes it
+    pub fn give_back(&self, name: &str) -> bool {
+        let mut free = self.free.lock();
+        if free.len() >= self.target {
+            return false;
+        }
+
+        let slot = (0..self.target * 4)
+            .map(|i| format!("{POOL_PREFIX}{i}"))
+            .find(|p| !free.contains(p) && !LinkStatus::query(p).exists);
+        let pooled = match slot {
+            Some(p) => p,
+            None => return false,
+        };
+
+        if ip(&["link", "set", "down", name]) && ip(&["link", "set", "dev", name, "name", &pooled])
+        {
+            info!("Returned interface {} to the pool as {}", name, pooled);
+            free.push(pooled);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn ip(args: &[&str]) -> bool {
+    match command::output("ip", args) {
+        Ok(result) => result.status.success(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * Filename: preflight.rs
+ * Created Date: Saturday, October 17th 2026, 2:14:37 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use log::{error, info};
+use std::fs;
+use std::path::Path;
+
+/// Exit code when a required kernel module is missing and couldn't be loaded
+pub const EXIT_MISSING_MODULES: i32 = 3;
+
+// Kernel modules every network needs, by the name they appear under in /sys/module
+const REQUIRED_MODULES: [&str; 3] = ["vcan", "vxcan", "can_gw"];
+
+/// Check that the CAN kernel modules the plugin relies on are available, trying
+/// `modprobe` for any that aren't
+/// Returns the modules that are still missing
+pub fn missing_modules() -> Vec<&'static str> {
+    let builtin = builtin_modules();
+    REQUIRED_MODULES
+        .iter()
+        .copied()
+        .filter(|m| !builtin.iter().any(|b| b == m))
+        .filter(|m| !loaded(m) && !load(m))
+        .collect()
+}
+
+fn loaded(module: &str) -> bool {
+    Path::new("/sys/module").join(module).exists()
+        || fs::read_to_string("/proc/modules").is_ok_and(|mods| {
+            mods.lines()
+                .any(|l| l.split_whitespace().next() == Some(module))
+        })
+}
+
+fn load(module: &str) -> bool {
+    info!("Kernel module {} is not loaded, trying modprobe", module);
+    match command::output("modprobe", &[module]) {
+        Ok(out) if out.status.success() => loaded(module),
+        _ => false,
+    }
+}
+
+// Modules compiled into the kernel never show up in /proc/modules
+fn builtin_modules() -> Vec<String> {
+    let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") else {
+        return Vec::new();
+    };
+    let path = format!("/lib/modules/{}/modules.builtin", release.trim());
+    fs::read_to_string(path)
+        .map(|list| {
+            list.lines()
+                .filter_map(|l| l.rsplit('/').next())
+                .map(|f| f.trim_end_matches(".ko").replace('-', "_"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Log which modules are missing and how to load them
+pub fn report_missing(missing: &[&str]) {
+    error!(
+        "Required kernel modules are not available: {}. Load them on the host with `modprobe {}` \
+         (add them to /etc/modules-load.d/ to load them at boot)",
+        missing.join(", "),
+        missing.join(" && modprobe ")
+    );
+}
@@ -0,0 +1,80 @@
+/*
+ * Filename: probe.rs
+ * Created Date: Friday, October 16th 2026, 4:05:48 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::command;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+// Extended CAN id used for probe frames, unlikely to collide with application traffic
+const PROBE_ID: &str = "1FFFFF7E";
+// Mask matching the whole 29-bit probe id
+const PROBE_MASK: &str = "1FFFFFFF";
+// Payload "RCPROBE" so probe frames are recognisable in a capture
+const PROBE_DATA: &str = "524350524F4245";
+
+// How long to wait for a probe frame to arrive on the destination
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// cangw filters splitting a frame-modifying job in two: the first job modifies every
+/// frame but the probe, the second forwards the probe unchanged, so `forwarding` still
+/// finds it on the destination
+pub fn modify_exemption() -> [String; 2] {
+    [format!("{PROBE_ID}~{PROBE_MASK}"), format!("{PROBE_ID}:{PROBE_MASK}")]
+}
+
+/// Send a probe frame on `src` and check that a gateway job forwards it to `dst`
+/// Relies on the echo flag the plugin sets on its rules, so the forwarded frame is
+/// visible to a listener on the host side of `dst`
+pub fn forwarding(src: &str, dst: &str) -> Result<bool, String> {
+    let filter = format!("{dst},{PROBE_ID}:{PROBE_MASK}");
+    let timeout = PROBE_TIMEOUT.as_millis().to_string();
+    let args = ["-n", "1", "-T", timeout.as_str(), filter.as_str()];
+    command::log_invocation("candump", &args);
+    let listener = Command::new("candump")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to execute candump: {e}"))?;
+
+    // Give candump a moment to bind before the frame goes out
+    std::thread::sleep(Duration::from_millis(50));
+
+    let sent = command::output("cansend", &[src, &format!("{PROBE_ID}#{PROBE_DATA}")])
+        .map_err(|e| format!("failed to execute cansend: {e}"))?;
+    if !sent.status.success() {
+        let _ = listener.wait_with_output();
+        return Err(format!(
+            "cansend on {src} failed: {}",
+            String::from_utf8_lossy(&sent.stderr).trim()
+        ));
+    }
+
+    let output = listener
+        .wait_with_output()
+        .map_err(|e| format!("candump on {dst} failed: {e}"))?;
+    Ok(String::from_utf8_lossy(&output.stdout).contains(dst))
+}
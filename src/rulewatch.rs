@@ -0,0 +1,224 @@
+/*
+ * Filename: rulewatch.rs
+ * Created Date: Friday, October 16th 2026, 11:20:05 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::cangw::{self, KernelRule};
+use crate::manager::NetworkManager;
+use crate::metrics::METRICS;
+use log::{error, info, warn};
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Rules that differ between the kernel and the plugin's tracked rules
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuleDiff {
+    // Tracked by the plugin but absent from the kernel
+    pub missing: BTreeSet<KernelRule>,
+    // Present in the kernel on a plugin interface but not tracked
+    pub external: BTreeSet<KernelRule>,
+}
+
+/// Compare kernel gateway jobs against the intended set
+/// Only kernel rules touching a plugin-managed interface are considered, so
+/// unrelated gateway jobs on the host are never reported
+pub fn diff_rules(
+    intended: &BTreeSet<KernelRule>,
+    managed: &HashSet<String>,
+    kernel: &[KernelRule],
+) -> RuleDiff {
+    let kernel_ours: BTreeSet<KernelRule> = kernel
+        .iter()
+        .filter(|r| managed.contains(&r.src) || managed.contains(&r.dst))
+        .cloned()
+        .collect();
+
+    RuleDiff {
+        missing: intended.difference(&kernel_ours).cloned().collect(),
+        external: kernel_ours.difference(intended).cloned().collect(),
+    }
+}
+
+/// Periodically detects cangw rules added or removed outside the plugin
+pub struct RuleWatcher {
+    mgr: NetworkManager,
+    strict: bool,
+    // Last observed difference, so each external change is reported once
+    reported: RuleDiff,
+}
+
+impl RuleWatcher {
+    pub fn new(mgr: NetworkManager, strict: bool) -> Self {
+        RuleWatcher {
+            mgr,
+            strict,
+            reported: RuleDiff::default(),
+        }
+    }
+
+    /// Run a single comparison pass against `cangw -L`
+    pub fn check(&mut self) {
+        let kernel = match cangw::list() {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Rule watcher unable to list kernel rules: {}", e);
+                return;
+            }
+        };
+
+        let (intended, managed) = self.mgr.intended_rules();
+        let diff = diff_rules(&intended, &managed, &kernel);
+
+        for rule in diff.missing.difference(&self.reported.missing) {
+            METRICS
+                .cangw_external_changes
+                .inc_with(&[("src", &rule.src), ("dst", &rule.dst)]);
+            warn!(
+                "External cangw change: rule {} -> {} (fd={}) was removed outside the plugin",
+                rule.src, rule.dst, rule.fd
+            );
+        }
+        for rule in diff.external.difference(&self.reported.external) {
+            METRICS
+                .cangw_external_changes
+                .inc_with(&[("src", &rule.src), ("dst", &rule.dst)]);
+            warn!(
+                "External cangw change: rule {} -> {} (fd={}) was added outside the plugin",
+                rule.src, rule.dst, rule.fd
+            );
+        }
+
+        if self.strict && !diff.missing.is_empty() {
+            let mut restored = BTreeSet::new();
+            for rule in diff.missing.iter() {
+                if self.mgr.reinstall_rule(rule) {
+                    info!(
+                        "Re-installed cangw rule {} -> {} (fd={})",
+                        rule.src, rule.dst, rule.fd
+                    );
+                    restored.insert(rule.clone());
+                }
+            }
+            // Restored rules no longer count as reported drift
+            self.reported = RuleDiff {
+                missing: diff.missing.difference(&restored).cloned().collect(),
+                external: diff.external,
+            };
+        } else {
+            self.reported = diff;
+        }
+    }
+}
+
+/// Background task running the rule watcher until shutdown
+pub async fn run(
+    mgr: NetworkManager,
+    interval: Duration,
+    strict: bool,
+    mut rx: oneshot::Receiver<()>,
+) {
+    info!(
+        "Watching for external cangw changes every {}s (strict={})",
+        interval.as_secs(),
+        strict
+    );
+
+    let mut watcher = RuleWatcher::new(mgr, strict);
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                watcher = match tokio::task::spawn_blocking(move || {
+                    let mut w = watcher;
+                    w.check();
+                    w
+                })
+                .await
+                {
+                    Ok(w) => w,
+                    Err(e) => {
+                        error!("Rule watcher pass failed: {}", e);
+                        return;
+                    }
+                };
+            }
+            _ = &mut rx => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(list: &[(&str, &str, bool)]) -> BTreeSet<KernelRule> {
+        list.iter()
+            .map(|(src, dst, fd)| KernelRule::new(src, dst, *fd))
+            .collect()
+    }
+
+    fn managed(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn nothing_differs_when_the_kernel_matches() {
+        let intended = rules(&[("vcan0", "vxcan1", false), ("vcan0", "vxcan1", true)]);
+        let kernel: Vec<KernelRule> = intended.iter().cloned().collect();
+        let diff = diff_rules(&intended, &managed(&["vcan0", "vxcan1"]), &kernel);
+        assert_eq!(diff, RuleDiff::default());
+    }
+
+    #[test]
+    fn reports_missing_and_external_rules() {
+        let intended = rules(&[("vcan0", "vxcan1", false), ("vxcan1", "vcan0", false)]);
+        let kernel = vec![
+            KernelRule::new("vcan0", "vxcan1", false),
+            KernelRule::new("vcan0", "vxcan2", false),
+        ];
+        let diff = diff_rules(&intended, &managed(&["vcan0", "vxcan1"]), &kernel);
+        assert_eq!(diff.missing, rules(&[("vxcan1", "vcan0", false)]));
+        assert_eq!(diff.external, rules(&[("vcan0", "vxcan2", false)]));
+    }
+
+    #[test]
+    fn ignores_rules_on_unmanaged_interfaces() {
+        let intended = rules(&[("vcan0", "vxcan1", false)]);
+        let kernel = vec![
+            KernelRule::new("vcan0", "vxcan1", false),
+            KernelRule::new("can0", "can1", false),
+        ];
+        let diff = diff_rules(&intended, &managed(&["vcan0", "vxcan1"]), &kernel);
+        assert!(diff.external.is_empty());
+    }
+
+    #[test]
+    fn classic_and_fd_jobs_are_told_apart() {
+        let intended = rules(&[("vcan0", "vxcan1", false), ("vcan0", "vxcan1", true)]);
+        let kernel = vec![KernelRule::new("vcan0", "vxcan1", false)];
+        let diff = diff_rules(&intended, &managed(&["vcan0", "vxcan1"]), &kernel);
+        assert_eq!(diff.missing, rules(&[("vcan0", "vxcan1", true)]));
+    }
+}
@@ -0,0 +1,97 @@
+/*
+ * Filename: selfheal.rs
+ * Created Date: Friday, October 16th 2026, 4:31:10 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::manager::NetworkManager;
+use crate::metrics::METRICS;
+use log::{error, info};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Verify forwarding on every network and repair the ones that fail
+/// A failing network has its rules reconciled against the kernel and is then
+/// verified again, so a repair is only logged as successful once frames flow
+pub fn heal(mgr: &NetworkManager) {
+    for nuid in mgr.network_ids() {
+        match mgr.verify_forwarding(&nuid) {
+            Ok(true) => continue,
+            Ok(false) => {
+                error!("Forwarding verification failed on network {}", nuid)
+            }
+            Err(e) => {
+                error!("Unable to verify forwarding on network {}: {}", nuid, e);
+                continue;
+            }
+        }
+
+        let reinstalled = match mgr.reconcile_rules(&nuid) {
+            Ok(n) => n,
+            Err(e) => {
+                error!("Unable to reconcile rules on network {}: {}", nuid, e);
+                continue;
+            }
+        };
+
+        match mgr.verify_forwarding(&nuid) {
+            Ok(true) => {
+                METRICS.self_heal_repairs.inc_with(&[("nuid", &nuid)]);
+                info!(
+                    "Self-heal repaired network {} ({} rules re-installed)",
+                    nuid, reinstalled
+                );
+            }
+            Ok(false) => error!(
+                "Self-heal could not restore forwarding on network {} ({} rules re-installed)",
+                nuid, reinstalled
+            ),
+            Err(e) => error!(
+                "Unable to re-verify forwarding on network {}: {}",
+                nuid, e
+            ),
+        }
+    }
+}
+
+/// Background task running self-heal passes until shutdown
+pub async fn run(mgr: NetworkManager, interval: Duration, mut rx: oneshot::Receiver<()>) {
+    info!(
+        "Self-heal verifying forwarding every {}s",
+        interval.as_secs()
+    );
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let m = mgr.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || heal(&m)).await {
+                    error!("Self-heal pass failed: {}", e);
+                    return;
+                }
+            }
+            _ = &mut rx => return,
+        }
+    }
+}
@@ -0,0 +1,612 @@
+/*
+ * Filename: state.rs
+ * Created Date: Friday, October 16th 2026, 10:02:11 am
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::endpoint::DEFAULT_PREFIX;
+use crate::error::RustyCanError;
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+pub const NETWORK_STATE_FILE: &str = "/var/lib/docker/network/files/rustycan4docker-networks.json";
+// Tried when the Docker state directory can't be written (e.g. on hardened hosts)
+pub const FALLBACK_STATE_FILE: &str = "/var/lib/rustycan4docker/networks.json";
+// Appended to the state file path for the copy of the last good state
+const BACKUP_SUFFIX: &str = ".bak";
+
+/// How a network connects its endpoints
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    // Shared vcan bus with cangw rules to every endpoint
+    #[default]
+    Bus,
+    // No bus: two endpoints share the two ends of a single vxcan pair
+    Direct,
+}
+
+impl NetworkMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "bus" => Some(NetworkMode::Bus),
+            "direct" => Some(NetworkMode::Direct),
+            _ => None,
+        }
+    }
+}
+
+// Persisted network configuration
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NetworkConfig {
+    pub device: String,
+    pub peer: String,
+    pub canid: String,
+    // Keep the interface in the kernel when the network is deleted
+    #[serde(default)]
+    pub persist: bool,
+    // Kept in memory only: never written to the state file or recovered after a restart
+    #[serde(default)]
+    pub ephemeral: bool,
+    // `device` is the exact kernel name of a real CAN interface; canid is not appended
+    #[serde(default)]
+    pub realname: bool,
+    // `device` was named through vxcan.link (which implies realname)
+    #[serde(default)]
+    pub link: bool,
+    // Hop limit applied to every gateway job on the network (cangw -l)
+    #[serde(default)]
+    pub hoplimit: Option<u8>,
+    #[serde(default)]
+    pub mode: NetworkMode,
+    // Ingress filter applied to frames from endpoints, in `cangw -f` form
+    #[serde(default)]
+    pub filter: Option<String>,
+    // Modifications applied to frames delivered to endpoints, in `cangw -m` form
+    #[serde(default)]
+    pub modify: Option<String>,
+    // Seconds after creation (or a restart) before the network is deleted automatically
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    // Restart the TTL whenever an endpoint attaches
+    #[serde(default)]
+    pub ttl_refresh: bool,
+    // Bitrate set on a real CAN interface when the network adopts it
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    // Carry CAN FD frames (virtual interfaces get a 72-byte MTU instead of 16)
+    #[serde(default)]
+    pub fd: bool,
+    // `canid` was given as a 29-bit extended CAN id rather than an 11-bit standard one
+    #[serde(default)]
+    pub extended: bool,
+    // CAN FD data-phase bitrate set on a real CAN interface
+    #[serde(default)]
+    pub dbitrate: Option<u32>,
+    // Controller loopback mode set on a real CAN interface; None leaves it as found
+    #[serde(default)]
+    pub loopback: Option<bool>,
+    // Prefix of the endpoint interface names (`{prefix}{id}` and its peer)
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    // Endpoints on the network when it was last saved, recreated at startup and by reconcile
+    #[serde(default)]
+    pub endpoints: Vec<SavedEndpoint>,
+}
+
+fn default_prefix() -> String {
+    String::from(DEFAULT_PREFIX)
+}
+
+/// An endpoint saved with its network, with the CreateEndpoint options it is rebuilt from
+/// (vxcan.direction, vxcan.mtu, vxcan.netem.*) and the interface names it was given
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "SavedEndpointFile")]
+pub struct SavedEndpoint {
+    pub uid: String,
+    pub options: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+}
+
+impl SavedEndpoint {
+    /// Device and peer names to rebuild the endpoint under, when both were saved
+    pub fn names(&self) -> Option<(String, String)> {
+        Some((self.device.clone()?, self.peer.clone()?))
+    }
+}
+
+// Version 1 files list endpoints by id only
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedEndpointFile {
+    Id(String),
+    Full {
+        uid: String,
+        #[serde(default)]
+        options: serde_json::Value,
+        #[serde(default)]
+        device: Option<String>,
+        #[serde(default)]
+        peer: Option<String>,
+    },
+}
+
+impl From<SavedEndpointFile> for SavedEndpoint {
+    fn from(saved: SavedEndpointFile) -> Self {
+        match saved {
+            SavedEndpointFile::Id(uid) => SavedEndpoint {
+                uid,
+                options: serde_json::Value::Null,
+                device: None,
+                peer: None,
+            },
+            SavedEndpointFile::Full {
+                uid,
+                options,
+                device,
+                peer,
+            } => SavedEndpoint {
+                uid,
+                options,
+                device,
+                peer,
+            },
+        }
+    }
+}
+
+// Version of the state file layout written by this plugin
+pub const STATE_VERSION: u32 = 2;
+
+/// Contents of the state file
+/// Version 0 files are the bare map of networks, from before the file was versioned
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PersistedState {
+    pub version: u32,
+    pub networks: HashMap<String, NetworkConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StateFile {
+    Versioned(PersistedState),
+    Unversioned(HashMap<String, NetworkConfig>),
+}
+
+impl PersistedState {
+    fn parse(contents: &str) -> Result<Self, String> {
+        let state = match serde_json::from_str::<StateFile>(contents) {
+            Ok(StateFile::Versioned(state)) => state,
+            Ok(StateFile::Unversioned(networks)) => PersistedState {
+                version: 0,
+                networks,
+            },
+            // Report why the current layout didn't parse, rather than the untagged mismatch
+            Err(_) => {
+                serde_json::from_str::<PersistedState>(contents).map_err(|e| e.to_string())?
+            }
+        };
+        if state.version > STATE_VERSION {
+            return Err(format!(
+                "version {} was written by a newer plugin (this one reads up to {})",
+                state.version, STATE_VERSION
+            ));
+        }
+        Ok(state)
+    }
+
+    /// Upgrade state read from an older file to the current layout, one version at a time
+    fn migrate(mut self) -> Self {
+        if self.version == 0 {
+            // v1 only wraps the network map; every field added since defaults when absent
+            self.version = 1;
+        }
+        if self.version == 1 {
+            // v2 saves endpoints with their options; v1's bare ids are read as endpoints
+            // without options, so they come back with the defaults as before
+            self.version = 2;
+        }
+        self
+    }
+}
+
+/// Owner of the on-disk network state file
+#[derive(Clone)]
+pub struct StateStore {
+    // None in memory-only mode, when no candidate location was writable
+    path: Option<String>,
+    // Serializes read-modify-write cycles so concurrent saves don't lose updates
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl StateStore {
+    pub fn new(path: &str) -> Self {
+        StateStore {
+            path: Some(path.to_string()),
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Use the first candidate location that can be written, in order
+    /// With none writable, the store runs in memory-only mode and nothing survives a restart
+    pub fn probe(candidates: &[String]) -> Self {
+        for path in candidates {
+            match writable(path) {
+                Ok(()) => {
+                    info!("Using state file {}", path);
+                    return StateStore::new(path);
+                }
+                Err(e) => warn!("State file {} is not writable: {}", path, e),
+            }
+        }
+
+        warn!("================================================");
+        warn!("WARNING: no writable state file location, running in memory-only mode");
+        warn!("Networks will not be recovered after a plugin restart or reboot");
+        warn!("================================================");
+        StateStore {
+            path: None,
+            write_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.as_ref().is_some_and(|p| Path::new(p).exists())
+    }
+
+    /// Size of the state file in bytes; None in memory-only mode or before the first save
+    pub fn size(&self) -> Option<u64> {
+        let path = self.path.as_ref()?;
+        fs::metadata(path).ok().map(|m| m.len())
+    }
+
+    /// Rewrite a state file in an older layout in the current one
+    pub fn upgrade(&self) -> Result<(), RustyCanError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _guard = self.write_lock.lock();
+        match read(path)? {
+            Some(state) if state.version < STATE_VERSION => {
+                info!(
+                    "Migrating state file {} from version {} to {}",
+                    path, state.version, STATE_VERSION
+                );
+                self.save(state.migrate().networks)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Load all persisted network configurations
+    /// An unreadable state file falls back to the backup of the last good one
+    /// Returns Ok(None) when no state file has been written yet
+    pub fn load(&self) -> Result<Option<HashMap<String, NetworkConfig>>, RustyCanError> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let err = match read(path) {
+            Ok(state) => return Ok(state.map(|s| s.migrate().networks)),
+            Err(e) => e,
+        };
+
+        let backup = format!("{path}{BACKUP_SUFFIX}");
+        match read(&backup) {
+            Ok(Some(state)) => {
+                let configs = state.migrate().networks;
+                warn!("{}", err);
+                warn!(
+                    "Recovered {} network configurations from backup {}",
+                    configs.len(),
+                    backup
+                );
+                Ok(Some(configs))
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// Start a batch of changes that is written to disk in a single save
+    pub fn batch(&self) -> PersistBatch<'_> {
+        PersistBatch {
+            store: self,
+            upserts: Vec::new(),
+            removals: Vec::new(),
+        }
+    }
+
+    fn save(&self, networks: HashMap<String, NetworkConfig>) -> Result<(), RustyCanError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        // Create directory if it doesn't exist
+        if let Some(parent) = Path::new(path).parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        // Keep the file being replaced as the backup, unless it is the corrupt one
+        if let Ok(previous) = fs::read_to_string(path) {
+            if PersistedState::parse(&previous).is_ok() {
+                let backup = format!("{path}{BACKUP_SUFFIX}");
+                if let Err(e) = write_atomic(&backup, previous.as_bytes()) {
+                    error!("Unable to write state backup {}: {}", backup, e);
+                }
+            }
+        }
+
+        let state = PersistedState {
+            version: STATE_VERSION,
+            networks,
+        };
+        let json = serde_json::to_string_pretty(&state)
+            .map_err(|e| RustyCanError::Persistence(format!("serialize: {}", e)))?;
+        write_atomic(path, json.as_bytes())
+            .map_err(|e| RustyCanError::Persistence(format!("write {}: {}", path, e)))
+    }
+}
+
+fn read(path: &str) -> Result<Option<PersistedState>, RustyCanError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => PersistedState::parse(&contents)
+            .map(Some)
+            .map_err(|e| RustyCanError::Persistence(format!("parse {}: {}", path, e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(RustyCanError::Persistence(format!("read {}: {}", path, e))),
+    }
+}
+
+// Write a temporary file next to `path`, sync it and rename it into place, so a crash
+// mid-write leaves either the old file or the new one, never a truncated one
+fn write_atomic(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    let result = (|| {
+        let mut file = File::create(&tmp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+
+    // Make the rename itself durable
+    if let Some(parent) = Path::new(path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+    {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// Check a state file can be written without touching an existing file's contents
+fn writable(path: &str) -> std::io::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if Path::new(path).exists() {
+        return OpenOptions::new().append(true).open(path).map(|_| ());
+    }
+    let probe = format!("{path}.probe");
+    fs::write(&probe, b"")?;
+    fs::remove_file(&probe)
+}
+
+/// Pending state file changes, applied together by `commit`
+/// Dropping the batch without committing discards the changes
+pub struct PersistBatch<'a> {
+    store: &'a StateStore,
+    upserts: Vec<(String, NetworkConfig)>,
+    removals: Vec<String>,
+}
+
+impl PersistBatch<'_> {
+    pub fn upsert(&mut self, nuid: String, config: NetworkConfig) {
+        self.removals.retain(|n| *n != nuid);
+        self.upserts.push((nuid, config));
+    }
+
+    pub fn remove(&mut self, nuid: String) {
+        self.upserts.retain(|(n, _)| *n != nuid);
+        self.removals.push(nuid);
+    }
+
+    pub fn commit(self) -> Result<(), RustyCanError> {
+        if self.upserts.is_empty() && self.removals.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.store.write_lock.lock();
+
+        // Neither the state file nor its backup can be read: writing only this batch
+        // would drop every other network, so the file is left for the operator
+        let mut configs = self.store.load()?.unwrap_or_default();
+
+        for nuid in self.removals {
+            configs.remove(&nuid);
+        }
+        for (nuid, config) in self.upserts {
+            configs.insert(nuid, config);
+        }
+
+        self.store.save(configs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(endpoints: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({
+            "device": "vcan",
+            "peer": "vcanp",
+            "canid": "3",
+            "endpoints": endpoints,
+        })
+    }
+
+    // A state file path of its own for each test, in an emptied directory
+    fn state_path(name: &str) -> String {
+        let dir =
+            std::env::temp_dir().join(format!("rustycan4docker-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("networks.json").to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parse_reads_an_unversioned_map_as_version_0() {
+        let contents = serde_json::json!({ "n1": config(serde_json::json!([])) }).to_string();
+        let state = PersistedState::parse(&contents).unwrap();
+        assert_eq!(state.version, 0);
+        let n1 = &state.networks["n1"];
+        assert_eq!((n1.device.as_str(), n1.canid.as_str()), ("vcan", "3"));
+        assert_eq!(n1.prefix, DEFAULT_PREFIX);
+        assert_eq!(n1.mode, NetworkMode::Bus);
+    }
+
+    #[test]
+    fn parse_reads_bare_endpoint_ids_from_version_1() {
+        let contents = serde_json::json!({
+            "version": 1,
+            "networks": { "n1": config(serde_json::json!(["ep1"])) },
+        })
+        .to_string();
+        let state = PersistedState::parse(&contents).unwrap();
+        assert_eq!(
+            state.networks["n1"].endpoints,
+            vec![SavedEndpoint {
+                uid: String::from("ep1"),
+                options: serde_json::Value::Null,
+                device: None,
+                peer: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn parse_reads_endpoint_options_and_names() {
+        let contents = serde_json::json!({
+            "version": 2,
+            "networks": { "n1": config(serde_json::json!([{
+                "uid": "ep1",
+                "options": { "vxcan.direction": "rx" },
+                "device": "vxcan1a2b3c4d",
+                "peer": "vxcan1a2b3c4dp",
+            }])) },
+        })
+        .to_string();
+        let state = PersistedState::parse(&contents).unwrap();
+        let ep = &state.networks["n1"].endpoints[0];
+        assert_eq!(ep.options["vxcan.direction"], "rx");
+        assert_eq!(
+            ep.names(),
+            Some((
+                String::from("vxcan1a2b3c4d"),
+                String::from("vxcan1a2b3c4dp")
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_refuses_a_newer_version() {
+        let contents = serde_json::json!({
+            "version": STATE_VERSION + 1,
+            "networks": {},
+        })
+        .to_string();
+        let err = PersistedState::parse(&contents).unwrap_err();
+        assert!(err.contains("newer plugin"), "{err}");
+    }
+
+    #[test]
+    fn parse_reports_why_the_current_layout_failed() {
+        let err = PersistedState::parse(r#"{"version": 2, "networks": 7}"#).unwrap_err();
+        assert!(err.contains("invalid type"), "{err}");
+        assert!(PersistedState::parse("{").is_err());
+    }
+
+    #[test]
+    fn migrate_brings_every_old_version_to_the_current_one() {
+        for version in 0..=STATE_VERSION {
+            let state = PersistedState {
+                version,
+                networks: HashMap::new(),
+            };
+            assert_eq!(state.migrate().version, STATE_VERSION);
+        }
+    }
+
+    #[test]
+    fn migrate_keeps_the_networks() {
+        let contents = serde_json::json!({ "n1": config(serde_json::json!(["ep1"])) }).to_string();
+        let state = PersistedState::parse(&contents).unwrap();
+        let networks = state.networks.clone();
+        assert_eq!(state.migrate().networks, networks);
+    }
+
+    #[test]
+    fn commit_refuses_to_overwrite_an_unreadable_file() {
+        let path = state_path("corrupt");
+        fs::write(&path, "{ not json").unwrap();
+        let store = StateStore::new(&path);
+
+        let mut batch = store.batch();
+        let config: NetworkConfig = serde_json::from_value(config(serde_json::json!([]))).unwrap();
+        batch.upsert(String::from("n1"), config);
+        assert!(batch.commit().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn commit_keeps_networks_outside_the_batch() {
+        let path = state_path("batch");
+        let store = StateStore::new(&path);
+        let config: NetworkConfig = serde_json::from_value(config(serde_json::json!([]))).unwrap();
+
+        let mut batch = store.batch();
+        batch.upsert(String::from("n1"), config.clone());
+        batch.commit().unwrap();
+        let mut batch = store.batch();
+        batch.upsert(String::from("n2"), config);
+        batch.commit().unwrap();
+
+        let mut ids: Vec<String> = store.load().unwrap().unwrap().into_keys().collect();
+        ids.sort();
+        assert_eq!(ids, ["n1", "n2"]);
+        let _ = fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * Filename: verify.rs
+ * Created Date: Saturday, October 17th 2026, 2:37:48 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::cangw::{self, KernelRule};
+use crate::config::PluginConfig;
+use crate::link::LinkStatus;
+use crate::network::Network;
+use crate::state::{NetworkConfig, NetworkMode, StateStore};
+use log::error;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// Result of checking one persisted network against the kernel
+#[derive(Debug, Serialize)]
+pub struct NetworkCheck {
+    // Empty for direct networks, which have no bus
+    pub interface: String,
+    pub interface_exists: bool,
+    // Endpoint interfaces with forwarding from the bus
+    pub endpoints: usize,
+    // Endpoint interfaces forwarded in only one direction
+    pub half_rules: Vec<String>,
+    pub healthy: bool,
+}
+
+/// Pre-flight report printed by `rustycan4docker verify`
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    // None when no state file exists in any configured location
+    pub state_file: Option<String>,
+    pub networks: BTreeMap<String, NetworkCheck>,
+    // Why the state file or the kernel's gateway jobs couldn't be read
+    pub errors: Vec<String>,
+    pub healthy: bool,
+}
+
+/// `verify` command: check persisted networks against the kernel and exit
+/// Nothing is created, repaired or written, not even a probe of the state directory
+/// Exits with 0 when healthy, 1 when the kernel has drifted and 2 when state can't be read
+pub fn verify_command(config: &PluginConfig) -> i32 {
+    let report = verify(config);
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => error!("Unable to serialize the verify report: {}", e),
+    }
+
+    if !report.errors.is_empty() {
+        2
+    } else if !report.healthy {
+        1
+    } else {
+        0
+    }
+}
+
+fn verify(config: &PluginConfig) -> VerifyReport {
+    let mut report = VerifyReport {
+        state_file: config
+            .state_files
+            .iter()
+            .find(|p| Path::new(p).exists())
+            .cloned(),
+        networks: BTreeMap::new(),
+        errors: Vec::new(),
+        healthy: true,
+    };
+
+    let configs = match &report.state_file {
+        Some(path) => match StateStore::new(path).load() {
+            Ok(configs) => configs.unwrap_or_default(),
+            Err(e) => {
+                report.errors.push(e.to_string());
+                HashMap::new()
+            }
+        },
+        None => HashMap::new(),
+    };
+
+    let rules: BTreeSet<KernelRule> = match cangw::list() {
+        Ok(rules) => rules.into_iter().filter(|r| !r.fd).collect(),
+        Err(e) => {
+            report.errors.push(e);
+            BTreeSet::new()
+        }
+    };
+
+    for (nuid, config) in configs.iter().filter(|(_, c)| !c.ephemeral) {
+        let check = check_network(config, &rules);
+        report.healthy &= check.healthy;
+        report.networks.insert(nuid.clone(), check);
+    }
+    report.healthy &= report.errors.is_empty();
+    report
+}
+
+fn check_network(config: &NetworkConfig, rules: &BTreeSet<KernelRule>) -> NetworkCheck {
+    let interface = Network::interface_name(config);
+    if config.mode == NetworkMode::Direct {
+        return NetworkCheck {
+            interface,
+            interface_exists: true,
+            endpoints: 0,
+            half_rules: Vec::new(),
+            healthy: true,
+        };
+    }
+
+    let interface_exists = LinkStatus::query(&interface).exists;
+    let outbound: BTreeSet<&str> = rules
+        .iter()
+        .filter(|r| r.src == interface)
+        .map(|r| r.dst.as_str())
+        .collect();
+    let inbound: BTreeSet<&str> = rules
+        .iter()
+        .filter(|r| r.dst == interface)
+        .map(|r| r.src.as_str())
+        .collect();
+    let half_rules: Vec<String> = outbound
+        .symmetric_difference(&inbound)
+        .map(|d| d.to_string())
+        .collect();
+
+    NetworkCheck {
+        healthy: interface_exists && half_rules.is_empty(),
+        interface,
+        interface_exists,
+        endpoints: outbound.len(),
+        half_rules,
+    }
+}
@@ -0,0 +1,80 @@
+/*
+ * Filename: warmup.rs
+ * Created Date: Saturday, October 17th 2026, 2:08:51 pm
+ * Author: Jonathan Haws
+ *
+ * Copyright (c) 2022 WiTricity
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use crate::config::WarmupPolicy;
+use crate::manager::{HealthReport, NetworkManager};
+use log::{error, info, warn};
+
+fn log_unhealthy(report: &HealthReport) {
+    for nuid in &report.missing_networks {
+        warn!("  network {} has no bus interface", nuid);
+    }
+    for (nuid, device) in &report.missing_endpoints {
+        warn!("  endpoint {} on network {} is missing", device, nuid);
+    }
+    for (nuid, reason) in &report.failed_networks {
+        error!("  network {} failed to restore: {}", nuid, reason);
+    }
+}
+
+/// Check the recovered state before the Docker API is served
+/// Fail-open returns straight away with a warning; fail-closed re-checks until
+/// the state is healthy or an operator releases the gate
+pub async fn gate(mgr: &NetworkManager) {
+    let policy = mgr.config().warmup_policy;
+    let retry = mgr.config().warmup_retry;
+
+    loop {
+        let report = mgr.health_report();
+        if report.healthy {
+            info!("Recovered state is healthy");
+            return;
+        }
+
+        match policy {
+            WarmupPolicy::FailOpen => {
+                warn!("================================================");
+                warn!("WARNING: serving Docker requests with an unhealthy recovered state");
+                log_unhealthy(&report);
+                warn!("================================================");
+                return;
+            }
+            WarmupPolicy::FailClosed if mgr.warmup_released() => {
+                warn!("Serving Docker requests with an unhealthy recovered state");
+                log_unhealthy(&report);
+                return;
+            }
+            WarmupPolicy::FailClosed => {
+                warn!(
+                    "Recovered state is unhealthy, not serving Docker requests (re-checking in {}s)",
+                    retry.as_secs()
+                );
+                log_unhealthy(&report);
+                tokio::time::sleep(retry).await;
+            }
+        }
+    }
+}